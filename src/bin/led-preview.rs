@@ -0,0 +1,209 @@
+//! 离线预览 `[led_record]` 录制的 LED 文件
+//!
+//! 用法: `led-preview <录制文件> [term | png <输出> | gif <输出>] [--fps N] [--scale N]`
+//!
+//! 每帧从左到右为 board 0 的 61 个 LED、一格间隔、board 1 的 6 个 LED。
+//! png 把所有帧从上到下拼成一张时间轴，gif 为动画。
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+
+use ongeki_io::led_recording::{self, LedState, BOARD0_LEDS, BOARD1_LEDS};
+use rgb::RGB8;
+
+/// 一帧的 LED 格数，含两块板之间的间隔
+const COLUMNS: usize = BOARD0_LEDS + 1 + BOARD1_LEDS;
+
+enum Output {
+    Term,
+    Png(String),
+    Gif(String),
+}
+
+struct Args {
+    input: String,
+    output: Output,
+    fps: u32,
+    scale: usize,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut positional = vec![];
+    let mut fps = 30;
+    let mut scale = 8;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fps" => fps = parse_number(args.next(), "--fps")?,
+            "--scale" => scale = parse_number(args.next(), "--scale")?,
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let input = positional.next().ok_or("缺少录制文件")?;
+    let output = match (positional.next().as_deref(), positional.next()) {
+        (None | Some("term"), None) => Output::Term,
+        (Some("png"), Some(path)) => Output::Png(path),
+        (Some("gif"), Some(path)) => Output::Gif(path),
+        _ => return Err("输出格式应为 term、png <输出> 或 gif <输出>".to_string()),
+    };
+    if fps == 0 || scale == 0 {
+        return Err("--fps 和 --scale 必须大于 0".to_string());
+    }
+    Ok(Args {
+        input,
+        output,
+        fps: fps as u32,
+        scale,
+    })
+}
+
+fn parse_number(value: Option<String>, name: &str) -> Result<usize, String> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| format!("{name} 需要一个数字"))
+}
+
+/// 一帧的 LED 颜色，间隔为 `None`
+fn columns(state: &LedState) -> impl Iterator<Item = Option<RGB8>> + '_ {
+    state
+        .board0
+        .iter()
+        .map(|c| Some(*c))
+        .chain([None])
+        .chain(state.board1.iter().map(|c| Some(*c)))
+}
+
+/// 一帧放大 `scale` 倍后的一行像素，间隔为深灰色
+fn row_pixels(state: &LedState, scale: usize) -> Vec<u8> {
+    const GAP: RGB8 = RGB8::new(32, 32, 32);
+    columns(state)
+        .flat_map(|c| {
+            let c = c.unwrap_or(GAP);
+            [c.r, c.g, c.b].repeat(scale)
+        })
+        .collect()
+}
+
+fn frame_pixels(state: &LedState, scale: usize) -> Vec<u8> {
+    row_pixels(state, scale).repeat(scale)
+}
+
+fn write_png(path: &str, frames: &[LedState], scale: usize) -> io::Result<()> {
+    let width = COLUMNS * scale;
+    let height = frames.len() * scale;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data: Vec<u8> = frames.iter().flat_map(|f| frame_pixels(f, scale)).collect();
+    encoder
+        .write_header()?
+        .write_image_data(&data)
+        .map_err(io::Error::other)
+}
+
+fn write_gif(path: &str, frames: &[LedState], fps: u32, scale: usize) -> io::Result<()> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "--scale 过大");
+    let width = u16::try_from(COLUMNS * scale).map_err(|_| too_large())?;
+    let height = u16::try_from(scale).map_err(|_| too_large())?;
+    let mut encoder = gif::Encoder::new(BufWriter::new(File::create(path)?), width, height, &[])
+        .map_err(io::Error::other)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(io::Error::other)?;
+    // GIF 的帧间隔以 10 毫秒为单位
+    let delay = (100 / fps).max(1) as u16;
+    for state in frames {
+        let mut frame = gif::Frame::from_rgb_speed(width, height, &frame_pixels(state, scale), 10);
+        frame.delay = delay;
+        encoder.write_frame(&frame).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+fn play(frames: &[LedState], fps: u32) -> io::Result<()> {
+    let period = Duration::from_secs(1) / fps;
+    let mut out = io::stdout().lock();
+    for (i, state) in frames.iter().enumerate() {
+        let mut line = format!("\r{:>8.2}s ", i as f64 / f64::from(fps));
+        for c in columns(state) {
+            match c {
+                Some(c) => line += &format!("\x1b[48;2;{};{};{}m ", c.r, c.g, c.b),
+                None => line += "\x1b[0m ",
+            }
+        }
+        line += "\x1b[0m";
+        out.write_all(line.as_bytes())?;
+        out.flush()?;
+        thread::sleep(period);
+    }
+    writeln!(out)
+}
+
+fn run(args: Args) -> io::Result<()> {
+    let events = led_recording::load(&args.input)?;
+    let frames = led_recording::sample(&events, args.fps);
+    println!("{} 次调用，{} 帧", events.len(), frames.len());
+    match args.output {
+        Output::Term => play(&frames, args.fps),
+        Output::Png(path) => write_png(&path, &frames, args.scale),
+        Output::Gif(path) => write_gif(&path, &frames, args.fps, args.scale),
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n用法: led-preview <录制文件> [term | png <输出> | gif <输出>] [--fps N] [--scale N]");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("led-preview: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod led_preview_test {
+    use super::*;
+
+    fn args(s: &str) -> Result<Args, String> {
+        parse_args(s.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn args_test() {
+        let a = args("led.rec gif out.gif --fps 10").unwrap();
+        assert!(matches!(a.output, Output::Gif(ref p) if p == "out.gif"));
+        assert_eq!((a.fps, a.scale), (10, 8));
+        assert!(matches!(args("led.rec").unwrap().output, Output::Term));
+        assert!(args("").is_err());
+        assert!(args("led.rec png").is_err());
+        assert!(args("led.rec --scale 0").is_err());
+    }
+
+    #[test]
+    fn pixels_test() {
+        let mut state = LedState::default();
+        state.board0[0] = RGB8::new(255, 0, 0);
+        state.board1[5] = RGB8::new(0, 0, 255);
+        let row = row_pixels(&state, 2);
+        assert_eq!(row.len(), COLUMNS * 2 * 3);
+        assert_eq!(row[..6], [255, 0, 0, 255, 0, 0]);
+        assert_eq!(row[BOARD0_LEDS * 6..BOARD0_LEDS * 6 + 3], [32, 32, 32]);
+        assert_eq!(row[row.len() - 3..], [0, 0, 255]);
+        assert_eq!(frame_pixels(&state, 2).len(), row.len() * 2);
+    }
+}
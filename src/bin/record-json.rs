@@ -0,0 +1,53 @@
+//! 把 `[record]` 录制的输入文件转换为 JSON
+//!
+//! 用法: `record-json <录制文件> [输出]`，不写输出时另存为同名的 `.json`。
+//! 转换后的文件可以直接用作 `[replay]` 的 `path`。
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use ongeki_io::recording::Recording;
+
+fn output_path(input: &str, output: Option<String>) -> PathBuf {
+    output.map_or_else(|| Path::new(input).with_extension("json"), PathBuf::from)
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(input) = args.next() else {
+        eprintln!("缺少录制文件\n用法: record-json <录制文件> [输出]");
+        return ExitCode::FAILURE;
+    };
+    let output = output_path(&input, args.next());
+    let saved = Recording::load(&input).and_then(|recording| {
+        recording.save_json(&output)?;
+        Ok(recording.records.len())
+    });
+    match saved {
+        Ok(count) => {
+            println!("{count} 条记录，已保存到 {}", output.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("record-json: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod record_json_test {
+    use super::*;
+
+    #[test]
+    fn output_path_test() {
+        assert_eq!(
+            output_path("ongeki-io-input.rec", None),
+            PathBuf::from("ongeki-io-input.json")
+        );
+        assert_eq!(
+            output_path("a.rec", Some("b.json".to_string())),
+            PathBuf::from("b.json")
+        );
+    }
+}
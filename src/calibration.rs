@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+pub const CALIBRATION_PATH: &str = "ongeki-io-calibration.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeverCalibration {
+    pub lever_left: i32,
+    pub lever_right: i32,
+}
+
+impl LeverCalibration {
+    /// 根据新读数扩大范围，保持原有方向，返回范围是否变化
+    pub fn widen(&mut self, value: i32) -> bool {
+        let before = *self;
+        if self.lever_left > self.lever_right {
+            if value > self.lever_left {
+                self.lever_left = value;
+            }
+            if value < self.lever_right {
+                self.lever_right = value;
+            }
+        } else {
+            if value < self.lever_left {
+                self.lever_left = value;
+            }
+            if value > self.lever_right {
+                self.lever_right = value;
+            }
+        }
+        *self != before
+    }
+
+    /// 以当前读数重新开始学习，保持原有方向
+    pub fn reset(&mut self, value: i32) {
+        let reversed = self.lever_left > self.lever_right;
+        self.lever_left = value;
+        self.lever_right = if reversed {
+            value.saturating_sub(1)
+        } else {
+            value.saturating_add(1)
+        };
+    }
+}
+
+/// 运行时学习到的摇杆范围，按设备 VID:PID:序列号 分别保存
+#[derive(Debug, Default)]
+pub struct CalibrationStore {
+    path: PathBuf,
+    entries: BTreeMap<String, LeverCalibration>,
+}
+
+impl CalibrationStore {
+    /// 读取校准文件，文件不存在或格式有误时返回空记录
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let entries = match fs::read_to_string(&path) {
+            Ok(s) => toml::from_str(&s).unwrap_or_else(|e| {
+                println!("Ongeki IO: 校准文件 {} 有误，已忽略\n{e}", path.display());
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self { path, entries }
+    }
+
+    pub fn key(vid: u16, pid: u16, serial: Option<&str>) -> String {
+        format!("{vid:04X}:{pid:04X}:{}", serial.unwrap_or_default())
+    }
+
+    pub fn get(&self, key: &str) -> Option<LeverCalibration> {
+        self.entries.get(key).copied()
+    }
+
+    pub fn set(&mut self, key: &str, calibration: LeverCalibration) {
+        self.entries.insert(key.to_string(), calibration);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let s = toml::to_string_pretty(&self.entries).map_err(io::Error::other)?;
+        fs::write(&self.path, s)
+    }
+}
+
+#[cfg(test)]
+mod calibration_test {
+    use super::{CalibrationStore, LeverCalibration};
+
+    #[test]
+    fn widen_test() {
+        let mut calibration = LeverCalibration {
+            lever_left: -10,
+            lever_right: 10,
+        };
+        assert!(!calibration.widen(5));
+        assert!(calibration.widen(-20));
+        assert!(calibration.widen(30));
+        assert_eq!((calibration.lever_left, calibration.lever_right), (-20, 30));
+
+        // 反向的范围保持反向
+        let mut calibration = LeverCalibration {
+            lever_left: 10,
+            lever_right: -10,
+        };
+        assert!(calibration.widen(20));
+        assert!(calibration.widen(-30));
+        assert_eq!((calibration.lever_left, calibration.lever_right), (20, -30));
+
+        calibration.reset(100);
+        assert!(calibration.lever_left > calibration.lever_right);
+        assert!(calibration.widen(50));
+        assert_eq!((calibration.lever_left, calibration.lever_right), (100, 50));
+
+        let mut calibration = LeverCalibration {
+            lever_left: -10,
+            lever_right: 10,
+        };
+        calibration.reset(0);
+        assert!(calibration.widen(-5));
+        assert_eq!((calibration.lever_left, calibration.lever_right), (-5, 1));
+    }
+
+    #[test]
+    fn save_load_test() {
+        let path = std::env::temp_dir().join(format!(
+            "ongeki-io-calibration-{}.toml",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = CalibrationStore::load(&path);
+        let key = CalibrationStore::key(0x2341, 0x8036, Some("ABC"));
+        assert_eq!(key, "2341:8036:ABC");
+        assert_eq!(store.get(&key), None);
+
+        let calibration = LeverCalibration {
+            lever_left: -1200,
+            lever_right: 1300,
+        };
+        store.set(&key, calibration);
+        store.set(
+            &CalibrationStore::key(1, 2, None),
+            LeverCalibration {
+                lever_left: 10,
+                lever_right: -10,
+            },
+        );
+        store.save().unwrap();
+
+        let store = CalibrationStore::load(&path);
+        assert_eq!(store.get(&key), Some(calibration));
+        assert_eq!(store.get("0001:0002:").unwrap().lever_right, -10);
+
+        std::fs::write(&path, "not toml [").unwrap();
+        assert_eq!(CalibrationStore::load(&path).get(&key), None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,16 +1,104 @@
 use serde::{Deserialize, Serialize};
 
+use crate::enums::PadButton;
+use crate::keys::KeyBinding;
+use crate::led_color::{ChannelOrder, ZoneBrightness};
+use crate::led_effects::LedEffect;
+use crate::led_layout::LedRoute;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyBoardSideMapping {
+    pub btn1: KeyBinding,
+    pub btn2: KeyBinding,
+    pub btn3: KeyBinding,
+    pub side: KeyBinding,
+    pub menu: KeyBinding,
+}
+
+impl KeyBoardSideMapping {
+    fn default_left() -> Self {
+        Self {
+            btn1: KeyBinding::from(0x41),
+            btn2: KeyBinding::from(0x53),
+            btn3: KeyBinding::from(0x44),
+            side: KeyBinding::from(0x01),
+            menu: KeyBinding::from(0x55),
+        }
+    }
+
+    fn default_right() -> Self {
+        Self {
+            btn1: KeyBinding::from(0x4A),
+            btn2: KeyBinding::from(0x4B),
+            btn3: KeyBinding::from(0x4C),
+            side: KeyBinding::from(0x02),
+            menu: KeyBinding::from(0x4F),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyBoardConfig {
     pub enabled: bool,
-    pub test: i32,
-    pub service: i32,
-    pub coin: i32,
+    pub test: KeyBinding,
+    pub service: KeyBinding,
+    pub coin: KeyBinding,
+    #[serde(default = "KeyBoardSideMapping::default_left")]
+    pub left: KeyBoardSideMapping,
+    #[serde(default = "KeyBoardSideMapping::default_right")]
+    pub right: KeyBoardSideMapping,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Smoothing {
+    None,
+    /// 指数移动平均，`alpha` 越小越平滑
+    Ema {
+        alpha: f32,
+    },
+    /// One-Euro 滤波
+    OneEuro {
+        min_cutoff: f32,
+        beta: f32,
+        d_cutoff: f32,
+    },
+}
+
+/// 摇杆滤波配置，比例均以满行程的一半为 1
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LeverFilterConfig {
+    /// 中心死区
+    pub deadzone: f32,
+    /// 边缘饱和，超过 `1 - saturation` 视为推到底
+    pub saturation: f32,
+    pub smoothing: Smoothing,
+    /// 响应曲线指数，1 为线性
+    pub curve: f32,
+    pub invert: bool,
+    /// 每秒最大变化量，0 为不限制
+    pub rate_limit: f32,
+}
+
+impl Default for LeverFilterConfig {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.0,
+            saturation: 0.0,
+            smoothing: Smoothing::None,
+            curve: 1.0,
+            invert: false,
+            rate_limit: 0.0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MouseConfig {
     pub enabled: bool,
+    #[serde(default)]
+    pub lever_filter: LeverFilterConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,14 +106,630 @@ pub struct LEDebugConfig {
     pub enabled: bool,
 }
 
+/// 输入报告中的一个按键：`data[offset] & mask` 不为 0 时视为按下
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HidButton {
+    pub offset: usize,
+    pub mask: u8,
+    /// 低电平有效
+    #[serde(default)]
+    pub invert: bool,
+}
+
+impl HidButton {
+    const fn byte(offset: usize) -> Self {
+        Self {
+            offset,
+            mask: 0x01,
+            invert: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HidButtonsLayout {
+    pub btn1: HidButton,
+    pub btn2: HidButton,
+    pub btn3: HidButton,
+    pub side: HidButton,
+    pub menu: HidButton,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HidLeverLayout {
+    pub offset: usize,
+    /// 字节数，1、2 或 4
+    pub width: u8,
+    pub endian: Endian,
+    pub signed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HidLedLayout {
+    pub report_id: u8,
+    /// 报告 ID 之后、颜色数据之前的固定字节
+    pub header: Vec<u8>,
+}
+
+impl HidLedLayout {
+    /// board 0 映射后的灯带，报告以 `0, 101` 开头
+    fn strip() -> Self {
+        Self {
+            report_id: 0,
+            header: vec![0, 101],
+        }
+    }
+
+    /// board 0 的分块报告，报告以 `0, 102` 开头
+    fn chunk() -> Self {
+        Self {
+            report_id: 0,
+            header: vec![0, 102],
+        }
+    }
+}
+
+/// HID 输入/输出报告布局，默认值为原有固件的布局：
+/// 字节 0-9 为按键，字节 10-11 为大端摇杆，LED 报告以 `0, 100` 开头
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HidLayout {
+    pub left: HidButtonsLayout,
+    pub right: HidButtonsLayout,
+    pub lever: HidLeverLayout,
+    pub led: HidLedLayout,
+    /// `led_routes` 生成的灯带，只发送一个报告能容纳的部分
+    #[serde(default = "HidLedLayout::strip")]
+    pub strip: HidLedLayout,
+    /// `board0_chunks` 启用时 board 0 的分块报告
+    #[serde(default = "HidLedLayout::chunk")]
+    pub chunk: HidLedLayout,
+}
+
+impl Default for HidLayout {
+    fn default() -> Self {
+        Self {
+            left: HidButtonsLayout {
+                btn1: HidButton::byte(0),
+                btn2: HidButton::byte(1),
+                btn3: HidButton::byte(2),
+                side: HidButton::byte(3),
+                menu: HidButton::byte(4),
+            },
+            right: HidButtonsLayout {
+                btn1: HidButton::byte(5),
+                btn2: HidButton::byte(6),
+                btn3: HidButton::byte(7),
+                side: HidButton::byte(8),
+                menu: HidButton::byte(9),
+            },
+            lever: HidLeverLayout {
+                offset: 10,
+                width: 2,
+                endian: Endian::Big,
+                signed: true,
+            },
+            led: HidLedLayout {
+                report_id: 0,
+                header: vec![0, 100],
+            },
+            strip: HidLedLayout::strip(),
+            chunk: HidLedLayout::chunk(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HIDConfig {
     pub enabled: bool,
     pub vid: u16,
     pub pid: u16,
     pub interface: i32,
-    pub lever_left: i16,
-    pub lever_right: i16,
+    pub lever_left: i32,
+    pub lever_right: i32,
+    /// 忽略已保存的摇杆校准，从 `lever_left`/`lever_right` 重新学习
+    #[serde(default)]
+    pub reset_calibration: bool,
+    #[serde(default)]
+    pub layout: HidLayout,
+    #[serde(default)]
+    pub lever_filter: LeverFilterConfig,
+    /// board 0 分区到灯带的映射，为空时不输出 board 0
+    #[serde(default)]
+    pub led_routes: Vec<LedRoute>,
+    #[serde(default)]
+    pub channel_order: ChannelOrder,
+    /// 固件支持分块报告时发送完整的 board 0，没有映射时发送原始的 61 个 LED
+    #[serde(default)]
+    pub board0_chunks: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GamepadSideMapping {
+    pub btn1: Vec<PadButton>,
+    pub btn2: Vec<PadButton>,
+    pub btn3: Vec<PadButton>,
+    pub side: Vec<PadButton>,
+    pub menu: Vec<PadButton>,
+}
+
+/// XInput 手柄，只写 `enabled = true` 时使用默认映射
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamepadConfig {
+    pub enabled: bool,
+    /// XInput 手柄序号 0-3
+    pub user_index: u32,
+    /// 左摇杆 X 轴死区
+    pub lever_deadzone: i16,
+    pub lever_invert: bool,
+    /// 根据按键灯亮度震动
+    pub rumble: bool,
+    pub left: GamepadSideMapping,
+    pub right: GamepadSideMapping,
+    pub lever_filter: LeverFilterConfig,
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            user_index: 0,
+            lever_deadzone: 4000,
+            lever_invert: false,
+            rumble: false,
+            left: GamepadSideMapping {
+                btn1: vec![PadButton::DpadLeft],
+                btn2: vec![PadButton::DpadUp],
+                btn3: vec![PadButton::DpadRight],
+                side: vec![PadButton::LeftShoulder, PadButton::LeftTrigger],
+                menu: vec![PadButton::Back],
+            },
+            right: GamepadSideMapping {
+                btn1: vec![PadButton::X],
+                btn2: vec![PadButton::Y],
+                btn3: vec![PadButton::B],
+                side: vec![PadButton::RightShoulder, PadButton::RightTrigger],
+                menu: vec![PadButton::Start],
+            },
+            lever_filter: LeverFilterConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SharedRole {
+    /// 没有存活的主进程时成为主进程，否则作为子进程
+    Auto,
+    Owner,
+    Client,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedConfig {
+    pub enabled: bool,
+    pub role: SharedRole,
+    /// Windows 下为命名共享内存名称，其他平台下为映射文件名
+    pub name: String,
+    /// 主进程超过该时间（毫秒）没有更新时视为已退出
+    pub timeout_ms: u64,
+}
+
+impl Default for SharedConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            role: SharedRole::Auto,
+            name: "ongeki-io".to_string(),
+            timeout_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialProtocol {
+    /// 带校验和的二进制帧，格式见 README
+    Framed,
+}
+
+/// USB-CDC 等串口控制器，摇杆值由设备映射到 i16 后发送
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SerialConfig {
+    pub enabled: bool,
+    /// Windows 下如 `COM3`，Linux 下如 `/dev/ttyACM0`
+    pub port: String,
+    pub baud_rate: u32,
+    pub protocol: SerialProtocol,
+    pub lever_filter: LeverFilterConfig,
+    /// board 0 分区到灯带的映射，为空时发送原始的 61 个 LED
+    pub led_routes: Vec<LedRoute>,
+    pub channel_order: ChannelOrder,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: "COM3".to_string(),
+            baud_rate: 115200,
+            protocol: SerialProtocol::Framed,
+            lever_filter: LeverFilterConfig::default(),
+            led_routes: vec![],
+            channel_order: ChannelOrder::Rgb,
+        }
+    }
+}
+
+/// 通过 UDP 接收远程手台的输入，数据包格式见 README
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub enabled: bool,
+    pub bind: String,
+    pub port: u16,
+    /// 超过该时间（毫秒）没有收到输入包时松开所有按键
+    pub timeout_ms: u64,
+    pub lever_filter: LeverFilterConfig,
+    /// board 0 分区到灯带的映射，为空时发送原始的 61 个 LED
+    pub led_routes: Vec<LedRoute>,
+    pub channel_order: ChannelOrder,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: "0.0.0.0".to_string(),
+            port: 5730,
+            timeout_ms: 500,
+            lever_filter: LeverFilterConfig::default(),
+            led_routes: vec![],
+            channel_order: ChannelOrder::Rgb,
+        }
+    }
+}
+
+/// 每次 poll 后把汇总的输入写入录制文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordConfig {
+    pub enabled: bool,
+    pub path: String,
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "ongeki-io-input.rec".to_string(),
+        }
+    }
+}
+
+/// 记录每次 LED 调用，用 `led-preview` 离线预览
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LedRecordConfig {
+    pub enabled: bool,
+    pub path: String,
+}
+
+impl Default for LedRecordConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "ongeki-io-led.rec".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayMode {
+    /// 按录制时的时间回放
+    Realtime,
+    /// 每次 poll 回放一条，结果与轮询频率无关
+    Step,
+}
+
+/// 用录制文件代替真实硬件的输入
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplayConfig {
+    pub enabled: bool,
+    /// 录制文件，扩展名为 `.json` 时按 JSON 读取
+    pub path: String,
+    pub mode: ReplayMode,
+    #[serde(rename = "loop")]
+    pub looping: bool,
+    pub lever_filter: LeverFilterConfig,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "ongeki-io-input.rec".to_string(),
+            mode: ReplayMode::Realtime,
+            looping: false,
+            lever_filter: LeverFilterConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DmxProtocol {
+    /// Art-Net 4 的 ArtDmx，默认端口 6454
+    ArtNet,
+    /// sACN (E1.31)，默认端口 5568
+    Sacn,
+}
+
+/// 一块板在 DMX 中的起始位置，一个 universe 放不下时接着放到下一个 universe
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DmxPatch {
+    pub enabled: bool,
+    pub universe: u16,
+    /// 起始通道，从 1 开始
+    pub channel: u16,
+}
+
+impl Default for DmxPatch {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            universe: 1,
+            channel: 1,
+        }
+    }
+}
+
+/// 把 LED 颜色以 Art-Net 或 sACN 发送给 WLED 等 DMX 控制器
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DmxConfig {
+    pub enabled: bool,
+    pub protocol: DmxProtocol,
+    /// 控制器地址，sACN 为空时发送到各 universe 的组播地址
+    pub host: String,
+    /// 不写时使用协议的默认端口
+    pub port: Option<u16>,
+    /// 最大发送帧率，为 0 时不限制
+    pub max_fps: u32,
+    pub board0: DmxPatch,
+    pub board1: DmxPatch,
+    /// board 0 分区到灯带的映射，为空时发送原始的 61 个 LED
+    pub led_routes: Vec<LedRoute>,
+    pub channel_order: ChannelOrder,
+}
+
+impl Default for DmxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            protocol: DmxProtocol::ArtNet,
+            host: "127.0.0.1".to_string(),
+            port: None,
+            max_fps: 40,
+            board0: DmxPatch::default(),
+            // 紧接在 board 0 的 61 个 LED 之后
+            board1: DmxPatch {
+                channel: 184,
+                ..Default::default()
+            },
+            led_routes: vec![],
+            channel_order: ChannelOrder::Rgb,
+        }
+    }
+}
+
+/// 把一块板的颜色发送到 OpenRGB 的一个设备或其中一个区域
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenRgbTarget {
+    /// OpenRGB 中的设备序号
+    pub device: u32,
+    /// 设备中的区域序号，不写时更新整个设备
+    #[serde(default)]
+    pub zone: Option<u32>,
+    pub board: u8,
+    /// board 0 分区到灯带的映射，为空时发送原始的 61 个 LED
+    #[serde(default)]
+    pub led_routes: Vec<LedRoute>,
+}
+
+/// 连接 OpenRGB SDK 服务器，控制桌面和机箱灯光
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpenRgbConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    /// 在 OpenRGB 中显示的客户端名称
+    pub client_name: String,
+    /// 连接断开后重连的间隔（毫秒）
+    pub reconnect_ms: u64,
+    /// 连接后把用到的设备切换到自定义模式，否则部分设备不接受直接设置颜色
+    pub custom_mode: bool,
+    pub targets: Vec<OpenRgbTarget>,
+}
+
+impl Default for OpenRgbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "127.0.0.1".to_string(),
+            port: 6742,
+            client_name: "ongeki-io".to_string(),
+            reconnect_ms: 2000,
+            custom_mode: true,
+            targets: vec![],
+        }
+    }
+}
+
+/// 发送给 LED 驱动前的颜色处理，默认不改变颜色，录制和转发给主进程的数据不经过处理
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LedColorConfig {
+    /// 全局亮度，0.0-1.0
+    pub brightness: f32,
+    /// board 1 按键灯的亮度
+    pub button_brightness: f32,
+    /// board 0 各分区的亮度
+    pub zones: Vec<ZoneBrightness>,
+    /// 伽马值，1.0 为不校正，WS2811 灯带通常为 2.2-2.8
+    pub gamma: f32,
+    /// R、G、B 各通道的系数
+    pub white_balance: [f32; 3],
+    /// 每块板估算的电流上限（毫安），超过时整体调暗，为 0 时不限制
+    pub current_limit_ma: u32,
+    /// 单个通道满亮度时的电流（毫安）
+    pub channel_ma: f32,
+}
+
+impl Default for LedColorConfig {
+    fn default() -> Self {
+        Self {
+            brightness: 1.0,
+            button_brightness: 1.0,
+            zones: vec![],
+            gamma: 1.0,
+            white_balance: [1.0; 3],
+            current_limit_ma: 0,
+            channel_ma: 20.0,
+        }
+    }
+}
+
+/// 游戏一段时间没有更新 LED 时播放的待机灯效
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LedEffectsConfig {
+    pub enabled: bool,
+    /// 游戏超过该时间（毫秒）没有更新 LED 时开始播放
+    pub idle_timeout_ms: u64,
+    pub fps: u32,
+    /// 按顺序循环播放，每个灯效播放 `duration_ms`
+    pub effects: Vec<LedEffect>,
+}
+
+impl Default for LedEffectsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_timeout_ms: 10_000,
+            fps: 30,
+            effects: vec![
+                LedEffect::Rainbow {
+                    duration_ms: 20_000,
+                    period_ms: 5000,
+                },
+                LedEffect::PillarChase {
+                    duration_ms: 10_000,
+                    period_ms: 1500,
+                    color: [0, 160, 255],
+                    length: 6,
+                },
+                LedEffect::Breathe {
+                    duration_ms: 10_000,
+                    period_ms: 4000,
+                    color: [255, 64, 160],
+                },
+            ],
+        }
+    }
+}
+
+/// 按下按键时在对应的 board 1 按键灯上叠加闪光
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ButtonFeedbackConfig {
+    pub enabled: bool,
+    pub color: [u8; 3],
+    /// 松开后淡出的时间（毫秒）
+    pub fade_ms: u64,
+    /// 只在游戏超过 `idle_ms` 没有设置 board 1 时叠加
+    pub only_when_idle: bool,
+    pub idle_ms: u64,
+    /// 淡出时的最大发送帧率
+    pub fps: u32,
+}
+
+impl Default for ButtonFeedbackConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            color: [255, 255, 255],
+            fade_ms: 200,
+            only_when_idle: false,
+            idle_ms: 1000,
+            fps: 60,
+        }
+    }
+}
+
+/// 发送给各 LED 驱动前的去重和限速，录制和共享模式的转发不受影响
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LedOutputConfig {
+    /// 跳过与上一次发送给该驱动相同的帧
+    pub dedupe: bool,
+    /// 每个驱动每块板的最大发送帧率，为 0 时不限制
+    pub max_fps: u32,
+}
+
+impl Default for LedOutputConfig {
+    fn default() -> Self {
+        Self {
+            dedupe: true,
+            max_fps: 0,
+        }
+    }
+}
+
+/// 旧版 `mu3_io_set_led` 每个通道点亮和熄灭时的亮度
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LegacyLedConfig {
+    pub on: [u8; 3],
+    pub off: [u8; 3],
+}
+
+impl Default for LegacyLedConfig {
+    fn default() -> Self {
+        Self {
+            on: [255; 3],
+            off: [0; 3],
+        }
+    }
+}
+
+/// 启用后驱动在后台线程中按固定频率轮询，`mu3_io_poll` 只读取最新的输入快照
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PollConfig {
+    pub threaded: bool,
+    pub rate_hz: u32,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        Self {
+            threaded: false,
+            rate_hz: 1000,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +738,36 @@ pub struct Config {
     pub mouse: MouseConfig,
     pub hid: HIDConfig,
     pub led_debug: LEDebugConfig,
+    #[serde(default)]
+    pub gamepad: GamepadConfig,
+    #[serde(default)]
+    pub shared: SharedConfig,
+    #[serde(default)]
+    pub serial: SerialConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub record: RecordConfig,
+    #[serde(default)]
+    pub replay: ReplayConfig,
+    #[serde(default)]
+    pub led_record: LedRecordConfig,
+    #[serde(default)]
+    pub legacy_led: LegacyLedConfig,
+    #[serde(default)]
+    pub led_color: LedColorConfig,
+    #[serde(default)]
+    pub led_effects: LedEffectsConfig,
+    #[serde(default)]
+    pub button_feedback: ButtonFeedbackConfig,
+    #[serde(default)]
+    pub led_output: LedOutputConfig,
+    #[serde(default)]
+    pub dmx: DmxConfig,
+    #[serde(default)]
+    pub openrgb: OpenRgbConfig,
+    #[serde(default)]
+    pub poll: PollConfig,
 }
 
 impl Default for Config {
@@ -41,20 +775,91 @@ impl Default for Config {
         Self {
             keyboard: KeyBoardConfig {
                 enabled: true,
-                test: 0x31,
-                service: 0x32,
-                coin: 0x33,
+                test: KeyBinding::from(0x31),
+                service: KeyBinding::from(0x32),
+                coin: KeyBinding::from(0x33),
+                left: KeyBoardSideMapping::default_left(),
+                right: KeyBoardSideMapping::default_right(),
+            },
+            mouse: MouseConfig {
+                enabled: true,
+                lever_filter: LeverFilterConfig::default(),
             },
-            mouse: MouseConfig { enabled: true },
             hid: HIDConfig {
                 enabled: false,
                 vid: 0x2341,
                 pid: 0x8036,
                 interface: 1,
-                lever_left: i16::MIN,
-                lever_right: i16::MAX,
+                lever_left: i16::MIN.into(),
+                lever_right: i16::MAX.into(),
+                reset_calibration: false,
+                layout: HidLayout::default(),
+                lever_filter: LeverFilterConfig::default(),
+                led_routes: vec![],
+                channel_order: ChannelOrder::Rgb,
+                board0_chunks: false,
             },
             led_debug: LEDebugConfig { enabled: true },
+            gamepad: GamepadConfig::default(),
+            shared: SharedConfig::default(),
+            serial: SerialConfig::default(),
+            network: NetworkConfig::default(),
+            record: RecordConfig::default(),
+            replay: ReplayConfig::default(),
+            led_record: LedRecordConfig::default(),
+            legacy_led: LegacyLedConfig::default(),
+            led_color: LedColorConfig::default(),
+            led_effects: LedEffectsConfig::default(),
+            button_feedback: ButtonFeedbackConfig::default(),
+            led_output: LedOutputConfig::default(),
+            dmx: DmxConfig::default(),
+            openrgb: OpenRgbConfig::default(),
+            poll: PollConfig::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod config_test {
+    use super::Config;
+    use crate::enums::PadButton;
+    use crate::keys::KeyBinding;
+
+    #[test]
+    fn round_trip_test() {
+        let s = toml::to_string_pretty(&Config::default()).unwrap();
+        let config: Config = toml::from_str(&s).unwrap();
+        assert_eq!(config.keyboard.left.btn1, KeyBinding::from(0x41));
+        assert_eq!(config.keyboard.right.side, KeyBinding::from(0x02));
+    }
+
+    #[test]
+    fn legacy_keyboard_test() {
+        let mut table: toml::Table =
+            toml::from_str(&toml::to_string(&Config::default()).unwrap()).unwrap();
+        let keyboard = table["keyboard"].as_table_mut().unwrap();
+        keyboard.remove("left");
+        keyboard.remove("right");
+        keyboard.insert("test".into(), toml::Value::Integer(0x70));
+        table.remove("gamepad");
+
+        let config: Config = toml::from_str(&table.to_string()).unwrap();
+        assert_eq!(config.keyboard.test, KeyBinding::from(0x70));
+        assert_eq!(config.keyboard.left.menu, KeyBinding::from(0x55));
+        assert_eq!(config.keyboard.right.btn3, KeyBinding::from(0x4C));
+    }
+
+    #[test]
+    fn partial_gamepad_test() {
+        let mut table: toml::Table =
+            toml::from_str(&toml::to_string(&Config::default()).unwrap()).unwrap();
+        let gamepad: toml::Table = toml::from_str("enabled = true\nrumble = true").unwrap();
+        table.insert("gamepad".into(), toml::Value::Table(gamepad));
+
+        let config: Config = toml::from_str(&table.to_string()).unwrap();
+        assert!(config.gamepad.enabled);
+        assert!(config.gamepad.rumble);
+        assert_eq!(config.gamepad.lever_deadzone, 4000);
+        assert_eq!(config.gamepad.right.btn1, [PadButton::X]);
+    }
+}
@@ -0,0 +1,454 @@
+//! 以 Art-Net 或 sACN (E1.31) 把 LED 颜色发送给 WLED 等 DMX 控制器
+//!
+//! 每个 LED 占 3 个通道，与 WLED 一样不跨 universe 拆分，
+//! 一个 universe 最多放 170 个 LED，放不下时接着放到下一个 universe 的通道 1。
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use dyn_dyn::dyn_dyn_impl;
+use rgb::RGB8;
+
+use crate::{
+    config::{DmxConfig, DmxPatch, DmxProtocol},
+    enums::HResult,
+    led_layout::LedLayout,
+    led_legacy::LegacyLedState,
+};
+
+use super::{Driver, DriverError, LEDriver, LEDriverNew, PollDriver};
+
+const UNIVERSE_CHANNELS: usize = 512;
+const ARTNET_PORT: u16 = 6454;
+const SACN_PORT: u16 = 5568;
+/// 画面不变时按该间隔重发，避免控制器认为信号丢失
+const KEEPALIVE: Duration = Duration::from_secs(1);
+/// 打开失败后的重试间隔，解析地址可能阻塞
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// sACN 的发送端标识，固定即可
+const SACN_CID: [u8; 16] = *b"ongeki-io-sacn\0\0";
+const SACN_SOURCE: &[u8] = b"ongeki-io";
+
+#[derive(Debug)]
+pub enum DmxError {
+    Resolve(String),
+    Socket(io::Error),
+}
+
+impl DmxError {
+    pub fn hresult(&self) -> HResult {
+        match self {
+            DmxError::Resolve(_) | DmxError::Socket(_) => HResult::E_HANDLE,
+        }
+    }
+
+    /// 重试间隔内再次返回上一次打开失败的原因
+    fn repeat(&self) -> Self {
+        match self {
+            DmxError::Resolve(host) => DmxError::Resolve(host.clone()),
+            DmxError::Socket(e) => DmxError::Socket(io::Error::new(e.kind(), e.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for DmxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DmxError::Resolve(host) => write!(f, "Ongeki IO DMX: 无法解析地址 {host}"),
+            DmxError::Socket(e) => write!(f, "Ongeki IO DMX: 发送失败 {e}"),
+        }
+    }
+}
+
+/// Art-Net 的 ArtDmx 包，`universe` 为 15 位的 Port-Address
+pub fn artnet_packet(universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(18 + data.len());
+    buf.extend(b"Art-Net\0");
+    // OpOutput 0x5000，小端
+    buf.extend([0x00, 0x50]);
+    // 协议版本 14，大端
+    buf.extend([0, 14]);
+    buf.extend([sequence, 0]);
+    buf.extend([(universe & 0xFF) as u8, ((universe >> 8) & 0x7F) as u8]);
+    buf.extend((data.len() as u16).to_be_bytes());
+    buf.extend(data);
+    buf
+}
+
+/// sACN 的数据包，由根层、帧层和 DMP 层组成
+pub fn sacn_packet(universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+    // 每层的长度从该层开头算到包尾，高 4 位为标志 0x7
+    let pdu = |len: usize| (0x7000 | len as u16).to_be_bytes();
+    let total = 126 + data.len();
+
+    let mut buf = Vec::with_capacity(total);
+    buf.extend([0x00, 0x10, 0x00, 0x00]);
+    buf.extend(b"ASC-E1.17\0\0\0");
+    buf.extend(pdu(total - 16));
+    buf.extend(4u32.to_be_bytes());
+    buf.extend(SACN_CID);
+
+    buf.extend(pdu(total - 38));
+    buf.extend(2u32.to_be_bytes());
+    let mut source = [0u8; 64];
+    source[..SACN_SOURCE.len()].copy_from_slice(SACN_SOURCE);
+    buf.extend(source);
+    // 优先级 100，不使用同步地址
+    buf.extend([100, 0, 0, sequence, 0]);
+    buf.extend(universe.to_be_bytes());
+
+    buf.extend(pdu(total - 115));
+    buf.extend([0x02, 0xA1, 0, 0, 0, 1]);
+    // 通道数加上 1 字节的起始码 0
+    buf.extend((data.len() as u16 + 1).to_be_bytes());
+    buf.push(0);
+    buf.extend(data);
+    buf
+}
+
+#[derive(Debug, Clone)]
+struct Universe {
+    data: [u8; UNIVERSE_CHANNELS],
+    /// 已写入的通道数
+    len: usize,
+    sequence: u8,
+}
+
+impl Default for Universe {
+    fn default() -> Self {
+        Self {
+            data: [0; UNIVERSE_CHANNELS],
+            len: 0,
+            sequence: 0,
+        }
+    }
+}
+
+/// 把 LED 写入从 `patch` 开始的各 universe
+fn patch_leds(universes: &mut BTreeMap<u16, Universe>, patch: &DmxPatch, rgb: &[RGB8]) {
+    if !patch.enabled {
+        return;
+    }
+    let mut universe = patch.universe;
+    let mut channel = usize::from(patch.channel.max(1)) - 1;
+    for c in rgb {
+        if channel + 3 > UNIVERSE_CHANNELS {
+            universe = universe.wrapping_add(1);
+            channel = 0;
+        }
+        let u = universes.entry(universe).or_default();
+        u.data[channel..channel + 3].copy_from_slice(&[c.r, c.g, c.b]);
+        channel += 3;
+        u.len = u.len.max(channel);
+    }
+}
+
+pub struct DmxIO {
+    config: DmxConfig,
+    led_layout: LedLayout,
+    socket: Option<UdpSocket>,
+    /// 单播时的控制器地址，sACN 组播时为 `None`
+    target: Option<SocketAddr>,
+    universes: BTreeMap<u16, Universe>,
+    /// 有还没有发送的变化
+    dirty: bool,
+    last_send: Option<Instant>,
+    /// 上一次打开失败的时间和原因
+    open_failed: Option<(Instant, DmxError)>,
+}
+
+#[dyn_dyn_impl(Driver, PollDriver, LEDriver, LEDriverNew)]
+impl Driver for DmxIO {}
+
+impl DmxIO {
+    pub fn new(config: DmxConfig) -> Self {
+        let mut s = Self {
+            led_layout: LedLayout::new(config.led_routes.clone()),
+            config,
+            socket: None,
+            target: None,
+            universes: BTreeMap::new(),
+            dirty: false,
+            last_send: None,
+            open_failed: None,
+        };
+        if let Err(e) = s.reopen(Instant::now()) {
+            println!("{e}");
+        }
+        s
+    }
+
+    fn port(&self) -> u16 {
+        self.config.port.unwrap_or(match self.config.protocol {
+            DmxProtocol::ArtNet => ARTNET_PORT,
+            DmxProtocol::Sacn => SACN_PORT,
+        })
+    }
+
+    fn try_open(&mut self) -> Result<(), DmxError> {
+        let multicast = self.config.host.is_empty() && self.config.protocol == DmxProtocol::Sacn;
+        if !multicast {
+            let host = format!("{}:{}", self.config.host, self.port());
+            let target = host
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.find(SocketAddr::is_ipv4))
+                .ok_or(DmxError::Resolve(host))?;
+            self.target = Some(target);
+        }
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .and_then(|s| s.set_nonblocking(true).map(|_| s))
+            .map_err(DmxError::Socket)?;
+        match self.target {
+            Some(target) => println!("Ongeki IO DMX: 发送到 {target}"),
+            None => println!("Ongeki IO DMX: 发送到 sACN 组播地址"),
+        }
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    /// 打开失败后每隔 `RETRY_INTERVAL` 才重试，避免每次 poll 都解析地址
+    fn reopen(&mut self, now: Instant) -> Result<(), DmxError> {
+        if let Some((t, ref e)) = self.open_failed {
+            if now.saturating_duration_since(t) < RETRY_INTERVAL {
+                return Err(e.repeat());
+            }
+        }
+        let result = self.try_open();
+        self.open_failed = result.as_ref().err().map(|e| (now, e.repeat()));
+        result
+    }
+
+    fn update(&mut self, board: u8, rgb: &[RGB8], now: Instant) {
+        let patch = match board {
+            0 => &self.config.board0,
+            1 => &self.config.board1,
+            _ => return,
+        };
+        let rgb = self.led_layout.apply(board, rgb);
+        patch_leds(&mut self.universes, patch, &rgb);
+        self.dirty = true;
+        // 没到发送时间的帧留到之后的 set_led 或 poll 再发
+        if let Err(e) = self.flush(now) {
+            println!("{e}");
+        }
+    }
+
+    /// 有变化且距上次发送超过帧间隔时发送，画面不变时定期重发
+    fn flush(&mut self, now: Instant) -> Result<(), DmxError> {
+        let period = match self.config.max_fps {
+            0 => Duration::ZERO,
+            fps => Duration::from_secs(1) / fps,
+        };
+        let due = match self.last_send {
+            None => self.dirty,
+            Some(t) if self.dirty => now - t >= period,
+            Some(t) => now - t >= KEEPALIVE,
+        };
+        if due {
+            self.send()?;
+            self.dirty = false;
+            self.last_send = Some(now);
+        }
+        Ok(())
+    }
+
+    fn send(&mut self) -> Result<(), DmxError> {
+        let Some(ref socket) = self.socket else {
+            return Ok(());
+        };
+        let port = self.port();
+        for (&number, universe) in self.universes.iter_mut() {
+            // Art-Net 的通道数必须为偶数
+            let len = universe.len.next_multiple_of(2).max(2);
+            let data = &universe.data[..len];
+            // 序号 0 表示不使用序号，跳过
+            universe.sequence = universe.sequence.checked_add(1).unwrap_or(1);
+            let packet = match self.config.protocol {
+                DmxProtocol::ArtNet => artnet_packet(number, universe.sequence, data),
+                DmxProtocol::Sacn => sacn_packet(number, universe.sequence, data),
+            };
+            let target = self.target.unwrap_or_else(|| {
+                let [hi, lo] = number.to_be_bytes();
+                SocketAddr::from((Ipv4Addr::new(239, 255, hi, lo), port))
+            });
+            match socket.send_to(&packet, target) {
+                Ok(_) => {}
+                // 发送缓冲区满时丢弃这一帧
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(DmxError::Socket(e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PollDriver for DmxIO {
+    fn poll(&mut self) -> Result<(), DriverError> {
+        if self.socket.is_none() {
+            self.reopen(Instant::now())?;
+        }
+        Ok(self.flush(Instant::now())?)
+    }
+}
+
+impl LEDriver for DmxIO {
+    fn set_led(&mut self, data: u32) {
+        self.update(
+            1,
+            &LegacyLedState(data).colors(&Default::default()),
+            Instant::now(),
+        );
+    }
+}
+
+impl LEDriverNew for DmxIO {
+    fn set_led_new(&mut self, board: u8, rgb: &[RGB8]) {
+        self.update(board, rgb, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod dmx_test {
+    use super::*;
+    use crate::config::Config;
+
+    fn dmx_io(protocol: DmxProtocol, max_fps: u32) -> (DmxIO, UdpSocket) {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let config = DmxConfig {
+            enabled: true,
+            protocol,
+            host: "127.0.0.1".to_string(),
+            port: Some(listener.local_addr().unwrap().port()),
+            max_fps,
+            ..Config::default().dmx
+        };
+        (DmxIO::new(config), listener)
+    }
+
+    fn recv(listener: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0u8; 1024];
+        let n = listener.recv(&mut buf).unwrap();
+        buf[..n].to_vec()
+    }
+
+    fn nothing_received(listener: &UdpSocket) -> bool {
+        listener.set_nonblocking(true).unwrap();
+        let mut buf = [0u8; 1024];
+        let empty = listener.recv(&mut buf).is_err();
+        listener.set_nonblocking(false).unwrap();
+        empty
+    }
+
+    #[test]
+    fn patch_test() {
+        let mut universes = BTreeMap::new();
+        let patch = DmxPatch {
+            enabled: true,
+            universe: 3,
+            channel: 508,
+        };
+        let rgb: Vec<_> = (1..=3).map(|i| RGB8::new(i, i, i)).collect();
+        patch_leds(&mut universes, &patch, &rgb);
+        // 第 2 个 LED 放不下，从下一个 universe 的通道 1 开始
+        assert_eq!(universes[&3].len, 510);
+        assert_eq!(universes[&3].data[507..510], [1, 1, 1]);
+        assert_eq!(universes[&4].len, 6);
+        assert_eq!(universes[&4].data[..6], [2, 2, 2, 3, 3, 3]);
+
+        let disabled = DmxPatch {
+            enabled: false,
+            ..patch
+        };
+        patch_leds(&mut universes, &disabled, &[RGB8::new(9, 9, 9)]);
+        assert_eq!(universes[&3].data[507], 1);
+    }
+
+    #[test]
+    fn artnet_test() {
+        let (mut io, listener) = dmx_io(DmxProtocol::ArtNet, 0);
+        io.set_led_new(1, &[RGB8::new(1, 2, 3); 6]);
+
+        let packet = recv(&listener);
+        assert_eq!(packet[..8], *b"Art-Net\0");
+        assert_eq!(packet[8..12], [0x00, 0x50, 0, 14]);
+        // 序号 1，universe 1，board 1 从通道 184 开始，共 201 个通道补齐为 202
+        assert_eq!(packet[12..18], [1, 0, 1, 0, 0, 202]);
+        assert_eq!(packet.len(), 18 + 202);
+        assert_eq!(packet[18 + 183..18 + 186], [1, 2, 3]);
+        assert_eq!(packet[18 + 201], 0);
+
+        // 旧版 API 的按键灯同样发送到 board 1
+        io.set_led(1 << 23);
+        let packet = recv(&listener);
+        assert_eq!(packet[12], 2);
+        assert_eq!(packet[18 + 183..18 + 186], [255, 0, 0]);
+    }
+
+    #[test]
+    fn sacn_test() {
+        let (mut io, listener) = dmx_io(DmxProtocol::Sacn, 0);
+        io.set_led_new(0, &[RGB8::new(7, 8, 9); 61]);
+
+        let packet = recv(&listener);
+        assert_eq!(packet.len(), 126 + 184);
+        assert_eq!(packet[4..16], *b"ASC-E1.17\0\0\0");
+        assert_eq!(packet[16..18], (0x7000u16 | (310 - 16)).to_be_bytes());
+        assert_eq!(packet[38..40], (0x7000u16 | (310 - 38)).to_be_bytes());
+        assert_eq!(packet[44..53], *b"ongeki-io");
+        // 优先级、序号和 universe
+        assert_eq!(packet[108], 100);
+        assert_eq!(packet[111], 1);
+        assert_eq!(packet[113..115], [0, 1]);
+        assert_eq!(packet[115..117], (0x7000u16 | (310 - 115)).to_be_bytes());
+        assert_eq!(packet[123..126], [0, 185, 0]);
+        assert_eq!(packet[126..129], [7, 8, 9]);
+    }
+
+    #[test]
+    fn rate_limit_test() {
+        let (mut io, listener) = dmx_io(DmxProtocol::ArtNet, 10);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        io.update(1, &[RGB8::new(1, 1, 1); 6], start);
+        assert_eq!(recv(&listener)[18 + 183], 1);
+        // 帧间隔内的变化先不发送，到时间后由 poll 发送最新的一帧
+        io.update(1, &[RGB8::new(2, 2, 2); 6], at(30));
+        io.update(1, &[RGB8::new(3, 3, 3); 6], at(60));
+        assert!(nothing_received(&listener));
+        io.flush(at(90)).unwrap();
+        assert!(nothing_received(&listener));
+        io.flush(at(100)).unwrap();
+        assert_eq!(recv(&listener)[18 + 183], 3);
+
+        // 没有变化时按 KEEPALIVE 重发
+        io.flush(at(500)).unwrap();
+        assert!(nothing_received(&listener));
+        io.flush(at(1100)).unwrap();
+        assert_eq!(recv(&listener)[18 + 183], 3);
+    }
+
+    #[test]
+    fn retry_test() {
+        let (mut io, _listener) = dmx_io(DmxProtocol::ArtNet, 0);
+        let now = Instant::now();
+        io.socket = None;
+        io.open_failed = Some((now, DmxError::Resolve("controller".to_string())));
+
+        // 重试间隔内不再打开，返回上一次的错误
+        let err = io.reopen(now + RETRY_INTERVAL / 2).unwrap_err();
+        assert!(matches!(err, DmxError::Resolve(ref host) if host == "controller"));
+        assert!(io.socket.is_none());
+
+        io.reopen(now + RETRY_INTERVAL).unwrap();
+        assert!(io.socket.is_some());
+        assert!(io.open_failed.is_none());
+    }
+}
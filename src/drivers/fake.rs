@@ -0,0 +1,103 @@
+//! 测试用驱动：输入由字段决定，记录收到的 LED 调用，可以模拟出错和 panic
+
+use std::sync::{Arc, Mutex};
+
+use dyn_dyn::dyn_dyn_impl;
+
+use super::{
+    ButtonDriver, Driver, DriverError, Drivers, LEDriver, LEDriverNew, LeverDriver, PollDriver,
+    ReconnectDriver,
+};
+use crate::enums::HResult;
+use crate::led_recording::LedCall;
+
+#[derive(Default)]
+pub struct FakeIO {
+    pub fail: Option<HResult>,
+    pub panic_poll: bool,
+    pub panic_input: bool,
+    pub panic_led: bool,
+    pub left: u8,
+    pub lever: i16,
+    /// 模拟重新连接了设备
+    pub reconnected: bool,
+    pub leds: Arc<Mutex<Vec<LedCall>>>,
+}
+
+#[dyn_dyn_impl(
+    Driver,
+    PollDriver,
+    ButtonDriver,
+    LeverDriver,
+    LEDriver,
+    LEDriverNew,
+    ReconnectDriver
+)]
+impl Driver for FakeIO {}
+
+impl PollDriver for FakeIO {
+    fn poll(&mut self) -> Result<(), DriverError> {
+        assert!(!self.panic_poll, "poll");
+        self.fail.map_or(Ok(()), |hr| Err(DriverError::Test(hr)))
+    }
+}
+
+impl ButtonDriver for FakeIO {
+    fn op_btns(&self) -> u8 {
+        assert!(!self.panic_input, "op_btns");
+        0
+    }
+
+    fn left_btns(&self) -> u8 {
+        assert!(!self.panic_input, "left_btns");
+        self.left
+    }
+
+    fn right_btns(&self) -> u8 {
+        0
+    }
+}
+
+impl LeverDriver for FakeIO {
+    fn lever(&self) -> i16 {
+        self.lever
+    }
+}
+
+impl LEDriver for FakeIO {
+    fn set_led(&mut self, data: u32) {
+        assert!(!self.panic_led, "set_led");
+        self.leds.lock().unwrap().push(LedCall::Legacy(data));
+    }
+}
+
+impl LEDriverNew for FakeIO {
+    fn set_led_new(&mut self, board: u8, rgb: &[rgb::RGB8]) {
+        assert!(!self.panic_led, "set_led_new");
+        self.leds
+            .lock()
+            .unwrap()
+            .push(LedCall::Colors(board, rgb.to_vec()));
+    }
+}
+
+impl ReconnectDriver for FakeIO {
+    fn take_reconnected(&mut self) -> bool {
+        std::mem::take(&mut self.reconnected)
+    }
+}
+
+impl Drivers {
+    /// 只包含这些测试驱动
+    pub fn with_fakes(fakes: Vec<FakeIO>) -> Self {
+        let mut drivers = Drivers::new();
+        for fake in fakes {
+            drivers.push(Box::new(fake));
+        }
+        drivers
+    }
+
+    pub fn replace_fake(&mut self, index: usize, fake: FakeIO) {
+        self.drivers[index].driver = Box::new(fake);
+    }
+}
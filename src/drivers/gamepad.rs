@@ -0,0 +1,375 @@
+use std::fmt;
+
+use crate::{
+    config::{GamepadConfig, GamepadSideMapping},
+    enums::{GameBtn, HResult, PadButton, ERROR_DEVICE_NOT_CONNECTED},
+    led_legacy::{ButtonLed, LegacyLedState},
+};
+
+use super::{
+    hid, ButtonDriver, Driver, DriverError, LEDriver, LEDriverNew, LeverDriver, PollDriver,
+};
+
+use dyn_dyn::dyn_dyn_impl;
+
+/// 扳机按下阈值，与 XINPUT_GAMEPAD_TRIGGER_THRESHOLD 相同
+const TRIGGER_THRESHOLD: u8 = 30;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PadState {
+    pub buttons: u16,
+    pub left_trigger: u8,
+    pub right_trigger: u8,
+    pub thumb_lx: i16,
+}
+
+impl PadState {
+    /// 按 `PadButton` 的位布局返回当前按下的键
+    fn pressed(&self) -> u32 {
+        let mut mask = u32::from(self.buttons);
+        if self.left_trigger > TRIGGER_THRESHOLD {
+            mask |= PadButton::LeftTrigger as u32;
+        }
+        if self.right_trigger > TRIGGER_THRESHOLD {
+            mask |= PadButton::RightTrigger as u32;
+        }
+        mask
+    }
+}
+
+/// 手柄状态来源，便于在没有 XInput 的环境下测试
+pub trait PadSource: Send + Sync {
+    /// 读取手柄状态，未连接时返回 `None`
+    fn state(&mut self, user_index: u32) -> Option<PadState>;
+    fn set_rumble(&mut self, user_index: u32, left: u16, right: u16);
+}
+
+#[cfg(windows)]
+pub struct XInput;
+
+#[cfg(windows)]
+impl PadSource for XInput {
+    fn state(&mut self, user_index: u32) -> Option<PadState> {
+        use windows::Win32::UI::Input::XboxController::{self, XINPUT_STATE};
+
+        let mut state = XINPUT_STATE::default();
+        if unsafe { XboxController::XInputGetState(user_index, &mut state) } != 0 {
+            return None;
+        }
+        Some(PadState {
+            buttons: state.Gamepad.wButtons.0,
+            left_trigger: state.Gamepad.bLeftTrigger,
+            right_trigger: state.Gamepad.bRightTrigger,
+            thumb_lx: state.Gamepad.sThumbLX,
+        })
+    }
+
+    fn set_rumble(&mut self, user_index: u32, left: u16, right: u16) {
+        use windows::Win32::UI::Input::XboxController::{self, XINPUT_VIBRATION};
+
+        let vibration = XINPUT_VIBRATION {
+            wLeftMotorSpeed: left,
+            wRightMotorSpeed: right,
+        };
+        unsafe {
+            XboxController::XInputSetState(user_index, &vibration);
+        }
+    }
+}
+
+/// 非 Windows 平台没有 XInput，手柄始终未连接
+#[cfg(not(windows))]
+pub struct XInput;
+
+#[cfg(not(windows))]
+impl PadSource for XInput {
+    fn state(&mut self, _user_index: u32) -> Option<PadState> {
+        None
+    }
+
+    fn set_rumble(&mut self, _user_index: u32, _left: u16, _right: u16) {}
+}
+
+#[derive(Debug)]
+pub enum GamepadError {
+    NotConnected(u32),
+}
+
+impl GamepadError {
+    pub fn hresult(&self) -> HResult {
+        match self {
+            GamepadError::NotConnected(_) => HResult::from_win32(ERROR_DEVICE_NOT_CONNECTED),
+        }
+    }
+}
+
+impl fmt::Display for GamepadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GamepadError::NotConnected(user_index) => {
+                write!(f, "Ongeki IO Gamepad: 手柄 {user_index} 未连接")
+            }
+        }
+    }
+}
+
+pub struct GamepadIO {
+    lever: i16,
+    left_btns: u8,
+    right_btns: u8,
+    connected: bool,
+    rumble: (u16, u16),
+    config: GamepadConfig,
+    source: Box<dyn PadSource>,
+}
+
+#[dyn_dyn_impl(Driver, PollDriver, ButtonDriver, LeverDriver, LEDriver, LEDriverNew)]
+impl Driver for GamepadIO {}
+
+impl GamepadIO {
+    /// 同时返回手柄当前是否已连接
+    pub fn new(config: GamepadConfig) -> (Self, Result<(), GamepadError>) {
+        let mut s = Self::with_source(config, Box::new(XInput));
+        let status = match s.source.state(s.config.user_index) {
+            Some(_) => Ok(()),
+            None => Err(GamepadError::NotConnected(s.config.user_index)),
+        };
+        (s, status)
+    }
+
+    pub fn with_source(config: GamepadConfig, source: Box<dyn PadSource>) -> Self {
+        Self {
+            lever: 0,
+            left_btns: 0,
+            right_btns: 0,
+            connected: false,
+            rumble: (0, 0),
+            config,
+            source,
+        }
+    }
+
+    fn set_rumble(&mut self, left: u16, right: u16) {
+        if !self.config.rumble || !self.connected || self.rumble == (left, right) {
+            return;
+        }
+        self.rumble = (left, right);
+        self.source.set_rumble(self.config.user_index, left, right);
+    }
+}
+
+fn side_btns(mapping: &GamepadSideMapping, pressed: u32) -> u8 {
+    let is_pressed = |btns: &[PadButton]| btns.iter().any(|b| pressed & *b as u32 != 0);
+
+    let mut btns = 0;
+    if is_pressed(&mapping.btn1) {
+        btns |= GameBtn::Btn1 as u8
+    }
+    if is_pressed(&mapping.btn2) {
+        btns |= GameBtn::Btn2 as u8
+    }
+    if is_pressed(&mapping.btn3) {
+        btns |= GameBtn::Btn3 as u8
+    }
+    if is_pressed(&mapping.side) {
+        btns |= GameBtn::Side as u8
+    }
+    if is_pressed(&mapping.menu) {
+        btns |= GameBtn::Menu as u8
+    }
+    btns
+}
+
+/// 去掉死区后将摇杆重新映射到完整的摇杆范围
+fn stick_to_lever(x: i16, deadzone: i16, invert: bool) -> i16 {
+    let deadzone = i32::from(deadzone.max(0));
+    let x = i32::from(x);
+    let lever = if x.abs() <= deadzone {
+        0
+    } else if x > 0 {
+        hid::map(x, deadzone, i32::from(i16::MAX), 0, i32::from(i16::MAX))
+    } else {
+        hid::map(x, -deadzone, i32::from(i16::MIN), 0, i32::from(i16::MIN))
+    };
+    let lever = if invert { -lever } else { lever };
+    lever.clamp(i32::from(i16::MIN), i32::from(i16::MAX)) as i16
+}
+
+/// 三个按键灯的平均亮度换算为马达转速
+fn brightness(rgb: &[rgb::RGB8]) -> u16 {
+    if rgb.is_empty() {
+        return 0;
+    }
+    let sum: u32 = rgb.iter().map(|c| u32::from(c.r.max(c.g).max(c.b))).sum();
+    (sum * 257 / rgb.len() as u32) as u16
+}
+
+impl PollDriver for GamepadIO {
+    fn poll(&mut self) -> Result<(), DriverError> {
+        self.left_btns = 0;
+        self.right_btns = 0;
+
+        let Some(state) = self.source.state(self.config.user_index) else {
+            self.connected = false;
+            self.rumble = (0, 0);
+            self.lever = 0;
+            return Err(GamepadError::NotConnected(self.config.user_index).into());
+        };
+        if !self.connected {
+            println!("Ongeki IO Gamepad: 手柄 {} 已连接", self.config.user_index);
+            self.connected = true;
+        }
+
+        let pressed = state.pressed();
+        self.left_btns = side_btns(&self.config.left, pressed);
+        self.right_btns = side_btns(&self.config.right, pressed);
+        self.lever = stick_to_lever(
+            state.thumb_lx,
+            self.config.lever_deadzone,
+            self.config.lever_invert,
+        );
+
+        Ok(())
+    }
+}
+
+impl LeverDriver for GamepadIO {
+    fn lever(&self) -> i16 {
+        self.lever
+    }
+}
+
+impl ButtonDriver for GamepadIO {
+    fn op_btns(&self) -> u8 {
+        0
+    }
+
+    fn left_btns(&self) -> u8 {
+        self.left_btns
+    }
+
+    fn right_btns(&self) -> u8 {
+        self.right_btns
+    }
+}
+
+impl LEDriver for GamepadIO {
+    fn set_led(&mut self, data: u32) {
+        // 每侧 3 个按键灯共 9 个通道，按点亮的通道数设置震动强度
+        let state = LegacyLedState(data);
+        let strength = |leds| (state.lit(leds) as u32 * u32::from(u16::MAX) / 9) as u16;
+        let left = strength(&ButtonLed::LEFT);
+        let right = strength(&ButtonLed::RIGHT);
+        self.set_rumble(left, right);
+    }
+}
+
+impl LEDriverNew for GamepadIO {
+    fn set_led_new(&mut self, board: u8, rgb: &[rgb::RGB8]) {
+        if board == 1 && rgb.len() >= 6 {
+            self.set_rumble(brightness(&rgb[..3]), brightness(&rgb[3..6]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod gamepad_test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct FakePad {
+        state: Arc<Mutex<Option<PadState>>>,
+        rumble: Arc<Mutex<Vec<(u16, u16)>>>,
+    }
+
+    impl PadSource for FakePad {
+        fn state(&mut self, _user_index: u32) -> Option<PadState> {
+            *self.state.lock().unwrap()
+        }
+
+        fn set_rumble(&mut self, _user_index: u32, left: u16, right: u16) {
+            self.rumble.lock().unwrap().push((left, right));
+        }
+    }
+
+    fn fake_gamepad(config: GamepadConfig) -> (GamepadIO, FakePad) {
+        let pad = FakePad::default();
+        let shared = FakePad {
+            state: pad.state.clone(),
+            rumble: pad.rumble.clone(),
+        };
+        (GamepadIO::with_source(config, Box::new(shared)), pad)
+    }
+
+    #[test]
+    fn default_mapping_test() {
+        let (mut io, pad) = fake_gamepad(GamepadConfig::default());
+        *pad.state.lock().unwrap() = Some(PadState {
+            buttons: PadButton::DpadLeft as u16 | PadButton::Y as u16 | PadButton::Start as u16,
+            right_trigger: 255,
+            ..Default::default()
+        });
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), GameBtn::Btn1 as u8);
+        assert_eq!(
+            io.right_btns(),
+            GameBtn::Btn2 as u8 | GameBtn::Side as u8 | GameBtn::Menu as u8
+        );
+
+        *pad.state.lock().unwrap() = None;
+        let err = io.poll().unwrap_err();
+        assert_eq!(err.hresult().0 as u32, 0x8007_048F);
+        assert_eq!(io.left_btns(), 0);
+        assert_eq!(io.right_btns(), 0);
+    }
+
+    #[test]
+    fn trigger_threshold_test() {
+        let state = PadState {
+            left_trigger: TRIGGER_THRESHOLD,
+            right_trigger: TRIGGER_THRESHOLD + 1,
+            ..Default::default()
+        };
+        assert_eq!(state.pressed(), PadButton::RightTrigger as u32);
+    }
+
+    #[test]
+    fn stick_to_lever_test() {
+        assert_eq!(stick_to_lever(3000, 4000, false), 0);
+        assert_eq!(stick_to_lever(-4000, 4000, false), 0);
+        assert_eq!(stick_to_lever(i16::MAX, 4000, false), i16::MAX);
+        assert_eq!(stick_to_lever(i16::MIN, 4000, false), i16::MIN);
+        assert_eq!(stick_to_lever(i16::MAX, 4000, true), -i16::MAX);
+        assert_eq!(stick_to_lever(i16::MIN, 0, true), i16::MAX);
+        assert!(stick_to_lever(10000, 4000, false) > 0);
+    }
+
+    #[test]
+    fn rumble_test() {
+        let config = GamepadConfig {
+            rumble: true,
+            ..Default::default()
+        };
+        let (mut io, pad) = fake_gamepad(config);
+        let mut rgb = [rgb::RGB8::default(); 6];
+        rgb[0] = rgb::RGB8::new(255, 0, 0);
+        rgb[1] = rgb::RGB8::new(0, 255, 0);
+        rgb[2] = rgb::RGB8::new(0, 0, 255);
+
+        // 未连接时不震动
+        io.set_led_new(1, &rgb);
+        assert!(pad.rumble.lock().unwrap().is_empty());
+
+        *pad.state.lock().unwrap() = Some(PadState::default());
+        io.poll().unwrap();
+        io.set_led_new(1, &rgb);
+        io.set_led_new(1, &rgb);
+        io.set_led_new(0, &[rgb::RGB8::new(255, 255, 255); 61]);
+        assert_eq!(*pad.rumble.lock().unwrap(), vec![(u16::MAX, 0)]);
+
+        io.set_led(1 << 14);
+        assert_eq!(pad.rumble.lock().unwrap()[1], (0, u16::MAX / 9));
+    }
+}
@@ -1,158 +1,444 @@
+use std::fmt;
 use std::io::{Cursor, Write};
+use std::time::{Duration, Instant};
 
 use crate::{
-    config::HIDConfig,
-    enums::{GameBtn, HResult},
+    calibration::{CalibrationStore, LeverCalibration, CALIBRATION_PATH},
+    config::{Endian, HIDConfig, HidButton, HidButtonsLayout, HidLedLayout, HidLeverLayout},
+    enums::{GameBtn, HResult, ERROR_DEVICE_NOT_CONNECTED},
+    led_layout::LedLayout,
+    led_legacy::LegacyLedState,
 };
 
-use super::{ButtonDriver, Driver, LEDriver, LeverDriver, PollDriver, LEDriverNew};
+use super::{
+    ButtonDriver, Driver, DriverError, LEDriver, LEDriverNew, LeverDriver, PollDriver,
+    ReconnectDriver, ShutdownDriver,
+};
 
-use byteorder::WriteBytesExt;
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use dyn_dyn::dyn_dyn_impl;
-use hidapi::{HidApi, HidDevice};
+
+use self::transport::{HidApiTransport, HidConnection, HidTransport};
+
+#[cfg(test)]
+mod mock;
+mod transport;
+
+/// 校准数据写入文件的最小间隔
+const CALIBRATION_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// 同时按住左右 Menu 键多久后重置校准
+const CALIBRATION_RESET_HOLD: Duration = Duration::from_secs(3);
+/// 每次 poll 最多读取的报告数
+const MAX_REPORTS_PER_POLL: usize = 16;
+/// 未连接时两次枚举设备的最小间隔
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum HidError {
+    /// hidapi 初始化或打开设备失败
+    Api(hidapi::HidError),
+    NotConnected,
+    Disconnected(hidapi::HidError),
+}
+
+impl HidError {
+    pub fn hresult(&self) -> HResult {
+        match self {
+            HidError::Api(_) => HResult::E_HANDLE,
+            HidError::NotConnected | HidError::Disconnected(_) => {
+                HResult::from_win32(ERROR_DEVICE_NOT_CONNECTED)
+            }
+        }
+    }
+}
+
+impl fmt::Display for HidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HidError::Api(e) => write!(f, "Ongeki IO HID: 无法打开设备 {e}"),
+            HidError::NotConnected => write!(f, "Ongeki IO HID: 设备未连接"),
+            HidError::Disconnected(e) => write!(f, "Ongeki IO HID: 设备断开 {e}"),
+        }
+    }
+}
 
 pub struct HidIO {
     lever: i16,
     left_btns: u8,
     right_btns: u8,
     config: HIDConfig,
-    device: Option<HidDevice>,
+    transport: Box<dyn HidTransport>,
+    device: Option<Box<dyn HidConnection>>,
+    /// 上一次尝试连接的时间，用于限制重连频率
+    last_connect: Option<Instant>,
+    /// 连接后还没有通知重新发送 LED
+    reconnected: bool,
+    range: LeverCalibration,
+    calibration: CalibrationStore,
+    calibration_key: Option<String>,
+    calibration_dirty: bool,
+    calibration_saved: Instant,
+    reset_held: Option<Instant>,
+    /// 已经重置过，松开 Menu 键之前不再重置
+    reset_latched: bool,
+    led_layout: LedLayout,
+    /// 上一次完整发送的 board 0 灯带，分块报告只发送变化的部分
+    last_strip: Option<Vec<rgb::RGB8>>,
+    strip_sequence: u8,
 }
 
-#[dyn_dyn_impl(Driver, PollDriver, ButtonDriver, LeverDriver, LEDriver, LEDriverNew)]
+#[dyn_dyn_impl(
+    Driver,
+    PollDriver,
+    ButtonDriver,
+    LeverDriver,
+    LEDriver,
+    LEDriverNew,
+    ReconnectDriver,
+    ShutdownDriver
+)]
 impl Driver for HidIO {}
 unsafe impl Sync for HidIO {}
 
 impl HidIO {
-    pub fn new(config: HIDConfig) -> Self {
+    /// 同时返回首次连接的结果，未连接时之后 poll 会继续尝试
+    pub fn new(config: HIDConfig) -> (Self, Result<(), HidError>) {
+        Self::with_transport(
+            config,
+            Box::new(HidApiTransport::default()),
+            CalibrationStore::load(CALIBRATION_PATH),
+        )
+    }
+
+    pub fn with_transport(
+        config: HIDConfig,
+        transport: Box<dyn HidTransport>,
+        calibration: CalibrationStore,
+    ) -> (Self, Result<(), HidError>) {
         let mut s = HidIO {
             lever: 0,
             left_btns: 0,
             right_btns: 0,
+            range: LeverCalibration {
+                lever_left: config.lever_left,
+                lever_right: config.lever_right,
+            },
+            led_layout: LedLayout::new(config.led_routes.clone()),
             config,
+            transport,
             device: None,
+            last_connect: None,
+            reconnected: false,
+            calibration,
+            calibration_key: None,
+            calibration_dirty: false,
+            calibration_saved: Instant::now(),
+            reset_held: None,
+            reset_latched: false,
+            last_strip: None,
+            strip_sequence: 0,
         };
-        s.try_connect_device();
-        s
-    }
-
-    fn try_connect_device(&mut self) {
-        let api = HidApi::new().unwrap();
-        self.device = api
-            .device_list()
-            .filter(|d| d.vendor_id() == self.config.vid && d.product_id() == self.config.pid)
-            .find_map(|d| {
-                if d.interface_number() == self.config.interface {
-                    println!(
-                        "Ongeki IO HID: {} 已连接",
-                        d.product_string().unwrap_or_default()
-                    );
-                    return d.open_device(&api).inspect(|d| {
-                        d.set_blocking_mode(false).unwrap();
-                    }).ok();
-                }
-                None
-            });
+        s.check_strip_length();
+        let status = s.reconnect(Instant::now());
+        (s, status)
     }
-}
-
-impl PollDriver for HidIO {
-    fn poll(&mut self) -> HResult {
-        let Some(ref device) = self.device else {
-            self.try_connect_device();
-            return HResult::Ok;
-        };
 
-        let mut data = [0u8; 64];
-        self.left_btns = 0;
-        self.right_btns = 0;
-        if let Err(e) = device.read(&mut data) {
-            println!("Ongeki IO HID: 设备断开 {e}");
-            self.device = None;
-            return HResult::Ok;
+    /// 不使用分块报告时，映射后的灯带超出一个报告的部分会被截断
+    fn check_strip_length(&self) {
+        if self.config.board0_chunks || self.led_layout.is_empty() {
+            return;
         }
+        let capacity = strip_capacity(&self.config.layout.strip);
+        if self.led_layout.len() > capacity {
+            println!(
+                "Ongeki IO HID: led_routes 共 {} 个 LED，一个报告只能发送前 {capacity} 个，固件支持时请设置 board0_chunks = true",
+                self.led_layout.len()
+            );
+        }
+    }
 
-        if data[0] == 1 {
-            self.left_btns |= GameBtn::Btn1 as u8
+    /// 距离上一次尝试不足 `RECONNECT_INTERVAL` 时不枚举设备
+    fn reconnect(&mut self, now: Instant) -> Result<(), HidError> {
+        if self
+            .last_connect
+            .is_some_and(|t| now.saturating_duration_since(t) < RECONNECT_INTERVAL)
+        {
+            return Err(HidError::NotConnected);
         }
-        if data[1] == 1 {
-            self.left_btns |= GameBtn::Btn2 as u8
+        self.last_connect = Some(now);
+        self.try_connect_device()
+    }
+
+    fn try_connect_device(&mut self) -> Result<(), HidError> {
+        let info = self
+            .transport
+            .enumerate()?
+            .into_iter()
+            .find(|d| {
+                d.vid == self.config.vid
+                    && d.pid == self.config.pid
+                    && d.interface == self.config.interface
+            })
+            .ok_or(HidError::NotConnected)?;
+        let device = self.transport.open(&info)?;
+        println!("Ongeki IO HID: {} 已连接", info.product);
+
+        let key = CalibrationStore::key(self.config.vid, self.config.pid, info.serial.as_deref());
+        self.device = Some(device);
+        // 设备可能刚上电，重新发送完整的 board 0
+        self.last_strip = None;
+        self.reconnected = true;
+        self.load_calibration(key);
+        Ok(())
+    }
+
+    /// 连接到新设备时读取该设备保存的校准
+    fn load_calibration(&mut self, key: String) {
+        if self.calibration_key.as_ref() == Some(&key) {
+            return;
         }
-        if data[2] == 1 {
-            self.left_btns |= GameBtn::Btn3 as u8
+        if self.config.reset_calibration {
+            println!("Ongeki IO HID: 忽略已保存的摇杆校准 {key}");
+        } else if let Some(range) = self.calibration.get(&key) {
+            println!(
+                "Ongeki IO HID: 读取摇杆校准 {key} {} {}",
+                range.lever_left, range.lever_right
+            );
+            self.range = range;
         }
-        if data[3] == 1 {
-            self.left_btns |= GameBtn::Side as u8
+        self.calibration_key = Some(key);
+    }
+
+    /// 设备断开时松开按键，并立即保存还没有写入的校准
+    fn disconnect(&mut self) {
+        self.device = None;
+        self.left_btns = 0;
+        self.right_btns = 0;
+        if self.calibration_dirty {
+            self.save_calibration();
         }
-        if data[4] == 1 {
-            self.left_btns |= GameBtn::Menu as u8
+    }
+
+    fn save_calibration(&mut self) {
+        let Some(ref key) = self.calibration_key else {
+            return;
+        };
+        self.calibration.set(key, self.range);
+        if let Err(e) = self.calibration.save() {
+            println!("Ongeki IO HID: 保存摇杆校准失败 {e}");
         }
+        self.calibration_dirty = false;
+        self.calibration_saved = Instant::now();
+    }
 
-        if data[5] == 1 {
-            self.right_btns |= GameBtn::Btn1 as u8
+    /// 同时按住左右 Menu 键一段时间后以当前位置重新开始校准
+    fn check_calibration_reset(&mut self, lever_meta: i32, now: Instant) {
+        let menu = GameBtn::Menu as u8;
+        if self.left_btns & menu == 0 || self.right_btns & menu == 0 {
+            self.reset_held = None;
+            self.reset_latched = false;
+            return;
         }
-        if data[6] == 1 {
-            self.right_btns |= GameBtn::Btn2 as u8
+        if self.reset_latched {
+            return;
         }
-        if data[7] == 1 {
-            self.right_btns |= GameBtn::Btn3 as u8
+        let held = *self.reset_held.get_or_insert(now);
+        if now - held >= CALIBRATION_RESET_HOLD {
+            println!("Ongeki IO HID: 摇杆校准已重置，请左右推动摇杆");
+            self.range.reset(lever_meta);
+            self.calibration_dirty = true;
+            self.reset_held = None;
+            self.reset_latched = true;
         }
-        if data[8] == 1 {
-            self.right_btns |= GameBtn::Side as u8
+    }
+}
+
+impl PollDriver for HidIO {
+    fn poll(&mut self) -> Result<(), DriverError> {
+        let now = Instant::now();
+        let result = self.read_input(now);
+        // 没有新报告时也按时保存校准
+        if self.calibration_dirty
+            && now.saturating_duration_since(self.calibration_saved) >= CALIBRATION_SAVE_INTERVAL
+        {
+            self.save_calibration();
         }
-        if data[9] == 1 {
-            self.right_btns |= GameBtn::Menu as u8
+        Ok(result?)
+    }
+}
+
+impl HidIO {
+    fn read_input(&mut self, now: Instant) -> Result<(), HidError> {
+        let Some(ref mut device) = self.device else {
+            return self.reconnect(now);
+        };
+
+        // 读空缓冲区中积压的报告，只处理最新一份
+        let mut report = [0u8; 64];
+        let mut latest = None;
+        for _ in 0..MAX_REPORTS_PER_POLL {
+            match device.read(&mut report) {
+                Ok(0) => break,
+                Ok(_) => latest = Some(report),
+                Err(e) => {
+                    self.disconnect();
+                    return Err(HidError::Disconnected(e));
+                }
+            }
         }
+        // 没有新报告时保持上一次的状态
+        let Some(data) = latest else {
+            return Ok(());
+        };
+
+        self.left_btns = side_btns(&self.config.layout.left, &data);
+        self.right_btns = side_btns(&self.config.layout.right, &data);
 
-        // self.lever = -20 * i16::from_be_bytes([data[10], data[11]]);
         // Auto Calculation
-        let lever_meta = i16::from_be_bytes([data[10], data[11]]);
-        if self.config.lever_left > self.config.lever_right {
-            if lever_meta > self.config.lever_left {
-                self.config.lever_left = lever_meta;
-            }
-            if lever_meta < self.config.lever_right {
-                self.config.lever_right = lever_meta;
-            }
-        } else {
-            if lever_meta < self.config.lever_left {
-                self.config.lever_left = lever_meta;
-            }
-            if lever_meta > self.config.lever_right {
-                self.config.lever_right = lever_meta;
-            }
+        let Some(lever_meta) = read_lever(&self.config.layout.lever, &data) else {
+            return Ok(());
+        };
+        if self.range.widen(lever_meta) {
+            self.calibration_dirty = true;
         }
+        self.check_calibration_reset(lever_meta, now);
 
         // 映射前动态交换左右边界，确保方向正确
-        let (in_min, in_max) = if self.config.lever_left < self.config.lever_right {
-            (self.config.lever_left, self.config.lever_right)
+        let (in_min, in_max) = if self.range.lever_left < self.range.lever_right {
+            (self.range.lever_left, self.range.lever_right)
         } else {
             // 如果校准值方向颠倒，交换它们
-            (self.config.lever_right, self.config.lever_left)
+            (self.range.lever_right, self.range.lever_left)
         };
 
-        if self.config.lever_right != self.config.lever_left {
-            self.lever = map(
-                i32::from(lever_meta),
-                i32::from(in_min),
-                i32::from(in_max),
-                -32768,
-                32768,
-            ) as i16;
+        if self.range.lever_right != self.range.lever_left {
+            self.lever = map(lever_meta, in_min, in_max, -32768, 32768)
+                .clamp(i32::from(i16::MIN), i32::from(i16::MAX)) as i16;
         }
 
-        HResult::Ok
+        Ok(())
+    }
+}
+
+fn side_btns(layout: &HidButtonsLayout, data: &[u8]) -> u8 {
+    let mut btns = 0;
+    if is_pressed(&layout.btn1, data) {
+        btns |= GameBtn::Btn1 as u8
+    }
+    if is_pressed(&layout.btn2, data) {
+        btns |= GameBtn::Btn2 as u8
+    }
+    if is_pressed(&layout.btn3, data) {
+        btns |= GameBtn::Btn3 as u8
+    }
+    if is_pressed(&layout.side, data) {
+        btns |= GameBtn::Side as u8
+    }
+    if is_pressed(&layout.menu, data) {
+        btns |= GameBtn::Menu as u8
     }
+    btns
+}
+
+fn is_pressed(btn: &HidButton, data: &[u8]) -> bool {
+    data.get(btn.offset)
+        .is_some_and(|b| (b & btn.mask != 0) != btn.invert)
+}
+
+/// 按布局读取摇杆原始值，超出报告长度时返回 `None`
+fn read_lever(layout: &HidLeverLayout, data: &[u8]) -> Option<i32> {
+    let width = usize::from(layout.width);
+    let bytes = data.get(layout.offset..layout.offset + width)?;
+    let mut buf = Cursor::new(bytes);
+    let value = match (width, layout.endian, layout.signed) {
+        (1, _, true) => i32::from(buf.read_i8().ok()?),
+        (1, _, false) => i32::from(buf.read_u8().ok()?),
+        (2, Endian::Big, true) => i32::from(buf.read_i16::<BigEndian>().ok()?),
+        (2, Endian::Big, false) => i32::from(buf.read_u16::<BigEndian>().ok()?),
+        (2, Endian::Little, true) => i32::from(buf.read_i16::<LittleEndian>().ok()?),
+        (2, Endian::Little, false) => i32::from(buf.read_u16::<LittleEndian>().ok()?),
+        (4, Endian::Big, _) => buf.read_i32::<BigEndian>().ok()?,
+        (4, Endian::Little, _) => buf.read_i32::<LittleEndian>().ok()?,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// 组装 LED 输出报告：报告 ID、固定头、颜色数据，超出报告长度的部分被截断
+fn led_report(layout: &HidLedLayout, colors: &[u8]) -> [u8; 65] {
+    let mut buf = Cursor::new([0u8; 65]);
+    let _ = buf
+        .write_u8(layout.report_id)
+        .and_then(|_| buf.write_all(&layout.header))
+        .and_then(|_| buf.write_all(colors));
+    buf.into_inner()
+}
+
+/// 一个 LED 报告能容纳的 LED 数
+fn strip_capacity(layout: &HidLedLayout) -> usize {
+    64usize.saturating_sub(layout.header.len()) / 3
+}
+
+/// 分块报告中固定头之后的字节：序号、起始 LED（小端 2 字节）、LED 数、标志
+const CHUNK_META_LEN: usize = 5;
+/// 一帧的最后一块，固件收到后显示这一帧
+const CHUNK_FLAG_END: u8 = 0x01;
+
+/// 把 board 0 拆成多个报告，只包含与 `last` 相比有变化的块，没有变化时为空
+fn chunk_reports(
+    layout: &HidLedLayout,
+    sequence: u8,
+    strip: &[rgb::RGB8],
+    last: Option<&[rgb::RGB8]>,
+) -> Vec<[u8; 65]> {
+    let per_chunk = (64usize.saturating_sub(layout.header.len() + CHUNK_META_LEN) / 3).max(1);
+    let changed: Vec<(usize, &[rgb::RGB8])> = strip
+        .chunks(per_chunk)
+        .enumerate()
+        .map(|(i, chunk)| (i * per_chunk, chunk))
+        .filter(|(offset, chunk)| {
+            last.is_none_or(|last| last.get(*offset..offset + chunk.len()) != Some(*chunk))
+        })
+        .collect();
+    let count = changed.len();
+    changed
+        .into_iter()
+        .enumerate()
+        .map(|(i, (offset, chunk))| {
+            let flags = if i + 1 == count { CHUNK_FLAG_END } else { 0 };
+            let mut data = vec![sequence];
+            data.extend((offset as u16).to_le_bytes());
+            data.extend([chunk.len() as u8, flags]);
+            data.extend(chunk.iter().flat_map(|c| [c.r, c.g, c.b]));
+            led_report(layout, &data)
+        })
+        .collect()
 }
 
 pub(crate) fn map(x: i32, in_min: i32, in_max: i32, out_min: i32, out_max: i32) -> i32 {
     // 自动处理反向输入（例如 in_min > in_max）
-    let numerator = (x - in_min) * (out_max - out_min);
-    let denominator = in_max - in_min;
+    // 用 i64 计算，避免 32 位摇杆值相乘溢出
+    let numerator = (i64::from(x) - i64::from(in_min)) * (i64::from(out_max) - i64::from(out_min));
+    let denominator = i64::from(in_max) - i64::from(in_min);
     if denominator == 0 {
         return out_min; // 避免除以零
     }
-    numerator / denominator + out_min
+    (numerator / denominator + i64::from(out_min)).clamp(i64::from(i32::MIN), i64::from(i32::MAX))
+        as i32
+}
+
+impl ReconnectDriver for HidIO {
+    fn take_reconnected(&mut self) -> bool {
+        std::mem::take(&mut self.reconnected)
+    }
+}
+
+impl ShutdownDriver for HidIO {
+    fn shutdown(&mut self) {
+        if self.calibration_dirty {
+            self.save_calibration();
+        }
+    }
 }
 
 impl LeverDriver for HidIO {
@@ -175,74 +461,68 @@ impl ButtonDriver for HidIO {
     }
 }
 
-impl LEDriver for HidIO {
-    fn set_led(&mut self, data: u32) {
-        let Some(ref device) = self.device else {
-            self.try_connect_device();
+impl HidIO {
+    fn write_led(&mut self, colors: &[u8]) {
+        let report = led_report(&self.config.layout.led, colors);
+        self.write_report(&report);
+    }
+
+    /// 以分块报告发送 board 0，全部写入成功后才记为已发送
+    fn write_board0(&mut self, rgb: &[rgb::RGB8]) {
+        let strip = self.led_layout.apply(0, rgb).into_owned();
+        // 断开时尝试重新连接并发送完整的一帧
+        let last = self.device.as_ref().and(self.last_strip.as_deref());
+        let reports = chunk_reports(&self.config.layout.chunk, self.strip_sequence, &strip, last);
+        if reports.is_empty() {
             return;
+        }
+        self.strip_sequence = self.strip_sequence.wrapping_add(1);
+        let written = reports.iter().all(|report| self.write_report(report));
+        self.last_strip = written.then_some(strip);
+    }
+
+    /// 返回报告是否写入
+    fn write_report(&mut self, report: &[u8]) -> bool {
+        let Some(ref mut device) = self.device else {
+            // 连接失败由 poll 报告
+            let _ = self.reconnect(Instant::now());
+            return false;
         };
 
-        let mut buf = Cursor::new([0u8; 65]);
-        buf.set_position(1);
-        buf.write_u8(0).unwrap();
-        buf.write_u8(100).unwrap();
-        buf.write_all(&[
-            (((data >> 23) & 1) * 255) as u8,
-            (((data >> 19) & 1) * 255) as u8,
-            (((data >> 22) & 1) * 255) as u8,
-            (((data >> 20) & 1) * 255) as u8,
-            (((data >> 21) & 1) * 255) as u8,
-            (((data >> 18) & 1) * 255) as u8,
-            (((data >> 17) & 1) * 255) as u8,
-            (((data >> 16) & 1) * 255) as u8,
-            (((data >> 15) & 1) * 255) as u8,
-            (((data >> 14) & 1) * 255) as u8,
-            (((data >> 13) & 1) * 255) as u8,
-            (((data >> 12) & 1) * 255) as u8,
-            (((data >> 11) & 1) * 255) as u8,
-            (((data >> 10) & 1) * 255) as u8,
-            (((data >> 9) & 1) * 255) as u8,
-            (((data >> 8) & 1) * 255) as u8,
-            (((data >> 7) & 1) * 255) as u8,
-            (((data >> 6) & 1) * 255) as u8,
-        ])
-        .unwrap();
-
-        if let Err(e) = device.write(buf.get_ref()) {
+        if let Err(e) = device.write(report) {
             println!("Ongeki IO HID: 设备断开 {e}");
-            self.device = None;
+            self.disconnect();
+            return false;
         }
+        true
+    }
+}
+
+impl LEDriver for HidIO {
+    fn set_led(&mut self, data: u32) {
+        let colors = LegacyLedState(data).colors(&Default::default());
+        let colors: Vec<u8> = colors.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+        self.write_led(&colors);
     }
 }
 
 
 impl LEDriverNew for HidIO {
     fn set_led_new(&mut self, board: u8, rgb: &[rgb::RGB8]) {
-        if board == 1 {
-            let Some(ref device) = self.device else {
-                self.try_connect_device();
-                return;
-            };
-    
-            let mut buf = Cursor::new([0u8; 65]);
-            buf.set_position(1);
-            buf.write_u8(0).unwrap();
-            buf.write_u8(100).unwrap();
-            buf.write_all(
-                &[
-                    rgb[0].r, rgb[0].g, rgb[0].b,
-                    rgb[1].r, rgb[1].g, rgb[1].b,
-                    rgb[2].r, rgb[2].g, rgb[2].b,
-                    rgb[3].r, rgb[3].g, rgb[3].b,
-                    rgb[4].r, rgb[4].g, rgb[4].b,
-                    rgb[5].r, rgb[5].g, rgb[5].b
-                ],
-            ).unwrap();
-
-            if let Err(e) = device.write(buf.get_ref()) {
-                println!("Ongeki IO HID: 设备断开 {e}");
-                self.device = None;
+        match board {
+            0 if self.config.board0_chunks => self.write_board0(rgb),
+            // 不支持分块报告时只在配置了映射时输出
+            0 if !self.led_layout.is_empty() => {
+                let strip = self.led_layout.render(rgb);
+                let colors: Vec<u8> = strip.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+                let report = led_report(&self.config.layout.strip, &colors);
+                self.write_report(&report);
             }
+            1 => {
+                let colors: Vec<u8> = rgb.iter().take(6).flat_map(|c| [c.r, c.g, c.b]).collect();
+                self.write_led(&colors);
+            }
+            _ => {}
         }
     }
 }
@@ -251,10 +531,378 @@ impl LEDriverNew for HidIO {
 /// test
 #[cfg(test)]
 mod hid_test {
-    use super::map;
+    use super::mock::MockHid;
+    use super::*;
+    use crate::config::{Config, HidLayout};
+    use crate::led_layout::{LedRoute, LedZone};
+
+    /// 默认布局的输入报告，`btns` 为字节 0-9
+    fn report(btns: [u8; 10], lever: i16) -> Vec<u8> {
+        let mut data = vec![0u8; 64];
+        data[..10].copy_from_slice(&btns);
+        data[10..12].copy_from_slice(&lever.to_be_bytes());
+        data
+    }
+
+    fn mock_hid(config: HIDConfig, calibration: CalibrationStore) -> (HidIO, MockHid) {
+        let mock = MockHid::default();
+        mock.plug(MockHid::device(&config, "SN1"));
+        let (io, _) = HidIO::with_transport(config, Box::new(mock.clone()), calibration);
+        (io, mock)
+    }
+
+    #[test]
+    fn poll_test() {
+        let (mut io, mock) = mock_hid(Config::default().hid, CalibrationStore::default());
+        assert_eq!(mock.opens(), 1);
+
+        mock.push_report(&report([1, 0, 0, 0, 1, 0, 0, 0, 0, 1], i16::MAX));
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), GameBtn::Btn1 as u8 | GameBtn::Menu as u8);
+        assert_eq!(io.right_btns(), GameBtn::Menu as u8);
+        assert_eq!(io.lever(), i16::MAX);
+
+        // 没有新报告时保持状态，积压的报告只处理最后一份
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), GameBtn::Btn1 as u8 | GameBtn::Menu as u8);
+        mock.push_report(&report([0; 10], 0));
+        mock.push_report(&report([0, 1, 0, 0, 0, 0, 0, 0, 0, 0], i16::MIN));
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), GameBtn::Btn2 as u8);
+        assert_eq!(io.right_btns(), 0);
+        assert_eq!(io.lever(), i16::MIN);
+    }
+
+    #[test]
+    fn calibration_test() {
+        let config = HIDConfig {
+            lever_left: -100,
+            lever_right: 100,
+            ..Config::default().hid
+        };
+        let path = std::env::temp_dir().join(format!(
+            "ongeki-io-hid-calibration-{}.toml",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let (mut io, mock) = mock_hid(config.clone(), CalibrationStore::load(&path));
+
+        mock.push_report(&report([0; 10], 50));
+        io.poll().unwrap();
+        assert_eq!(io.lever(), 16384);
+
+        // 超出范围时扩大范围，退出时按设备保存
+        mock.push_report(&report([0; 10], 200));
+        io.poll().unwrap();
+        assert_eq!(io.lever(), i16::MAX);
+        mock.push_report(&report([0; 10], 50));
+        io.poll().unwrap();
+        assert_eq!(io.lever(), 0);
+        io.shutdown();
+
+        let store = CalibrationStore::load(&path);
+        let saved = store.get(&CalibrationStore::key(config.vid, config.pid, Some("SN1")));
+        assert_eq!(
+            saved,
+            Some(LeverCalibration {
+                lever_left: -100,
+                lever_right: 200,
+            })
+        );
+
+        // 同一设备再次连接时使用保存的范围
+        let (mut io, mock) = mock_hid(config, store);
+        mock.push_report(&report([0; 10], 50));
+        io.poll().unwrap();
+        assert_eq!(io.lever(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn calibration_save_test() {
+        let config = HIDConfig {
+            lever_left: -100,
+            lever_right: 100,
+            ..Config::default().hid
+        };
+        let path = std::env::temp_dir().join(format!(
+            "ongeki-io-hid-calibration-save-{}.toml",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let (mut io, mock) = mock_hid(config.clone(), CalibrationStore::load(&path));
+        let key = CalibrationStore::key(config.vid, config.pid, Some("SN1"));
+        let saved_right = || CalibrationStore::load(&path).get(&key).map(|c| c.lever_right);
+
+        mock.push_report(&report([0; 10], 200));
+        io.poll().unwrap();
+        assert_eq!(saved_right(), None);
+
+        // 没有新报告时也按时保存
+        io.calibration_saved = Instant::now()
+            .checked_sub(CALIBRATION_SAVE_INTERVAL)
+            .unwrap();
+        io.poll().unwrap();
+        assert_eq!(saved_right(), Some(200));
+
+        // 断开时立即保存
+        mock.push_report(&report([0; 10], 300));
+        io.poll().unwrap();
+        mock.unplug();
+        assert!(io.poll().is_err());
+        assert_eq!(saved_right(), Some(300));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn led_test() {
+        let (mut io, mock) = mock_hid(Config::default().hid, CalibrationStore::default());
+
+        io.set_led(1 << 23 | 1 << 6);
+        let mut colors = [0u8; 18];
+        colors[0] = 255;
+        colors[17] = 255;
+        assert_eq!(
+            mock.take_written(),
+            vec![led_report(&HidLayout::default().led, &colors).to_vec()]
+        );
+
+        let mut rgb = [rgb::RGB8::default(); 6];
+        rgb[0] = rgb::RGB8::new(1, 2, 3);
+        rgb[5] = rgb::RGB8::new(4, 5, 6);
+        io.set_led_new(0, &[rgb::RGB8::new(9, 9, 9); 61]);
+        io.set_led_new(1, &rgb);
+        let written = mock.take_written();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0][..6], [0, 0, 100, 1, 2, 3]);
+        assert_eq!(written[0][18..21], [4, 5, 6]);
+
+        // 配置映射后 board 0 输出映射后的灯带
+        let config = HIDConfig {
+            led_routes: vec![LedRoute {
+                zone: LedZone::RightSide,
+                offset: 1,
+                count: None,
+                reverse: true,
+            }],
+            ..Config::default().hid
+        };
+        let (mut io, mock) = mock_hid(config, CalibrationStore::default());
+        let mut board0 = [rgb::RGB8::default(); 61];
+        board0[60] = rgb::RGB8::new(7, 8, 9);
+        io.set_led_new(0, &board0);
+        let written = mock.take_written();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0][..9], [0, 0, 101, 0, 0, 0, 7, 8, 9]);
+    }
+
+    #[test]
+    fn calibration_reset_test() {
+        let (mut io, _mock) = mock_hid(Config::default().hid, CalibrationStore::default());
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+        io.left_btns = GameBtn::Menu as u8;
+        io.right_btns = GameBtn::Menu as u8;
+
+        io.check_calibration_reset(100, at(0));
+        io.check_calibration_reset(100, at(2));
+        assert_eq!(io.range.lever_left, i32::from(i16::MIN));
+        io.check_calibration_reset(100, at(3));
+        assert_eq!((io.range.lever_left, io.range.lever_right), (100, 101));
+
+        // 一直按住时只重置一次
+        io.range.widen(-500);
+        io.check_calibration_reset(100, at(6));
+        io.check_calibration_reset(100, at(9));
+        assert_eq!(io.range.lever_left, -500);
+
+        // 松开后可以再次重置
+        io.right_btns = 0;
+        io.check_calibration_reset(100, at(10));
+        io.right_btns = GameBtn::Menu as u8;
+        io.check_calibration_reset(100, at(10));
+        io.check_calibration_reset(100, at(13));
+        assert_eq!(io.range.lever_left, 100);
+    }
+
+    #[test]
+    fn chunk_test() {
+        let config = HIDConfig {
+            board0_chunks: true,
+            ..Config::default().hid
+        };
+        let (mut io, mock) = mock_hid(config, CalibrationStore::default());
+        let mut board0 = [rgb::RGB8::new(1, 1, 1); 61];
+
+        // 每块 19 个 LED，61 个 LED 分为 4 块，最后一块带结束标志
+        io.set_led_new(0, &board0);
+        let written = mock.take_written();
+        assert_eq!(written.len(), 4);
+        assert_eq!(written[0][..8], [0, 0, 102, 0, 0, 0, 19, 0]);
+        assert_eq!(written[3][..8], [0, 0, 102, 0, 57, 0, 4, CHUNK_FLAG_END]);
+        assert_eq!(written[3][8..20], [1; 12]);
+        assert!(written[3][20..].iter().all(|b| *b == 0));
+
+        // 只发送变化的块
+        io.set_led_new(0, &board0);
+        assert!(mock.take_written().is_empty());
+        board0[20] = rgb::RGB8::new(2, 3, 4);
+        io.set_led_new(0, &board0);
+        let written = mock.take_written();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0][..8], [0, 0, 102, 1, 19, 0, 19, CHUNK_FLAG_END]);
+        assert_eq!(written[0][11..14], [2, 3, 4]);
+
+        // 重新连接后发送完整的一帧
+        mock.unplug();
+        assert!(io.poll().is_err());
+        mock.plug(MockHid::device(&Config::default().hid, "SN1"));
+        io.last_connect = None;
+        io.set_led_new(0, &board0);
+        io.set_led_new(0, &board0);
+        let written = mock.take_written();
+        assert_eq!(written.len(), 4);
+        // 连接失败的一帧也占用序号
+        assert_eq!(written[0][3], 3);
+    }
+
+    #[test]
+    fn reconnect_test() {
+        let config = Config::default().hid;
+        let (mut io, mock) = mock_hid(config.clone(), CalibrationStore::default());
+        mock.push_report(&report([1; 10], 0));
+        io.poll().unwrap();
+        assert_ne!(io.left_btns(), 0);
+        assert!(io.take_reconnected());
+
+        // 拔出时松开按键并报告断开
+        mock.unplug();
+        let err = io.poll().unwrap_err();
+        assert!(matches!(err, DriverError::Hid(HidError::Disconnected(_))));
+        assert_eq!(err.hresult().0 as u32, 0x8007_048F);
+        assert_eq!((io.left_btns(), io.right_btns()), (0, 0));
+        assert!(matches!(
+            io.poll(),
+            Err(DriverError::Hid(HidError::NotConnected))
+        ));
+        io.set_led(0);
+        assert!(mock.take_written().is_empty());
+        assert_eq!(mock.opens(), 1);
+
+        // 重新插入后，过了重连间隔的下一次 poll 才重连
+        mock.plug(MockHid::device(&config, "SN1"));
+        let now = Instant::now();
+        io.last_connect = Some(now);
+        assert!(io.reconnect(now + RECONNECT_INTERVAL / 2).is_err());
+        assert_eq!(mock.opens(), 1);
+        io.last_connect = None;
+        io.poll().unwrap();
+        assert_eq!(mock.opens(), 2);
+        // 重新连接后通知一次重新发送 LED
+        assert!(io.take_reconnected());
+        assert!(!io.take_reconnected());
+        mock.push_report(&report([0, 0, 1, 0, 0, 0, 0, 0, 0, 0], 0));
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), GameBtn::Btn3 as u8);
+
+        // 写 LED 失败同样会断开
+        mock.unplug();
+        io.set_led(0);
+        mock.plug(MockHid::device(&config, "SN1"));
+        io.last_connect = None;
+        io.set_led(0);
+        assert_eq!(mock.opens(), 3);
+    }
+
     #[test]
     fn map_test() {
         let temp = map(12, -280, 280, -20000, 20000);
         assert_eq!(temp, 857);
+        // 4 字节摇杆的原始值
+        assert_eq!(map(0, i32::MIN, i32::MAX, -32768, 32768), 0);
+        assert_eq!(map(i32::MAX, i32::MIN, i32::MAX, -32768, 32768), 32768);
+    }
+
+    #[test]
+    fn default_layout_test() {
+        let layout = HidLayout::default();
+        let mut data = [0u8; 64];
+        data[0] = 1;
+        data[4] = 1;
+        data[8] = 1;
+        data[10..12].copy_from_slice(&(-1234i16).to_be_bytes());
+
+        assert_eq!(
+            side_btns(&layout.left, &data),
+            GameBtn::Btn1 as u8 | GameBtn::Menu as u8
+        );
+        assert_eq!(side_btns(&layout.right, &data), GameBtn::Side as u8);
+        assert_eq!(read_lever(&layout.lever, &data), Some(-1234));
+    }
+
+    #[test]
+    fn button_mask_test() {
+        let mut layout = HidLayout::default();
+        layout.left.btn2 = HidButton {
+            offset: 20,
+            mask: 0x04,
+            invert: false,
+        };
+        layout.left.btn3 = HidButton {
+            offset: 20,
+            mask: 0x08,
+            invert: true,
+        };
+        layout.right.menu = HidButton {
+            offset: 100,
+            mask: 0xFF,
+            invert: false,
+        };
+
+        let mut data = [0u8; 64];
+        data[20] = 0b0000_1100;
+        assert_eq!(side_btns(&layout.left, &data), GameBtn::Btn2 as u8);
+        data[20] = 0;
+        assert_eq!(side_btns(&layout.left, &data), GameBtn::Btn3 as u8);
+        assert_eq!(side_btns(&layout.right, &data), 0);
+    }
+
+    #[test]
+    fn lever_layout_test() {
+        let data = [0x12, 0x34, 0xFF, 0xFE, 0x80];
+        let read = |offset, width, endian, signed| {
+            let layout = HidLeverLayout {
+                offset,
+                width,
+                endian,
+                signed,
+            };
+            read_lever(&layout, &data)
+        };
+
+        assert_eq!(read(0, 2, Endian::Little, false), Some(0x3412));
+        assert_eq!(read(2, 2, Endian::Big, false), Some(0xFFFE));
+        assert_eq!(read(2, 2, Endian::Little, true), Some(-257));
+        assert_eq!(read(4, 1, Endian::Big, true), Some(-128));
+        assert_eq!(read(4, 1, Endian::Big, false), Some(128));
+        assert_eq!(read(0, 4, Endian::Big, true), Some(0x1234FFFE));
+        assert_eq!(read(3, 4, Endian::Big, true), None);
+        assert_eq!(read(0, 3, Endian::Big, true), None);
+    }
+
+    #[test]
+    fn led_report_test() {
+        let report = led_report(&HidLayout::default().led, &[1, 2, 3]);
+        assert_eq!(report[..6], [0, 0, 100, 1, 2, 3]);
+
+        let layout = HidLedLayout {
+            report_id: 2,
+            header: vec![0xAA; 70],
+        };
+        let report = led_report(&layout, &[1, 2, 3]);
+        assert_eq!(report[0], 2);
+        assert!(report[1..].iter().all(|b| *b == 0xAA));
+        assert_eq!(strip_capacity(&layout), 0);
+        assert_eq!(strip_capacity(&HidLayout::default().strip), 20);
     }
 }
\ No newline at end of file
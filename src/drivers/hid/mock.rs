@@ -0,0 +1,118 @@
+//! 测试用 HID 设备：按顺序回放输入报告，记录输出报告，可以模拟断开
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use hidapi::{HidError as ApiError, HidResult};
+
+use super::transport::{HidConnection, HidDeviceInfo, HidTransport};
+use super::HidError;
+use crate::config::HIDConfig;
+
+#[derive(Default)]
+struct MockState {
+    device: Option<HidDeviceInfo>,
+    /// 每次拔出后加一，旧连接随之失效
+    generation: u32,
+    reports: VecDeque<Vec<u8>>,
+    written: Vec<Vec<u8>>,
+    opens: usize,
+}
+
+/// 克隆后共享同一台设备
+#[derive(Clone, Default)]
+pub struct MockHid {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockHid {
+    /// 与配置匹配的设备
+    pub fn device(config: &HIDConfig, serial: &str) -> HidDeviceInfo {
+        HidDeviceInfo {
+            vid: config.vid,
+            pid: config.pid,
+            interface: config.interface,
+            serial: Some(serial.to_string()),
+            product: "Mock".to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn plug(&self, info: HidDeviceInfo) {
+        self.state.lock().unwrap().device = Some(info);
+    }
+
+    pub fn unplug(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.device = None;
+        state.generation += 1;
+        state.reports.clear();
+    }
+
+    pub fn push_report(&self, report: &[u8]) {
+        self.state
+            .lock()
+            .unwrap()
+            .reports
+            .push_back(report.to_vec());
+    }
+
+    pub fn take_written(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.state.lock().unwrap().written)
+    }
+
+    pub fn opens(&self) -> usize {
+        self.state.lock().unwrap().opens
+    }
+}
+
+impl HidTransport for MockHid {
+    fn enumerate(&mut self) -> Result<Vec<HidDeviceInfo>, HidError> {
+        Ok(self.state.lock().unwrap().device.iter().cloned().collect())
+    }
+
+    fn open(&mut self, _info: &HidDeviceInfo) -> Result<Box<dyn HidConnection>, HidError> {
+        let mut state = self.state.lock().unwrap();
+        state.opens += 1;
+        Ok(Box::new(MockConnection {
+            generation: state.generation,
+            state: self.state.clone(),
+        }))
+    }
+}
+
+struct MockConnection {
+    generation: u32,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockConnection {
+    fn check(&self, state: &MockState) -> HidResult<()> {
+        if state.generation != self.generation {
+            return Err(ApiError::HidApiError {
+                message: "device unplugged".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl HidConnection for MockConnection {
+    fn read(&mut self, buf: &mut [u8]) -> HidResult<usize> {
+        let mut state = self.state.lock().unwrap();
+        self.check(&state)?;
+        let Some(report) = state.reports.pop_front() else {
+            return Ok(0);
+        };
+        let len = report.len().min(buf.len());
+        buf[..len].copy_from_slice(&report[..len]);
+        Ok(len)
+    }
+
+    fn write(&mut self, data: &[u8]) -> HidResult<usize> {
+        let mut state = self.state.lock().unwrap();
+        self.check(&state)?;
+        state.written.push(data.to_vec());
+        Ok(data.len())
+    }
+}
@@ -0,0 +1,82 @@
+//! HID 传输层，`HidIO` 只通过这里访问设备
+
+use std::ffi::CString;
+
+use hidapi::{HidApi, HidDevice, HidResult};
+
+use super::HidError;
+
+#[derive(Debug, Clone, Default)]
+pub struct HidDeviceInfo {
+    pub vid: u16,
+    pub pid: u16,
+    pub interface: i32,
+    pub serial: Option<String>,
+    pub product: String,
+    pub path: CString,
+}
+
+pub trait HidTransport: Send {
+    fn enumerate(&mut self) -> Result<Vec<HidDeviceInfo>, HidError>;
+    /// 打开设备，读取为非阻塞模式
+    fn open(&mut self, info: &HidDeviceInfo) -> Result<Box<dyn HidConnection>, HidError>;
+}
+
+pub trait HidConnection: Send {
+    /// 没有新报告时返回 0
+    fn read(&mut self, buf: &mut [u8]) -> HidResult<usize>;
+    fn write(&mut self, data: &[u8]) -> HidResult<usize>;
+}
+
+/// 基于 hidapi 的实现
+#[derive(Default)]
+pub struct HidApiTransport {
+    api: Option<HidApi>,
+}
+
+impl HidTransport for HidApiTransport {
+    fn enumerate(&mut self) -> Result<Vec<HidDeviceInfo>, HidError> {
+        let api = match self.api {
+            Some(ref mut api) => {
+                api.refresh_devices().map_err(HidError::Api)?;
+                api
+            }
+            None => self.api.insert(HidApi::new().map_err(HidError::Api)?),
+        };
+        Ok(api
+            .device_list()
+            .map(|d| HidDeviceInfo {
+                vid: d.vendor_id(),
+                pid: d.product_id(),
+                interface: d.interface_number(),
+                serial: d.serial_number().map(str::to_string),
+                product: d.product_string().unwrap_or_default().to_string(),
+                path: d.path().to_owned(),
+            })
+            .collect())
+    }
+
+    fn open(&mut self, info: &HidDeviceInfo) -> Result<Box<dyn HidConnection>, HidError> {
+        let api = match self.api {
+            Some(ref api) => api,
+            None => self.api.insert(HidApi::new().map_err(HidError::Api)?),
+        };
+        let device = api
+            .open_path(&info.path)
+            .and_then(|d| d.set_blocking_mode(false).map(|_| d))
+            .map_err(HidError::Api)?;
+        Ok(Box::new(HidApiConnection(device)))
+    }
+}
+
+struct HidApiConnection(HidDevice);
+
+impl HidConnection for HidApiConnection {
+    fn read(&mut self, buf: &mut [u8]) -> HidResult<usize> {
+        self.0.read(buf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> HidResult<usize> {
+        self.0.write(data)
+    }
+}
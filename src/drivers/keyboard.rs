@@ -1,25 +1,33 @@
-use crate::{config::KeyBoardConfig, enums::{GameBtn, HResult, OpBtn}};
-use super::{ButtonDriver, Driver, PollDriver};
+use crate::{
+    config::{KeyBoardConfig, KeyBoardSideMapping},
+    enums::{GameBtn, OpBtn},
+    keys::KeyBinding,
+    platform::{self, KeySource},
+};
+use super::{ButtonDriver, Driver, DriverError, PollDriver};
 
 use dyn_dyn::dyn_dyn_impl;
-use windows::Win32::UI::Input::KeyboardAndMouse;
 
-
-#[derive(Debug)]
 pub struct KeyBoardIO {
     op_btns: u8,
     left_btns: u8,
     right_btns: u8,
-    config: KeyBoardConfig
+    config: KeyBoardConfig,
+    keys: Box<dyn KeySource>,
 }
 
 impl KeyBoardIO {
     pub fn new(config: KeyBoardConfig) -> Self {
+        Self::with_source(config, Box::new(platform::Keys))
+    }
+
+    pub fn with_source(config: KeyBoardConfig, keys: Box<dyn KeySource>) -> Self {
         Self {
             op_btns: 0,
             left_btns: 0,
             right_btns: 0,
             config,
+            keys,
         }
     }
 }
@@ -28,55 +36,45 @@ impl KeyBoardIO {
 impl Driver for KeyBoardIO {}
 
 impl PollDriver for KeyBoardIO {
-    fn poll(&mut self) -> HResult {
+    fn poll(&mut self) -> Result<(), DriverError> {
         self.op_btns = 0;
 
-        if is_key_pressed(self.config.test) {
+        let keys = self.keys.as_ref();
+        if is_bound_pressed(keys, &self.config.test) {
             self.op_btns |= OpBtn::Test as u8
         }
-        if is_key_pressed(self.config.service) {
+        if is_bound_pressed(keys, &self.config.service) {
             self.op_btns |= OpBtn::Service as u8
         }
-        if is_key_pressed(self.config.coin) {
+        if is_bound_pressed(keys, &self.config.coin) {
             self.op_btns |= OpBtn::Coin as u8
         }
 
-        self.left_btns = 0;
-        self.right_btns = 0;
+        self.left_btns = side_btns(keys, &self.config.left);
+        self.right_btns = side_btns(keys, &self.config.right);
 
-        if is_key_pressed(0x41) {
-            self.left_btns |= GameBtn::Btn1 as u8
-        }
-        if is_key_pressed(0x53) {
-            self.left_btns |= GameBtn::Btn2 as u8
-        }
-        if is_key_pressed(0x44) {
-            self.left_btns |= GameBtn::Btn3 as u8
-        }
-        if is_key_pressed(0x4A) {
-            self.right_btns |= GameBtn::Btn1 as u8
-        }
-        if is_key_pressed(0x4B) {
-            self.right_btns |= GameBtn::Btn2 as u8
-        }
-        if is_key_pressed(0x4C) {
-            self.right_btns |= GameBtn::Btn3 as u8
-        }
-        if is_key_pressed(0x55) {
-            self.left_btns |= GameBtn::Menu as u8
-        }
-        if is_key_pressed(0x4F) {
-            self.right_btns |= GameBtn::Menu as u8
-        }
-        if is_key_pressed(0x01) {
-            self.left_btns |= GameBtn::Side as u8
-        }
-        if is_key_pressed(0x02) {
-            self.right_btns |= GameBtn::Side as u8
-        }
+        Ok(())
+    }
+}
 
-        HResult::Ok
+fn side_btns(keys: &dyn KeySource, mapping: &KeyBoardSideMapping) -> u8 {
+    let mut btns = 0;
+    if is_bound_pressed(keys, &mapping.btn1) {
+        btns |= GameBtn::Btn1 as u8
+    }
+    if is_bound_pressed(keys, &mapping.btn2) {
+        btns |= GameBtn::Btn2 as u8
+    }
+    if is_bound_pressed(keys, &mapping.btn3) {
+        btns |= GameBtn::Btn3 as u8
+    }
+    if is_bound_pressed(keys, &mapping.side) {
+        btns |= GameBtn::Side as u8
     }
+    if is_bound_pressed(keys, &mapping.menu) {
+        btns |= GameBtn::Menu as u8
+    }
+    btns
 }
 
 impl ButtonDriver for KeyBoardIO {
@@ -93,7 +91,42 @@ impl ButtonDriver for KeyBoardIO {
     }
 }
 
+fn is_bound_pressed(keys: &dyn KeySource, binding: &KeyBinding) -> bool {
+    binding.iter().any(|key| keys.is_pressed(key.0))
+}
+
+#[cfg(test)]
+mod keyboard_test {
+    use super::*;
+    use crate::config::Config;
+    use crate::keys::VirtualKey;
+    use crate::platform::fake::FakeKeys;
 
-fn is_key_pressed(key: i32) -> bool {
-    unsafe { KeyboardAndMouse::GetAsyncKeyState(key) != 0 }
+    #[test]
+    fn mapping_test() {
+        let keys = FakeKeys::default();
+        let mut config = Config::default().keyboard;
+        config.left.btn1 = KeyBinding(vec![VirtualKey(0x41), VirtualKey(0x20)]);
+        let mut io = KeyBoardIO::with_source(config, Box::new(keys.clone()));
+
+        io.poll().unwrap();
+        assert_eq!((io.op_btns(), io.left_btns(), io.right_btns()), (0, 0, 0));
+
+        // 默认: 1 = Test, D = 左 Btn3, MOUSE2 = 右 Side, O = 右 Menu
+        for vk in [0x31, 0x44, 0x02, 0x4F] {
+            keys.press(vk);
+        }
+        io.poll().unwrap();
+        assert_eq!(io.op_btns(), OpBtn::Test as u8);
+        assert_eq!(io.left_btns(), GameBtn::Btn3 as u8);
+        assert_eq!(io.right_btns(), GameBtn::Side as u8 | GameBtn::Menu as u8);
+
+        // 同一按键绑定的任意一个键都可以触发
+        for vk in [0x31, 0x44, 0x02, 0x4F] {
+            keys.release(vk);
+        }
+        keys.press(0x20);
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), GameBtn::Btn1 as u8);
+    }
 }
@@ -0,0 +1,54 @@
+use dyn_dyn::dyn_dyn_impl;
+
+use crate::led_recording::{LedCall, LedRecordWriter};
+
+use super::{Driver, LEDriver, LEDriverNew, ShutdownDriver};
+
+/// 把每次 LED 调用写入录制文件，可用 `led-preview` 查看
+pub struct LedRecordIO {
+    writer: Option<LedRecordWriter>,
+}
+
+#[dyn_dyn_impl(Driver, LEDriver, LEDriverNew, ShutdownDriver)]
+impl Driver for LedRecordIO {}
+
+impl LedRecordIO {
+    pub fn new(writer: LedRecordWriter) -> Self {
+        Self {
+            writer: Some(writer),
+        }
+    }
+
+    fn record(&mut self, call: LedCall) {
+        let Some(ref mut writer) = self.writer else {
+            return;
+        };
+        // 录制失败不影响游戏，停止录制即可
+        if let Err(e) = writer.record(&call) {
+            println!("Ongeki IO: 录制 LED 失败，已停止录制 {e}");
+            self.writer = None;
+        }
+    }
+}
+
+impl LEDriver for LedRecordIO {
+    fn set_led(&mut self, data: u32) {
+        self.record(LedCall::Legacy(data));
+    }
+}
+
+impl LEDriverNew for LedRecordIO {
+    fn set_led_new(&mut self, board: u8, rgb: &[rgb::RGB8]) {
+        self.record(LedCall::Colors(board, rgb.to_vec()));
+    }
+}
+
+impl ShutdownDriver for LedRecordIO {
+    fn shutdown(&mut self) {
+        if let Some(ref mut writer) = self.writer {
+            if let Err(e) = writer.flush() {
+                println!("Ongeki IO: 保存 LED 录制文件失败 {e}");
+            }
+        }
+    }
+}
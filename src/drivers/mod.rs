@@ -1,28 +1,157 @@
 use dyn_dyn::{dyn_dyn_base, dyn_dyn_cast};
+use std::env;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{self, Write};
 use std::ops::{Deref, DerefMut};
+use std::time::Instant;
 
-use crate::config::Config;
+use crate::config::{
+    Config, LedOutputConfig, LegacyLedConfig, LeverFilterConfig, PollConfig, SharedRole,
+};
 use crate::drivers::hid::HidIO;
 use crate::enums::HResult;
+use crate::filter::LeverFilter;
+use crate::ipc::{self, Channel, InputSnapshot, LedFrame};
+use crate::led_color::{ChannelOrder, ColorProcessor};
+use crate::led_effects::LedEffects;
+use crate::led_feedback::ButtonFeedback;
+use crate::led_legacy::LegacyLedState;
+use crate::led_output::LedQueue;
+use crate::led_recording::{LedCall, LedRecordWriter, BOARD1_LEDS};
+use crate::recording::{InputRecorder, Recording};
 
 #[dyn_dyn_base]
 trait Driver: Sync + Send {}
 
 
 
+mod dmx;
+#[cfg(test)]
+pub mod fake;
+mod gamepad;
 pub mod hid;
 mod keyboard;
 mod led_debug;
+mod led_record;
 mod mouse;
+mod network;
+mod openrgb;
+mod replay;
+mod serial;
+mod shared;
 
+use self::dmx::{DmxError, DmxIO};
+use self::gamepad::{GamepadError, GamepadIO};
+use self::hid::HidError;
 use self::keyboard::KeyBoardIO;
 use self::led_debug::LEDebug;
-use self::mouse::MouseIO;
+use self::led_record::LedRecordIO;
+use self::mouse::{MouseError, MouseIO};
+use self::network::{NetworkError, NetworkIO};
+use self::openrgb::OpenRgbIO;
+use self::replay::ReplayIO;
+use self::serial::{SerialError, SerialIO};
+use self::shared::{SharedError, SharedIO, SharedOwner};
+
+/// 各驱动 poll 失败的原因，转换为 HRESULT 返回给游戏
+#[derive(Debug)]
+enum DriverError {
+    Hid(HidError),
+    Dmx(DmxError),
+    Mouse(MouseError),
+    Gamepad(GamepadError),
+    Network(NetworkError),
+    Serial(SerialError),
+    Shared(SharedError),
+    #[cfg(test)]
+    Test(HResult),
+}
+
+impl DriverError {
+    fn hresult(&self) -> HResult {
+        match self {
+            DriverError::Hid(e) => e.hresult(),
+            DriverError::Dmx(e) => e.hresult(),
+            DriverError::Mouse(e) => e.hresult(),
+            DriverError::Gamepad(e) => e.hresult(),
+            DriverError::Network(e) => e.hresult(),
+            DriverError::Serial(e) => e.hresult(),
+            DriverError::Shared(e) => e.hresult(),
+            #[cfg(test)]
+            DriverError::Test(hr) => *hr,
+        }
+    }
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DriverError::Hid(e) => e.fmt(f),
+            DriverError::Dmx(e) => e.fmt(f),
+            DriverError::Mouse(e) => e.fmt(f),
+            DriverError::Gamepad(e) => e.fmt(f),
+            DriverError::Network(e) => e.fmt(f),
+            DriverError::Serial(e) => e.fmt(f),
+            DriverError::Shared(e) => e.fmt(f),
+            #[cfg(test)]
+            DriverError::Test(hr) => write!(f, "Ongeki IO Test: {hr}"),
+        }
+    }
+}
+
+impl From<HidError> for DriverError {
+    fn from(e: HidError) -> Self {
+        DriverError::Hid(e)
+    }
+}
+
+impl From<DmxError> for DriverError {
+    fn from(e: DmxError) -> Self {
+        DriverError::Dmx(e)
+    }
+}
+
+impl From<MouseError> for DriverError {
+    fn from(e: MouseError) -> Self {
+        DriverError::Mouse(e)
+    }
+}
+
+impl From<GamepadError> for DriverError {
+    fn from(e: GamepadError) -> Self {
+        DriverError::Gamepad(e)
+    }
+}
+
+impl From<NetworkError> for DriverError {
+    fn from(e: NetworkError) -> Self {
+        DriverError::Network(e)
+    }
+}
+
+impl From<SerialError> for DriverError {
+    fn from(e: SerialError) -> Self {
+        DriverError::Serial(e)
+    }
+}
+
+impl From<SharedError> for DriverError {
+    fn from(e: SharedError) -> Self {
+        DriverError::Shared(e)
+    }
+}
+
+/// 只有全部输入驱动都失败时才向游戏报告错误，此时返回第一个错误
+fn combine_results(results: &[HResult]) -> HResult {
+    if results.iter().any(|r| r.is_ok()) {
+        return HResult::S_OK;
+    }
+    results.first().copied().unwrap_or(HResult::S_OK)
+}
 
 trait PollDriver {
-    fn poll(&mut self) -> HResult;
+    fn poll(&mut self) -> Result<(), DriverError>;
 }
 
 trait ButtonDriver {
@@ -43,95 +172,923 @@ trait LEDriverNew {
     fn set_led_new(&mut self, board: u8, rgb: &[rgb::RGB8]);
 }
 
-pub struct Drivers(Vec<Box<dyn Driver>>);
+/// 重新连接设备后需要重新发送 LED 的驱动
+trait ReconnectDriver {
+    /// 上一次调用之后连接过设备时返回 `true`
+    fn take_reconnected(&mut self) -> bool;
+}
+
+/// 进程退出前保存状态
+trait ShutdownDriver {
+    fn shutdown(&mut self);
+}
+
+struct DriverEntry {
+    driver: Box<dyn Driver>,
+    lever_filter: LeverFilter,
+    /// 滤波后的摇杆值，每次 poll 更新
+    lever: i16,
+    /// 上一次 poll 的错误信息，变化时才打印
+    last_error: Option<String>,
+    /// LED 输出的颜色顺序，为 `None` 时不经过颜色处理（录制、转发给主进程）
+    channel_order: Option<ChannelOrder>,
+    /// 经过颜色处理的输出的去重和限速
+    led_queue: LedQueue,
+}
+
+impl DriverEntry {
+    /// 录制和转发给主进程的驱动直接发送，其他驱动经过输出队列
+    fn send_led(&mut self, call: LedCall, now: Instant) {
+        let call = match self.channel_order {
+            Some(_) => self.led_queue.push(call, now),
+            None => Some(call),
+        };
+        if let Some(call) = call {
+            self.write_led(&call);
+        }
+    }
+
+    fn write_led(&mut self, call: &LedCall) {
+        match call {
+            LedCall::Legacy(data) => {
+                if let Ok(d) = dyn_dyn_cast!(mut Driver => LEDriver, self.driver.deref_mut()) {
+                    d.set_led(*data);
+                }
+            }
+            LedCall::Colors(board, rgb) => {
+                if let Ok(d) = dyn_dyn_cast!(mut Driver => LEDriverNew, self.driver.deref_mut()) {
+                    d.set_led_new(*board, rgb);
+                }
+            }
+        }
+    }
+}
+
+pub struct Drivers {
+    drivers: Vec<DriverEntry>,
+    shared: Option<SharedOwner>,
+    recorder: Option<InputRecorder>,
+    led_color: ColorProcessor,
+    legacy_led: LegacyLedConfig,
+    /// 合并旧版与新版 API 后 board 1 的颜色
+    board1: [rgb::RGB8; BOARD1_LEDS],
+    led_output: LedOutputConfig,
+    /// 启用待机灯效时存在
+    effects: Option<LedEffects>,
+    /// 启用按键反馈时存在
+    feedback: Option<ButtonFeedback>,
+    poll: PollConfig,
+}
 
 impl Drivers {
     pub fn new() -> Self {
-        Self(vec![])
+        Self {
+            drivers: vec![],
+            shared: None,
+            recorder: None,
+            led_color: ColorProcessor::new(&Default::default()),
+            legacy_led: LegacyLedConfig::default(),
+            board1: [rgb::RGB8::default(); BOARD1_LEDS],
+            led_output: LedOutputConfig::default(),
+            effects: None,
+            feedback: None,
+            poll: PollConfig::default(),
+        }
     }
 
-    pub fn init(&mut self) {
-        const CONFIG_PATH: &str = "ongeki-io.toml";
+    /// `led_only` 为 true 时只由 `mu3_io_led_init` 初始化，启用共享时总是作为子进程
+    pub fn init(&mut self, led_only: bool) -> HResult {
+        // 环境变量可以为不同进程指定不同的配置文件
+        let config_path =
+            env::var("ONGEKI_IO_CONFIG").unwrap_or_else(|_| "ongeki-io.toml".to_string());
         let mut config = Config::default();
 
-        if let Ok(s) = fs::read_to_string(CONFIG_PATH) {
-            config = toml::from_str(&s).unwrap();
+        if let Ok(s) = fs::read_to_string(&config_path) {
+            config = match toml::from_str(&s) {
+                Ok(config) => config,
+                Err(e) => {
+                    println!("Ongeki IO: 配置文件 {config_path} 有误\n{e}");
+                    return HResult::E_INVALIDARG;
+                }
+            };
             println!("Ongeki IO: 使用配置文件\n{:#?}", config);
         } else {
-            let mut f = File::create(CONFIG_PATH).unwrap();
-            f.write_all(toml::to_string_pretty(&config).unwrap().as_bytes())
-                .unwrap();
+            let written = toml::to_string_pretty(&config)
+                .map_err(io::Error::other)
+                .and_then(|s| File::create(&config_path)?.write_all(s.as_bytes()));
+            if let Err(e) = written {
+                println!("Ongeki IO: 无法写入默认配置文件 {config_path} {e}");
+            }
             println!("Ongeki IO: 未发现配置文件，使用默认配置\n{:#?}", config);
         }
 
+        self.poll = config.poll.clone();
+        self.led_color = ColorProcessor::new(&config.led_color);
+        self.legacy_led = config.legacy_led.clone();
+        self.led_output = config.led_output.clone();
+        // 先读取回放文件，录制到同一文件时不会先被清空
+        let replay = config.replay.enabled.then(|| {
+            Recording::load(&config.replay.path).map_err(|e| {
+                println!(
+                    "Ongeki IO Replay: 无法读取录制文件 {}，使用真实硬件 {e}",
+                    config.replay.path
+                );
+            })
+        });
+        if config.shared.enabled {
+            match Channel::open(&config.shared) {
+                Ok(channel) => {
+                    let timeout_ms = config.shared.timeout_ms;
+                    // 只输出 LED 的进程（mu3）不读取输入，不能作为主进程
+                    let role = match led_only {
+                        true => SharedRole::Client,
+                        false => config.shared.role,
+                    };
+                    if channel.claim(role, ipc::now_ms(), timeout_ms) {
+                        println!("Ongeki IO Shared: 作为主进程");
+                        self.shared = Some(SharedOwner::new(channel));
+                    } else {
+                        if role == SharedRole::Owner {
+                            println!("Ongeki IO Shared: 已有存活的主进程，无法作为主进程");
+                        }
+                        // 子进程不持有硬件，只转发 LED 并读取主进程的输入
+                        println!("Ongeki IO Shared: 作为子进程");
+                        self.push_raw(Box::new(SharedIO::new(channel, timeout_ms)));
+                        if config.led_debug.enabled {
+                            self.push(Box::new(LEDebug::new()));
+                        }
+                        return HResult::S_OK;
+                    }
+                }
+                Err(e) => println!("Ongeki IO Shared: 无法打开共享内存 {e}"),
+            }
+        }
+
+        // 共享时只由主进程录制，两个进程使用同一份配置
+        if config.record.enabled {
+            match InputRecorder::create(&config.record) {
+                Ok(recorder) => {
+                    println!("Ongeki IO: 录制输入到 {}", config.record.path);
+                    self.recorder = Some(recorder);
+                }
+                Err(e) => println!("Ongeki IO: 无法创建录制文件 {} {e}", config.record.path),
+            }
+        }
+        if config.led_effects.enabled {
+            self.effects = Some(LedEffects::new(&config.led_effects, Instant::now()));
+        }
+        if config.button_feedback.enabled {
+            self.feedback = Some(ButtonFeedback::new(&config.button_feedback));
+        }
+        if config.led_record.enabled {
+            match LedRecordWriter::create(&config.led_record.path) {
+                Ok(writer) => {
+                    println!("Ongeki IO: 录制 LED 到 {}", config.led_record.path);
+                    self.push_raw(Box::new(LedRecordIO::new(writer)));
+                }
+                Err(e) => println!(
+                    "Ongeki IO: 无法创建 LED 录制文件 {} {e}",
+                    config.led_record.path
+                ),
+            }
+        }
+        // 只输出 LED 的驱动在回放时同样启用
+        if config.dmx.enabled {
+            self.push_entry(
+                Box::new(DmxIO::new(config.dmx.clone())),
+                &LeverFilterConfig::default(),
+                Some(config.dmx.channel_order),
+            );
+        }
+        if config.openrgb.enabled {
+            self.push(Box::new(OpenRgbIO::new(config.openrgb.clone())));
+        }
+        if let Some(Ok(recording)) = replay {
+            println!(
+                "Ongeki IO Replay: 回放 {}，共 {} 条",
+                config.replay.path,
+                recording.records.len()
+            );
+            self.push_lever(
+                Box::new(ReplayIO::new(&config.replay, recording)),
+                &config.replay.lever_filter,
+            );
+            if config.led_debug.enabled {
+                self.push(Box::new(LEDebug::new()));
+            }
+            return HResult::S_OK;
+        }
+
+        // 与 poll 相同，只统计输入驱动的初始化结果
+        let mut results = vec![];
         if config.keyboard.enabled {
-            self.0
-                .push(Box::new(KeyBoardIO::new(config.keyboard.clone())));
+            self.push(Box::new(KeyBoardIO::new(config.keyboard.clone())));
+            results.push(HResult::S_OK);
         }
         if config.mouse.enabled {
-            self.0.push(Box::new(MouseIO::new()));
+            self.push_lever(Box::new(MouseIO::new()), &config.mouse.lever_filter);
+            results.push(HResult::S_OK);
         }
         if config.led_debug.enabled {
-            self.0.push(Box::new(LEDebug::new()));
+            self.push(Box::new(LEDebug::new()));
         }
         if config.hid.enabled {
-            self.0.push(Box::new(HidIO::new(config.hid.clone())));
+            let (hid, status) = HidIO::new(config.hid.clone());
+            self.push_entry(
+                Box::new(hid),
+                &config.hid.lever_filter,
+                Some(config.hid.channel_order),
+            );
+            results.push(self.init_result(status));
+        }
+        if config.serial.enabled {
+            let (serial, status) = SerialIO::new(config.serial.clone());
+            self.push_entry(
+                Box::new(serial),
+                &config.serial.lever_filter,
+                Some(config.serial.channel_order),
+            );
+            results.push(self.init_result(status));
+        }
+        if config.network.enabled {
+            let (network, status) = NetworkIO::new(config.network.clone());
+            self.push_entry(
+                Box::new(network),
+                &config.network.lever_filter,
+                Some(config.network.channel_order),
+            );
+            results.push(self.init_result(status));
+        }
+        if config.gamepad.enabled {
+            let (gamepad, status) = GamepadIO::new(config.gamepad.clone());
+            self.push_lever(Box::new(gamepad), &config.gamepad.lever_filter);
+            results.push(self.init_result(status));
+        }
+        combine_results(&results)
+    }
+
+    /// 打印最后加入的驱动初始化时的错误，之后 poll 遇到相同的错误时不再重复打印
+    fn init_result(&mut self, status: Result<(), impl Into<DriverError>>) -> HResult {
+        let Err(e) = status else {
+            return HResult::S_OK;
+        };
+        let e: DriverError = e.into();
+        let message = e.to_string();
+        println!("{message}");
+        if let Some(entry) = self.drivers.last_mut() {
+            entry.last_error = Some(message);
+        }
+        e.hresult()
+    }
+
+    fn push(&mut self, driver: Box<dyn Driver>) {
+        self.push_lever(driver, &LeverFilterConfig::default());
+    }
+
+    fn push_lever(&mut self, driver: Box<dyn Driver>, filter: &LeverFilterConfig) {
+        self.push_entry(driver, filter, Some(ChannelOrder::Rgb));
+    }
+
+    /// 收到的 LED 数据不经过颜色处理
+    fn push_raw(&mut self, driver: Box<dyn Driver>) {
+        self.push_entry(driver, &LeverFilterConfig::default(), None);
+    }
+
+    fn push_entry(
+        &mut self,
+        driver: Box<dyn Driver>,
+        filter: &LeverFilterConfig,
+        channel_order: Option<ChannelOrder>,
+    ) {
+        self.drivers.push(DriverEntry {
+            driver,
+            lever_filter: LeverFilter::new(filter),
+            lever: 0,
+            last_error: None,
+            channel_order,
+            led_queue: LedQueue::new(&self.led_output),
+        });
+    }
+
+    pub fn poll(&mut self) -> HResult {
+        let mut results = vec![];
+        for entry in self.drivers.iter_mut() {
+            // 只输出 LED 的驱动出错时只打印，不影响返回给游戏的结果
+            let is_input = dyn_dyn_cast!(Driver => ButtonDriver, entry.driver.deref()).is_ok()
+                || dyn_dyn_cast!(Driver => LeverDriver, entry.driver.deref()).is_ok();
+            let Ok(d) = dyn_dyn_cast!(mut Driver => PollDriver, entry.driver.deref_mut()) else {
+                continue;
+            };
+            let result = match d.poll() {
+                Ok(()) => {
+                    entry.last_error = None;
+                    HResult::S_OK
+                }
+                Err(e) => {
+                    let message = e.to_string();
+                    if entry.last_error.as_ref() != Some(&message) {
+                        println!("{message}");
+                        entry.last_error = Some(message);
+                    }
+                    e.hresult()
+                }
+            };
+            if is_input {
+                results.push(result);
+            }
+        }
+
+        let now = Instant::now();
+        for entry in self.drivers.iter_mut() {
+            if let Ok(d) = dyn_dyn_cast!(Driver => LeverDriver, entry.driver.deref()) {
+                entry.lever = entry.lever_filter.apply(d.lever(), now);
+            }
         }
+
+        if self.shared.is_some() {
+            let input = self.input();
+            let frames = self
+                .shared
+                .as_mut()
+                .map(|s| s.sync(input))
+                .unwrap_or_default();
+            for frame in frames {
+                match frame {
+                    LedFrame::Legacy(data) => self.set_led(data),
+                    LedFrame::Colors(board, rgb) => self.set_led_new(board, &rgb),
+                }
+            }
+        }
+
+        self.tick_leds(now);
+        for entry in self.drivers.iter_mut() {
+            // 断开期间的帧已经记为发送过，重新连接后需要重新发送
+            if let Ok(d) = dyn_dyn_cast!(mut Driver => ReconnectDriver, entry.driver.deref_mut()) {
+                if d.take_reconnected() {
+                    entry.led_queue.resend();
+                }
+            }
+            for call in entry.led_queue.flush(now) {
+                entry.write_led(&call);
+            }
+        }
+
+        if self.recorder.is_some() {
+            let input = self.input();
+            let recorded = self.recorder.as_mut().map(|r| r.record(input));
+            // 录制失败不影响游戏，停止录制即可
+            if let Some(Err(e)) = recorded {
+                println!("Ongeki IO: 录制输入失败，已停止录制 {e}");
+                self.recorder = None;
+            }
+        }
+
+        combine_results(&results)
     }
 
-    pub fn poll(&mut self) {
-        for driver in self.0.iter_mut() {
-            if let Ok(d) = dyn_dyn_cast!(mut Driver => PollDriver, driver.deref_mut()) {
-                d.poll();
+    /// 播放待机灯效，按键反馈变化时发送 board 1
+    fn tick_leds(&mut self, now: Instant) {
+        if self.feedback.is_some() {
+            let (left, right) = (self.left_btns(), self.right_btns());
+            if let Some(feedback) = self.feedback.as_mut() {
+                feedback.update_buttons(left, right, now);
             }
         }
+        if let Some(state) = self.effects.as_mut().and_then(|e| e.tick(now)) {
+            self.dispatch_led(0, &state.board0, None, now);
+            let board1 = match self.feedback.as_mut() {
+                Some(feedback) => {
+                    feedback.set_base(&state.board1);
+                    let blended = feedback.blend(now);
+                    feedback.mark_sent(now);
+                    blended.unwrap_or(state.board1)
+                }
+                None => state.board1,
+            };
+            self.dispatch_led(1, &board1, None, now);
+        } else if let Some(frame) = self.feedback.as_mut().and_then(|f| f.tick(now)) {
+            self.dispatch_led(1, &frame, None, now);
+        }
+    }
+
+    /// 启用后台轮询时的轮询频率
+    pub fn poll_rate(&self) -> Option<u32> {
+        self.poll.threaded.then_some(self.poll.rate_hz)
+    }
+
+    pub fn input(&self) -> InputSnapshot {
+        InputSnapshot {
+            op_btns: self.op_btns(),
+            left_btns: self.left_btns(),
+            right_btns: self.right_btns(),
+            lever: self.lever().unwrap_or(0),
+        }
     }
 
     pub fn op_btns(&self) -> u8 {
-        self.0
+        self.drivers
             .iter()
-            .filter_map(|d| dyn_dyn_cast!(Driver => ButtonDriver, d.deref()).ok())
+            .filter_map(|e| dyn_dyn_cast!(Driver => ButtonDriver, e.driver.deref()).ok())
             .map(|d| d.op_btns())
             .fold(0, |r, v| r | v)
     }
 
     pub fn left_btns(&self) -> u8 {
-        self.0
+        self.drivers
             .iter()
-            .filter_map(|d| dyn_dyn_cast!(Driver => ButtonDriver, d.deref()).ok())
+            .filter_map(|e| dyn_dyn_cast!(Driver => ButtonDriver, e.driver.deref()).ok())
             .map(|d| d.left_btns())
             .fold(0, |r, v| r | v)
     }
 
     pub fn right_btns(&self) -> u8 {
-        self.0
+        self.drivers
             .iter()
-            .filter_map(|d| dyn_dyn_cast!(Driver => ButtonDriver, d.deref()).ok())
+            .filter_map(|e| dyn_dyn_cast!(Driver => ButtonDriver, e.driver.deref()).ok())
             .map(|d| d.right_btns())
             .fold(0, |r, v| r | v)
     }
 
     pub fn lever(&self) -> Option<i16> {
-        self.0
+        self.drivers
             .iter()
-            .filter_map(|d| dyn_dyn_cast!(Driver => LeverDriver, d.deref()).ok())
-            .map(|d| d.lever())
-            .next()
+            .find(|e| dyn_dyn_cast!(Driver => LeverDriver, e.driver.deref()).is_ok())
+            .map(|e| e.lever)
     }
 
-    pub fn set_led(&mut self, data: u32) {
-        for driver in self.0.iter_mut() {
-            if let Ok(d) = dyn_dyn_cast!(mut Driver => LEDriver, driver.deref_mut()) {
-                d.set_led(data);
+    pub fn shutdown(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            if let Err(e) = recorder.finish() {
+                println!("Ongeki IO: 保存录制文件失败 {e}");
+            }
+        }
+        for entry in self.drivers.iter_mut() {
+            if let Ok(d) = dyn_dyn_cast!(mut Driver => ShutdownDriver, entry.driver.deref_mut()) {
+                d.shutdown();
             }
         }
     }
 
+    pub fn set_led(&mut self, data: u32) {
+        let now = Instant::now();
+        if let Some(effects) = self.effects.as_mut() {
+            effects.game_update(now);
+        }
+        self.board1 = LegacyLedState(data).colors(&self.legacy_led);
+        let board1 = self.board1;
+        let overlay = self.feedback.as_mut().and_then(|f| {
+            f.game_update(&board1, now);
+            f.blend(now)
+        });
+        // 颜色与驱动自己解码的结果相同时发送原始位域，否则发送 board 1 的颜色
+        let convert = overlay.is_some()
+            || !self.led_color.is_identity()
+            || self.legacy_led != LegacyLedConfig::default();
+        let colors = self
+            .led_color
+            .process(1, overlay.as_ref().unwrap_or(&board1));
+        for entry in self.drivers.iter_mut() {
+            let legacy = dyn_dyn_cast!(Driver => LEDriver, entry.driver.deref()).is_ok();
+            let new = dyn_dyn_cast!(Driver => LEDriverNew, entry.driver.deref()).is_ok();
+            let call = match entry.channel_order {
+                Some(order) if new && (convert || order != ChannelOrder::Rgb || !legacy) => {
+                    LedCall::Colors(1, order.apply_all(&colors).into_owned())
+                }
+                _ if legacy => LedCall::Legacy(data),
+                _ => continue,
+            };
+            entry.send_led(call, now);
+        }
+    }
+
     pub fn set_led_new(&mut self, board: u8, rgb: &[rgb::RGB8]) {
-        for driver in self.0.iter_mut() {
-            if let Ok(d) = dyn_dyn_cast!(mut Driver => LEDriverNew, driver.deref_mut()) {
-                d.set_led_new(board, rgb);
+        let now = Instant::now();
+        if let Some(effects) = self.effects.as_mut() {
+            effects.game_update(now);
+        }
+        if board != 1 {
+            self.dispatch_led(board, rgb, Some(rgb), now);
+            return;
+        }
+        // 只设置了部分按键灯时，其余的保持旧版或新版 API 最近设置的颜色
+        for (led, c) in self.board1.iter_mut().zip(rgb) {
+            *led = *c;
+        }
+        let board1 = self.board1;
+        let overlay = self.feedback.as_mut().and_then(|f| {
+            f.game_update(&board1, now);
+            f.blend(now)
+        });
+        self.dispatch_led(1, overlay.as_ref().unwrap_or(&board1), Some(rgb), now);
+    }
+
+    /// `raw` 为游戏的原始数据，发送给录制和转发给主进程的驱动，
+    /// 为 `None` 时（待机灯效、按键反馈）不发送给这些驱动
+    fn dispatch_led(
+        &mut self,
+        board: u8,
+        rgb: &[rgb::RGB8],
+        raw: Option<&[rgb::RGB8]>,
+        now: Instant,
+    ) {
+        let processed = self.led_color.process(board, rgb);
+        for entry in self.drivers.iter_mut() {
+            if dyn_dyn_cast!(Driver => LEDriverNew, entry.driver.deref()).is_err() {
+                continue;
             }
+            let rgb = match (entry.channel_order, raw) {
+                (Some(order), _) => order.apply_all(&processed).into_owned(),
+                (None, Some(raw)) => raw.to_vec(),
+                (None, None) => continue,
+            };
+            entry.send_led(LedCall::Colors(board, rgb), now);
         }
     }
 }
+
+#[cfg(test)]
+mod drivers_test {
+    use super::fake::FakeIO;
+    use super::*;
+    use crate::enums::GameBtn;
+    use crate::led_recording::LedCall;
+
+    #[test]
+    fn combine_results_test() {
+        assert_eq!(combine_results(&[]), HResult::S_OK);
+        assert_eq!(
+            combine_results(&[HResult::E_HANDLE, HResult::S_OK]),
+            HResult::S_OK
+        );
+        assert_eq!(
+            combine_results(&[HResult::E_HANDLE, HResult::E_FAIL]),
+            HResult::E_HANDLE
+        );
+    }
+
+    #[test]
+    fn init_result_test() {
+        let mut drivers = Drivers::with_fakes(vec![FakeIO::default()]);
+        assert_eq!(drivers.init_result(Ok::<(), DriverError>(())), HResult::S_OK);
+        assert_eq!(drivers.drivers[0].last_error, None);
+        let status = Err(DriverError::Test(HResult::E_FAIL));
+        assert_eq!(drivers.init_result(status), HResult::E_FAIL);
+        assert_eq!(
+            drivers.drivers[0].last_error.as_deref(),
+            Some("Ongeki IO Test: 0x80004005")
+        );
+    }
+
+    #[test]
+    fn aggregate_test() {
+        use crate::config::Config;
+        use crate::platform::fake::{FakeCursor, FakeKeys};
+
+        let keys = FakeKeys::default();
+        let cursor = FakeCursor::new(1000);
+        let mut drivers = Drivers::new();
+        drivers.push(Box::new(KeyBoardIO::with_source(
+            Config::default().keyboard,
+            Box::new(keys.clone()),
+        )));
+        drivers.push_lever(
+            Box::new(MouseIO::with_source(Box::new(cursor.clone()))),
+            &LeverFilterConfig::default(),
+        );
+        drivers.push(Box::new(FakeIO {
+            left: GameBtn::Side as u8,
+            lever: 1234,
+            ..Default::default()
+        }));
+
+        // A = 左 Btn1，L = 右 Btn3
+        keys.press(0x41);
+        keys.press(0x4C);
+        cursor.set(Ok(750));
+        assert_eq!(drivers.poll(), HResult::S_OK);
+        // 按键按位或，摇杆取第一个摇杆驱动
+        assert_eq!(
+            drivers.input(),
+            InputSnapshot {
+                op_btns: 0,
+                left_btns: GameBtn::Btn1 as u8 | GameBtn::Side as u8,
+                right_btns: GameBtn::Btn3 as u8,
+                lever: 16384,
+            }
+        );
+    }
+
+    #[test]
+    fn record_replay_test() {
+        use crate::config::{Config, RecordConfig, ReplayMode};
+
+        let config = RecordConfig {
+            enabled: true,
+            path: std::env::temp_dir()
+                .join(format!("ongeki-io-drivers-{}.rec", std::process::id()))
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let mut drivers = Drivers::new();
+        drivers.push_lever(
+            Box::new(FakeIO {
+                left: GameBtn::Btn2 as u8,
+                lever: -2000,
+                ..Default::default()
+            }),
+            &LeverFilterConfig::default(),
+        );
+        drivers.recorder = Some(InputRecorder::create(&config).unwrap());
+        for _ in 0..3 {
+            drivers.poll();
+        }
+        drivers.shutdown();
+
+        let recording = Recording::load(&config.path).unwrap();
+        fs::remove_file(&config.path).unwrap();
+        assert_eq!(recording.records.len(), 3);
+        assert!(recording.records.iter().all(|r| r.input() == drivers.input()));
+
+        // 回放经过同样的汇总和摇杆滤波
+        let replay = crate::config::ReplayConfig {
+            mode: ReplayMode::Step,
+            ..Config::default().replay
+        };
+        let mut replayed = Drivers::new();
+        replayed.push_lever(
+            Box::new(ReplayIO::new(&replay, recording)),
+            &LeverFilterConfig {
+                invert: true,
+                ..Default::default()
+            },
+        );
+        replayed.poll();
+        assert_eq!(replayed.left_btns(), GameBtn::Btn2 as u8);
+        assert_eq!(replayed.lever(), Some(2000));
+    }
+
+    #[test]
+    fn led_color_test() {
+        use crate::config::LedColorConfig;
+        use rgb::RGB8;
+
+        let mut drivers = Drivers::new();
+        let (processed, raw) = (FakeIO::default(), FakeIO::default());
+        let (processed_leds, raw_leds) = (processed.leds.clone(), raw.leds.clone());
+        drivers.push_entry(
+            Box::new(processed),
+            &LeverFilterConfig::default(),
+            Some(ChannelOrder::Grb),
+        );
+        drivers.push_raw(Box::new(raw));
+
+        // 只调整颜色顺序时旧版 API 也转换为颜色
+        drivers.set_led(1 << 23);
+        let mut buttons = vec![RGB8::default(); 6];
+        buttons[0] = RGB8::new(0, 255, 0);
+        assert_eq!(
+            processed_leds.lock().unwrap().pop(),
+            Some(LedCall::Colors(1, buttons))
+        );
+        assert_eq!(
+            raw_leds.lock().unwrap().pop(),
+            Some(LedCall::Legacy(1 << 23))
+        );
+
+        drivers.led_color = ColorProcessor::new(&LedColorConfig {
+            brightness: 0.5,
+            ..Default::default()
+        });
+        let rgb = [RGB8::new(255, 0, 0), RGB8::new(0, 100, 200)];
+        drivers.set_led_new(0, &rgb);
+        assert_eq!(
+            processed_leds.lock().unwrap().pop(),
+            Some(LedCall::Colors(
+                0,
+                vec![RGB8::new(0, 128, 0), RGB8::new(50, 0, 100)]
+            ))
+        );
+        assert_eq!(
+            raw_leds.lock().unwrap().pop(),
+            Some(LedCall::Colors(0, rgb.to_vec()))
+        );
+    }
+
+    #[test]
+    fn led_output_test() {
+        use rgb::RGB8;
+
+        let mut drivers = Drivers::new();
+        drivers.led_output = LedOutputConfig {
+            dedupe: true,
+            max_fps: 20,
+        };
+        let (io, raw) = (FakeIO::default(), FakeIO::default());
+        let (leds, raw_leds) = (io.leds.clone(), raw.leds.clone());
+        drivers.push(Box::new(io));
+        drivers.push_raw(Box::new(raw));
+
+        let red = [RGB8::new(255, 0, 0); 6];
+        let blue = [RGB8::new(0, 0, 255); 6];
+        drivers.set_led_new(1, &red);
+        drivers.set_led_new(1, &red);
+        // 超过帧率的帧延后，board 0 单独限速
+        drivers.set_led_new(1, &blue);
+        drivers.set_led_new(0, &blue);
+        assert_eq!(
+            *leds.lock().unwrap(),
+            [
+                LedCall::Colors(1, red.to_vec()),
+                LedCall::Colors(0, blue.to_vec())
+            ]
+        );
+        // 录制和转发不去重
+        assert_eq!(raw_leds.lock().unwrap().len(), 4);
+
+        std::thread::sleep(std::time::Duration::from_millis(60));
+        drivers.poll();
+        assert_eq!(
+            leds.lock().unwrap().pop(),
+            Some(LedCall::Colors(1, blue.to_vec()))
+        );
+        drivers.poll();
+        assert_eq!(leds.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn led_reconnect_test() {
+        use rgb::RGB8;
+
+        let io = FakeIO::default();
+        let leds = io.leds.clone();
+        let mut drivers = Drivers::with_fakes(vec![io]);
+        let red = [RGB8::new(255, 0, 0); 6];
+        drivers.set_led_new(1, &red);
+        drivers.set_led_new(1, &red);
+        assert_eq!(leds.lock().unwrap().len(), 1);
+
+        // 重新连接后重新发送最近的一帧
+        drivers.replace_fake(
+            0,
+            FakeIO {
+                reconnected: true,
+                leds: leds.clone(),
+                ..Default::default()
+            },
+        );
+        drivers.poll();
+        assert_eq!(
+            *leds.lock().unwrap(),
+            [
+                LedCall::Colors(1, red.to_vec()),
+                LedCall::Colors(1, red.to_vec())
+            ]
+        );
+        drivers.poll();
+        assert_eq!(leds.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn legacy_led_test() {
+        use crate::config::LegacyLedConfig;
+        use rgb::RGB8;
+
+        let mut drivers = Drivers::new();
+        let io = FakeIO::default();
+        let leds = io.leds.clone();
+        drivers.push(Box::new(io));
+
+        // 默认颜色时直接发送位域
+        drivers.set_led(1 << 23);
+        assert_eq!(leds.lock().unwrap().pop(), Some(LedCall::Legacy(1 << 23)));
+
+        // 新版 API 只设置了左侧按键灯，右侧保持旧版 API 的颜色
+        drivers.set_led(1 << 14);
+        drivers.set_led_new(1, &[RGB8::new(1, 2, 3); 3]);
+        let mut board1 = vec![RGB8::new(1, 2, 3); 3];
+        board1.extend([RGB8::new(255, 0, 0), RGB8::default(), RGB8::default()]);
+        assert_eq!(leds.lock().unwrap().pop(), Some(LedCall::Colors(1, board1)));
+
+        drivers.legacy_led = LegacyLedConfig {
+            on: [128, 128, 128],
+            off: [0, 0, 16],
+        };
+        drivers.set_led(1 << 19);
+        let mut board1 = vec![RGB8::new(0, 0, 16); 6];
+        board1[0] = RGB8::new(0, 128, 16);
+        assert_eq!(leds.lock().unwrap().pop(), Some(LedCall::Colors(1, board1)));
+    }
+
+    #[test]
+    fn led_effects_test() {
+        use crate::config::{Config, LedEffectsConfig};
+        use std::time::Duration;
+
+        let mut drivers = Drivers::new();
+        let (output, raw) = (FakeIO::default(), FakeIO::default());
+        let (output_leds, raw_leds) = (output.leds.clone(), raw.leds.clone());
+        drivers.push(Box::new(output));
+        drivers.push_raw(Box::new(raw));
+        let config = LedEffectsConfig {
+            enabled: true,
+            idle_timeout_ms: 500,
+            ..Config::default().led_effects
+        };
+        let idle_since = Instant::now() - Duration::from_secs(1);
+        drivers.effects = Some(LedEffects::new(&config, idle_since));
+
+        // 空闲时播放灯效，录制等原始输出收不到
+        drivers.poll();
+        assert_eq!(output_leds.lock().unwrap().len(), 2);
+        assert!(raw_leds.lock().unwrap().is_empty());
+
+        // 游戏设置 LED 后立即停止
+        drivers.set_led_new(1, &[rgb::RGB8::new(1, 2, 3); 6]);
+        drivers.poll();
+        assert_eq!(output_leds.lock().unwrap().len(), 3);
+        assert_eq!(raw_leds.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn button_feedback_test() {
+        use crate::config::{ButtonFeedbackConfig, Config};
+        use rgb::RGB8;
+
+        let mut drivers = Drivers::new();
+        let (output, raw) = (
+            FakeIO {
+                left: GameBtn::Btn1 as u8,
+                ..Default::default()
+            },
+            FakeIO::default(),
+        );
+        let (output_leds, raw_leds) = (output.leds.clone(), raw.leds.clone());
+        drivers.push(Box::new(output));
+        drivers.push_raw(Box::new(raw));
+        drivers.feedback = Some(ButtonFeedback::new(&ButtonFeedbackConfig {
+            enabled: true,
+            ..Config::default().button_feedback
+        }));
+
+        let mut flashed = vec![RGB8::default(); 6];
+        flashed[0] = RGB8::new(255, 255, 255);
+        drivers.poll();
+        assert_eq!(
+            output_leds.lock().unwrap().pop(),
+            Some(LedCall::Colors(1, flashed.clone()))
+        );
+
+        // 按住时游戏设置的颜色同样叠加闪光，录制收到原始颜色
+        let blue = vec![RGB8::new(0, 0, 255); 6];
+        flashed = blue.clone();
+        flashed[0] = RGB8::new(255, 255, 255);
+        drivers.set_led_new(1, &blue);
+        assert_eq!(
+            output_leds.lock().unwrap().pop(),
+            Some(LedCall::Colors(1, flashed))
+        );
+        assert_eq!(
+            raw_leds.lock().unwrap().pop(),
+            Some(LedCall::Colors(1, blue))
+        );
+        assert!(raw_leds.lock().unwrap().is_empty());
+    }
+
+    /// cargo test --release snapshot_bench -- --ignored --nocapture
+    #[test]
+    #[ignore = "基准测试"]
+    fn snapshot_bench() {
+        use std::hint::black_box;
+        use std::sync::RwLock;
+
+        use crate::poller::InputCell;
+
+        const N: u32 = 1_000_000;
+        let mut drivers = Drivers::new();
+        for i in 0..5 {
+            drivers.push(Box::new(FakeIO {
+                left: 1 << i,
+                ..Default::default()
+            }));
+        }
+        let cell = InputCell::new();
+        cell.store(drivers.input(), HResult::S_OK);
+        let drivers = RwLock::new(drivers);
+
+        let start = Instant::now();
+        for _ in 0..N {
+            black_box(black_box(&drivers).read().unwrap().left_btns());
+        }
+        let fold = start.elapsed();
+
+        let start = Instant::now();
+        for _ in 0..N {
+            black_box(black_box(&cell).input().left_btns);
+        }
+        let snapshot = start.elapsed();
+
+        println!("RwLock + 遍历驱动: {:?}/次", fold / N);
+        println!("原子快照: {:?}/次", snapshot / N);
+        assert_eq!(cell.input().left_btns, 0x1F);
+    }
+}
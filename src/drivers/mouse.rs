@@ -1,21 +1,47 @@
+use std::fmt;
+
 use dyn_dyn::dyn_dyn_impl;
-use windows::Win32::UI::WindowsAndMessaging::{self, SM_CXSCREEN};
-use windows::Win32::Foundation::POINT;
 
-use crate::drivers::{Driver, LeverDriver, PollDriver};
+use crate::drivers::{Driver, DriverError, LeverDriver, PollDriver};
 use crate::enums::HResult;
+use crate::platform::{self, CursorSource};
 
 use super::hid;
 
+#[derive(Debug)]
+pub enum MouseError {
+    /// 锁屏等情况下取不到光标位置
+    Cursor(HResult),
+}
+
+impl MouseError {
+    pub fn hresult(&self) -> HResult {
+        match self {
+            MouseError::Cursor(hr) => *hr,
+        }
+    }
+}
+
+impl fmt::Display for MouseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MouseError::Cursor(hr) => write!(f, "Ongeki IO Mouse: 无法获取光标位置 {hr}"),
+        }
+    }
+}
 
-#[derive(Debug, Default)]
 pub struct MouseIO {
     lever: i16,
+    cursor: Box<dyn CursorSource>,
 }
 
 impl MouseIO {
     pub fn new() -> Self {
-        Self::default()
+        Self::with_source(Box::new(platform::Cursor))
+    }
+
+    pub fn with_source(cursor: Box<dyn CursorSource>) -> Self {
+        Self { lever: 0, cursor }
     }
 }
 
@@ -23,26 +49,23 @@ impl MouseIO {
 impl Driver for MouseIO {}
 
 impl PollDriver for MouseIO {
-    fn poll(&mut self) -> HResult {
-        unsafe {
-            let mut p = POINT::default();
-            WindowsAndMessaging::GetCursorPos(&mut p as *mut POINT).unwrap();
-            let mut mouse_x = p.x;
-            let screen_width = WindowsAndMessaging::GetSystemMetrics(SM_CXSCREEN);
-            if mouse_x < 0 {
-                mouse_x = 0;
-            } else if mouse_x > screen_width {
-                mouse_x = screen_width;
-            }
-
-            // let x_norm = mouse_x as f64 / screen_width as f64;
-            // let mouse_x = ((x_norm * 65536.) - 32767.) as i32;
-
-            let mouse_x = hid::map(mouse_x, 0, screen_width, -32768, 32768);
-
-            self.lever = mouse_x as i16;
+    fn poll(&mut self) -> Result<(), DriverError> {
+        // 取不到光标位置时保留上一次的值
+        let mut mouse_x = self.cursor.cursor_x().map_err(MouseError::Cursor)?;
+        let screen_width = self.cursor.screen_width();
+        if mouse_x < 0 {
+            mouse_x = 0;
+        } else if mouse_x > screen_width {
+            mouse_x = screen_width;
         }
-        HResult::Ok
+
+        // let x_norm = mouse_x as f64 / screen_width as f64;
+        // let mouse_x = ((x_norm * 65536.) - 32767.) as i32;
+
+        let mouse_x = hid::map(mouse_x, 0, screen_width, -32768, 32768);
+
+        self.lever = mouse_x.clamp(i32::from(i16::MIN), i32::from(i16::MAX)) as i16;
+        Ok(())
     }
 }
 
@@ -52,3 +75,36 @@ impl LeverDriver for MouseIO {
     }
 }
 
+#[cfg(test)]
+mod mouse_test {
+    use super::*;
+    use crate::platform::fake::FakeCursor;
+
+    #[test]
+    fn lever_test() {
+        let cursor = FakeCursor::new(1920);
+        let mut io = MouseIO::with_source(Box::new(cursor.clone()));
+
+        for (x, lever) in [
+            (0, i16::MIN),
+            (960, 0),
+            (-100, i16::MIN),
+            (1920, i16::MAX),
+            (5000, i16::MAX),
+        ] {
+            cursor.set(Ok(x));
+            io.poll().unwrap();
+            assert_eq!(io.lever(), lever, "{x}");
+        }
+
+        cursor.set(Ok(480));
+        io.poll().unwrap();
+        assert_eq!(io.lever(), -16384);
+
+        // 取不到光标时保留上一次的值
+        cursor.set(Err(HResult::E_ACCESSDENIED));
+        let err = io.poll().unwrap_err();
+        assert_eq!(err.hresult(), HResult::E_ACCESSDENIED);
+        assert_eq!(io.lever(), -16384);
+    }
+}
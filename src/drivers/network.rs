@@ -0,0 +1,400 @@
+//! 通过 UDP 接收远程手台（ESP32、树莓派等）的输入
+//!
+//! 所有数据包以 8 字节包头开始，多字节字段均为小端:
+//!
+//! | 偏移 | 类型 | 内容 |
+//! | --- | --- | --- |
+//! | 0 | `[u8; 2]` | `MAGIC` |
+//! | 2 | u8 | 类型 |
+//! | 3 | u8 | 保留，为 0 |
+//! | 4 | u32 | 序号，每个包加 1 |
+//!
+//! 输入包（手台 -> 主机）在包头后为 `op left right 0 lever(i16)`，
+//! LED 包（主机 -> 手台）在包头后为 `board count rgb[count]`。
+
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use dyn_dyn::dyn_dyn_impl;
+
+use crate::{
+    config::NetworkConfig,
+    enums::{HResult, ERROR_DEVICE_NOT_CONNECTED, ERROR_TIMEOUT},
+    ipc::InputSnapshot,
+    led_layout::LedLayout,
+};
+
+use super::{
+    ButtonDriver, Driver, DriverError, LEDriverNew, LeverDriver, PollDriver, ReconnectDriver,
+};
+
+pub const MAGIC: [u8; 2] = *b"OI";
+pub const KIND_INPUT: u8 = 0x01;
+pub const KIND_LED: u8 = 0x10;
+const HEADER_LEN: usize = 8;
+const INPUT_LEN: usize = HEADER_LEN + 6;
+/// 每次 poll 最多处理的包数
+const MAX_PACKETS_PER_POLL: usize = 64;
+
+#[derive(Debug)]
+pub enum NetworkError {
+    Bind(io::Error),
+    Socket(io::Error),
+    /// 还没有收到过输入包
+    NoPeer,
+    /// 超过 `timeout_ms` 没有收到输入包
+    Timeout,
+}
+
+impl NetworkError {
+    pub fn hresult(&self) -> HResult {
+        match self {
+            NetworkError::Bind(_) | NetworkError::Socket(_) => HResult::E_HANDLE,
+            NetworkError::NoPeer => HResult::from_win32(ERROR_DEVICE_NOT_CONNECTED),
+            NetworkError::Timeout => HResult::from_win32(ERROR_TIMEOUT),
+        }
+    }
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Bind(e) => write!(f, "Ongeki IO Network: 无法监听端口 {e}"),
+            NetworkError::Socket(e) => write!(f, "Ongeki IO Network: 接收失败 {e}"),
+            NetworkError::NoPeer => write!(f, "Ongeki IO Network: 等待手台连接"),
+            NetworkError::Timeout => write!(f, "Ongeki IO Network: 手台无响应"),
+        }
+    }
+}
+
+fn header(kind: u8, seq: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(64);
+    buf.extend(MAGIC);
+    buf.extend([kind, 0]);
+    buf.extend(seq.to_le_bytes());
+    buf
+}
+
+/// 解析输入包，返回序号与输入，格式不对时返回 `None`
+pub fn parse_input(data: &[u8]) -> Option<(u32, InputSnapshot)> {
+    if data.len() < INPUT_LEN || data[..2] != MAGIC || data[2] != KIND_INPUT {
+        return None;
+    }
+    let seq = u32::from_le_bytes(data[4..8].try_into().ok()?);
+    let input = InputSnapshot {
+        op_btns: data[8] & 0x07,
+        left_btns: data[9] & 0x1F,
+        right_btns: data[10] & 0x1F,
+        lever: i16::from_le_bytes([data[12], data[13]]),
+    };
+    Some((seq, input))
+}
+
+pub fn led_packet(seq: u32, board: u8, rgb: &[rgb::RGB8]) -> Vec<u8> {
+    let rgb = &rgb[..rgb.len().min(usize::from(u8::MAX))];
+    let mut buf = header(KIND_LED, seq);
+    buf.extend([board, rgb.len() as u8]);
+    buf.extend(rgb.iter().flat_map(|c| [c.r, c.g, c.b]));
+    buf
+}
+
+pub struct NetworkIO {
+    input: InputSnapshot,
+    config: NetworkConfig,
+    socket: Option<UdpSocket>,
+    /// 最近一次发来有效输入的地址，LED 数据发回这里
+    peer: Option<SocketAddr>,
+    last_seq: u32,
+    last_packet: Option<Instant>,
+    led_seq: u32,
+    /// 手台连接或超时后重新发来输入，还没有通知重新发送 LED
+    reconnected: bool,
+    led_layout: LedLayout,
+}
+
+#[dyn_dyn_impl(
+    Driver,
+    PollDriver,
+    ButtonDriver,
+    LeverDriver,
+    LEDriverNew,
+    ReconnectDriver
+)]
+impl Driver for NetworkIO {}
+
+impl NetworkIO {
+    /// 同时返回首次绑定端口的结果，未绑定时之后 poll 会继续尝试
+    pub fn new(config: NetworkConfig) -> (Self, Result<(), NetworkError>) {
+        let mut s = Self {
+            input: InputSnapshot::default(),
+            led_layout: LedLayout::new(config.led_routes.clone()),
+            config,
+            socket: None,
+            peer: None,
+            last_seq: 0,
+            last_packet: None,
+            led_seq: 0,
+            reconnected: false,
+        };
+        let status = s.try_bind();
+        (s, status)
+    }
+
+    fn try_bind(&mut self) -> Result<(), NetworkError> {
+        let socket = UdpSocket::bind((self.config.bind.as_str(), self.config.port))
+            .and_then(|s| s.set_nonblocking(true).map(|_| s))
+            .map_err(NetworkError::Bind)?;
+        if let Ok(addr) = socket.local_addr() {
+            println!("Ongeki IO Network: 监听 {addr}");
+        }
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    fn timed_out(&self, now: Instant) -> bool {
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        self.last_packet.is_none_or(|t| now - t > timeout)
+    }
+
+    fn handle_packet(&mut self, data: &[u8], from: SocketAddr, now: Instant) {
+        let Some((seq, input)) = parse_input(data) else {
+            return;
+        };
+        // 超时后或换了手台时接受任意序号，以便手台重启后序号从头开始
+        let resync = self.timed_out(now) || self.peer != Some(from);
+        if !resync && (seq.wrapping_sub(self.last_seq) as i32) <= 0 {
+            return;
+        }
+        if self.peer != Some(from) {
+            println!("Ongeki IO Network: {from} 已连接");
+        }
+        self.peer = Some(from);
+        self.reconnected |= resync;
+        self.last_seq = seq;
+        self.last_packet = Some(now);
+        self.input = input;
+    }
+}
+
+impl PollDriver for NetworkIO {
+    fn poll(&mut self) -> Result<(), DriverError> {
+        let Some(ref socket) = self.socket else {
+            return Ok(self.try_bind()?);
+        };
+
+        let mut buf = [0u8; 64];
+        let mut packets = vec![];
+        for _ in 0..MAX_PACKETS_PER_POLL {
+            match socket.recv_from(&mut buf) {
+                Ok((n, from)) => packets.push((buf[..n].to_vec(), from)),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                // Windows 下发往已关闭端口的 LED 包会导致下一次接收报错，忽略即可
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(NetworkError::Socket(e).into()),
+            }
+        }
+
+        let now = Instant::now();
+        for (data, from) in packets {
+            self.handle_packet(&data, from, now);
+        }
+
+        // 没有收到输入包时松开所有按键
+        if self.timed_out(now) {
+            self.input = InputSnapshot::default();
+            let e = match self.last_packet {
+                Some(_) => NetworkError::Timeout,
+                None => NetworkError::NoPeer,
+            };
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+impl ButtonDriver for NetworkIO {
+    fn op_btns(&self) -> u8 {
+        self.input.op_btns
+    }
+
+    fn left_btns(&self) -> u8 {
+        self.input.left_btns
+    }
+
+    fn right_btns(&self) -> u8 {
+        self.input.right_btns
+    }
+}
+
+impl LeverDriver for NetworkIO {
+    fn lever(&self) -> i16 {
+        self.input.lever
+    }
+}
+
+impl LEDriverNew for NetworkIO {
+    fn set_led_new(&mut self, board: u8, rgb: &[rgb::RGB8]) {
+        let (Some(socket), Some(peer)) = (&self.socket, self.peer) else {
+            return;
+        };
+        self.led_seq = self.led_seq.wrapping_add(1);
+        // UDP 不保证送达，发送失败时丢弃这一帧
+        let rgb = self.led_layout.apply(board, rgb);
+        let _ = socket.send_to(&led_packet(self.led_seq, board, &rgb), peer);
+    }
+}
+
+impl ReconnectDriver for NetworkIO {
+    fn take_reconnected(&mut self) -> bool {
+        std::mem::take(&mut self.reconnected)
+    }
+}
+
+#[cfg(test)]
+mod network_test {
+    use std::thread;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::led_layout::{LedRoute, LedZone};
+
+    fn network_io(timeout_ms: u64) -> (NetworkIO, UdpSocket) {
+        let config = NetworkConfig {
+            enabled: true,
+            bind: "127.0.0.1".to_string(),
+            port: 0,
+            timeout_ms,
+            ..Config::default().network
+        };
+        let (io, _) = NetworkIO::new(config);
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.connect(io.socket.as_ref().unwrap().local_addr().unwrap())
+            .unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        (io, peer)
+    }
+
+    /// 手台一侧的编码
+    fn input_packet(seq: u32, input: &InputSnapshot) -> Vec<u8> {
+        let mut buf = header(KIND_INPUT, seq);
+        buf.extend([input.op_btns, input.left_btns, input.right_btns, 0]);
+        buf.extend(input.lever.to_le_bytes());
+        buf
+    }
+
+    fn input(left_btns: u8, lever: i16) -> InputSnapshot {
+        InputSnapshot {
+            op_btns: 0,
+            left_btns,
+            right_btns: 0,
+            lever,
+        }
+    }
+
+    /// 本机回环也需要一点时间
+    fn settle() {
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    #[test]
+    fn loopback_test() {
+        let (mut io, peer) = network_io(1000);
+        assert!(matches!(
+            io.poll(),
+            Err(DriverError::Network(NetworkError::NoPeer))
+        ));
+
+        let sent = InputSnapshot {
+            op_btns: 0x04,
+            left_btns: 0x11,
+            right_btns: 0x0A,
+            lever: -1234,
+        };
+        peer.send(&input_packet(1, &sent)).unwrap();
+        // 格式不对的包被忽略
+        peer.send(b"hello").unwrap();
+        settle();
+        io.poll().unwrap();
+        assert_eq!(
+            (io.op_btns(), io.left_btns(), io.right_btns(), io.lever()),
+            (0x04, 0x11, 0x0A, -1234)
+        );
+
+        // LED 发回最近的手台
+        let mut rgb = [rgb::RGB8::default(); 6];
+        rgb[5] = rgb::RGB8::new(1, 2, 3);
+        io.set_led_new(1, &rgb);
+        let mut buf = [0u8; 256];
+        let n = peer.recv(&mut buf).unwrap();
+        assert_eq!(buf[..2], MAGIC);
+        assert_eq!(buf[2], KIND_LED);
+        assert_eq!(buf[HEADER_LEN..HEADER_LEN + 2], [1, 6]);
+        assert_eq!(n, HEADER_LEN + 2 + 18);
+        assert_eq!(buf[n - 3..n], [1, 2, 3]);
+    }
+
+    #[test]
+    fn led_route_test() {
+        let (mut io, peer) = network_io(1000);
+        io.led_layout = LedLayout::new(vec![LedRoute {
+            zone: LedZone::LeftSide,
+            offset: 0,
+            count: Some(4),
+            reverse: false,
+        }]);
+        peer.send(&input_packet(1, &input(0, 0))).unwrap();
+        settle();
+        io.poll().unwrap();
+
+        let mut board0 = [rgb::RGB8::default(); 61];
+        board0[1] = rgb::RGB8::new(1, 2, 3);
+        io.set_led_new(0, &board0);
+        let mut buf = [0u8; 256];
+        let n = peer.recv(&mut buf).unwrap();
+        assert_eq!(buf[HEADER_LEN..HEADER_LEN + 2], [0, 4]);
+        assert_eq!(buf[HEADER_LEN + 2..n], [0, 0, 0, 0, 0, 0, 1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn sequence_test() {
+        let (mut io, peer) = network_io(1000);
+        peer.send(&input_packet(u32::MAX - 1, &input(0x01, 0)))
+            .unwrap();
+        // 乱序到达的旧包被丢弃
+        peer.send(&input_packet(u32::MAX - 2, &input(0x02, 0)))
+            .unwrap();
+        settle();
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), 0x01);
+
+        // 序号回绕
+        peer.send(&input_packet(u32::MAX, &input(0x04, 0))).unwrap();
+        peer.send(&input_packet(0, &input(0x08, 0))).unwrap();
+        peer.send(&input_packet(u32::MAX, &input(0x10, 0))).unwrap();
+        settle();
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), 0x08);
+    }
+
+    #[test]
+    fn timeout_test() {
+        let (mut io, peer) = network_io(50);
+        peer.send(&input_packet(100, &input(0x1F, 100))).unwrap();
+        settle();
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), 0x1F);
+
+        thread::sleep(Duration::from_millis(100));
+        let err = io.poll().unwrap_err();
+        assert!(matches!(err, DriverError::Network(NetworkError::Timeout)));
+        assert_eq!((io.left_btns(), io.lever()), (0, 0));
+
+        // 手台重启后序号从头开始
+        peer.send(&input_packet(1, &input(0x02, 0))).unwrap();
+        settle();
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), 0x02);
+    }
+}
@@ -0,0 +1,339 @@
+//! OpenRGB SDK 客户端
+//!
+//! 连接和发送都在后台线程中进行，服务器未启动或响应慢时不会阻塞游戏线程。
+//! 断开后按 `reconnect_ms` 重连，重连后重新发送每个目标最近的颜色。
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dyn_dyn::dyn_dyn_impl;
+use rgb::RGB8;
+
+use crate::{
+    config::{OpenRgbConfig, OpenRgbTarget},
+    led_layout::LedLayout,
+    led_legacy::LegacyLedState,
+};
+
+use super::{Driver, LEDriver, LEDriverNew};
+
+pub const MAGIC: [u8; 4] = *b"ORGB";
+pub const SET_CLIENT_NAME: u32 = 50;
+pub const UPDATE_LEDS: u32 = 1050;
+pub const UPDATE_ZONE_LEDS: u32 = 1051;
+pub const SET_CUSTOM_MODE: u32 = 1100;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 16 字节包头 `ORGB 设备序号 包类型 数据长度`（小端）加数据
+pub fn packet(device: u32, id: u32, data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16 + data.len());
+    buf.extend(MAGIC);
+    buf.extend(device.to_le_bytes());
+    buf.extend(id.to_le_bytes());
+    buf.extend((data.len() as u32).to_le_bytes());
+    buf.extend(data);
+    buf
+}
+
+/// 更新整个设备或一个区域的颜色，每个颜色为 `r g b 0`
+pub fn update_packet(target: &OpenRgbTarget, rgb: &[RGB8]) -> Vec<u8> {
+    let rgb = &rgb[..rgb.len().min(usize::from(u16::MAX))];
+    let mut data = vec![0u8; 4];
+    if let Some(zone) = target.zone {
+        data.extend(zone.to_le_bytes());
+    }
+    data.extend((rgb.len() as u16).to_le_bytes());
+    data.extend(rgb.iter().flat_map(|c| [c.r, c.g, c.b, 0]));
+    // 数据开头的长度包含自身
+    let len = data.len() as u32;
+    data[..4].copy_from_slice(&len.to_le_bytes());
+    let id = match target.zone {
+        Some(_) => UPDATE_ZONE_LEDS,
+        None => UPDATE_LEDS,
+    };
+    packet(target.device, id, &data)
+}
+
+/// 连接后先发送的客户端名称和自定义模式
+fn handshake(config: &OpenRgbConfig) -> Vec<u8> {
+    let mut name = config.client_name.clone().into_bytes();
+    name.push(0);
+    let mut buf = packet(0, SET_CLIENT_NAME, &name);
+    if config.custom_mode {
+        let devices: BTreeSet<u32> = config.targets.iter().map(|t| t.device).collect();
+        for device in devices {
+            buf.extend(packet(device, SET_CUSTOM_MODE, &[]));
+        }
+    }
+    buf
+}
+
+fn connect(config: &OpenRgbConfig) -> io::Result<TcpStream> {
+    let addr = (config.host.as_str(), config.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "地址为空"))?;
+    let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    Ok(stream)
+}
+
+/// 后台线程，`frames` 为每个目标最近一次的更新包
+fn run(config: OpenRgbConfig, receiver: Receiver<(usize, Vec<u8>)>) {
+    let reconnect = Duration::from_millis(config.reconnect_ms);
+    let mut frames: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+    // 连续失败时只打印第一次
+    let mut failed = false;
+    loop {
+        let connected = connect(&config).and_then(|mut stream| {
+            stream.write_all(&handshake(&config))?;
+            for frame in frames.values() {
+                stream.write_all(frame)?;
+            }
+            Ok(stream)
+        });
+        match connected {
+            Ok(mut stream) => {
+                println!("Ongeki IO OpenRGB: 已连接 {}:{}", config.host, config.port);
+                failed = false;
+                // 发送端关闭时退出
+                let Some(e) = forward(&mut stream, &receiver, &mut frames) else {
+                    return;
+                };
+                println!("Ongeki IO OpenRGB: 连接断开 {e}");
+            }
+            Err(e) if !failed => {
+                println!(
+                    "Ongeki IO OpenRGB: 无法连接 {}:{} {e}",
+                    config.host, config.port
+                );
+                failed = true;
+            }
+            Err(_) => {}
+        }
+
+        // 等待重连期间继续接收，重连后发送最新的颜色
+        let deadline = Instant::now() + reconnect;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok((i, frame)) => {
+                    frames.insert(i, frame);
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+}
+
+/// 转发更新直到写入失败，积压的更新只发送每个目标最新的一个
+fn forward(
+    stream: &mut TcpStream,
+    receiver: &Receiver<(usize, Vec<u8>)>,
+    frames: &mut BTreeMap<usize, Vec<u8>>,
+) -> Option<io::Error> {
+    loop {
+        let (i, frame) = receiver.recv().ok()?;
+        let mut updated = BTreeSet::from([i]);
+        frames.insert(i, frame);
+        while let Ok((i, frame)) = receiver.try_recv() {
+            updated.insert(i);
+            frames.insert(i, frame);
+        }
+        for i in updated {
+            if let Err(e) = stream.write_all(&frames[&i]) {
+                return Some(e);
+            }
+        }
+    }
+}
+
+pub struct OpenRgbIO {
+    targets: Vec<(OpenRgbTarget, LedLayout)>,
+    /// 后台线程启动失败时为 `None`，释放时后台线程随之退出
+    sender: Option<Sender<(usize, Vec<u8>)>>,
+}
+
+#[dyn_dyn_impl(Driver, LEDriver, LEDriverNew)]
+impl Driver for OpenRgbIO {}
+
+impl OpenRgbIO {
+    pub fn new(config: OpenRgbConfig) -> Self {
+        let targets = config
+            .targets
+            .iter()
+            .map(|t| (t.clone(), LedLayout::new(t.led_routes.clone())))
+            .collect();
+        let (sender, receiver) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name("ongeki-io-openrgb".to_string())
+            .spawn(move || run(config, receiver));
+        if let Err(e) = &spawned {
+            println!("Ongeki IO OpenRGB: 无法启动后台线程 {e}");
+        }
+        Self {
+            targets,
+            sender: spawned.ok().map(|_| sender),
+        }
+    }
+}
+
+impl LEDriver for OpenRgbIO {
+    fn set_led(&mut self, data: u32) {
+        self.set_led_new(1, &LegacyLedState(data).colors(&Default::default()));
+    }
+}
+
+impl LEDriverNew for OpenRgbIO {
+    fn set_led_new(&mut self, board: u8, rgb: &[RGB8]) {
+        let Some(ref sender) = self.sender else {
+            return;
+        };
+        for (i, (target, layout)) in self.targets.iter().enumerate() {
+            if target.board == board {
+                let _ = sender.send((i, update_packet(target, &layout.apply(board, rgb))));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod openrgb_test {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::led_layout::{LedRoute, LedZone};
+
+    /// 读取一个包，返回设备序号、包类型和数据
+    fn read_packet(stream: &mut TcpStream) -> (u32, u32, Vec<u8>) {
+        let mut header = [0u8; 16];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[..4], MAGIC);
+        let field = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        let mut data = vec![0u8; field(12) as usize];
+        stream.read_exact(&mut data).unwrap();
+        (field(4), field(8), data)
+    }
+
+    fn configure(stream: TcpStream) -> TcpStream {
+        stream.set_nonblocking(false).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        stream
+    }
+
+    fn colors(r: u8, count: usize) -> Vec<RGB8> {
+        vec![RGB8::new(r, 2, 3); count]
+    }
+
+    #[test]
+    fn packet_test() {
+        let target = OpenRgbTarget {
+            device: 4,
+            zone: Some(2),
+            board: 1,
+            led_routes: vec![],
+        };
+        let buf = update_packet(&target, &colors(1, 2));
+        assert_eq!(buf[4..12], [4, 0, 0, 0, 0x1B, 0x04, 0, 0]);
+        assert_eq!(buf[12..16], [18, 0, 0, 0]);
+        assert_eq!(
+            buf[16..],
+            [18, 0, 0, 0, 2, 0, 0, 0, 2, 0, 1, 2, 3, 0, 1, 2, 3, 0]
+        );
+
+        let target = OpenRgbTarget {
+            zone: None,
+            ..target
+        };
+        let buf = update_packet(&target, &colors(1, 1));
+        assert_eq!(buf[8..12], 1050u32.to_le_bytes());
+        assert_eq!(buf[16..], [10, 0, 0, 0, 1, 0, 1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn stub_server_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = OpenRgbConfig {
+            enabled: true,
+            port: listener.local_addr().unwrap().port(),
+            reconnect_ms: 20,
+            targets: vec![
+                OpenRgbTarget {
+                    device: 3,
+                    zone: Some(1),
+                    board: 1,
+                    led_routes: vec![],
+                },
+                OpenRgbTarget {
+                    device: 0,
+                    zone: None,
+                    board: 0,
+                    led_routes: vec![LedRoute {
+                        zone: LedZone::RightSide,
+                        offset: 0,
+                        count: None,
+                        reverse: false,
+                    }],
+                },
+            ],
+            ..Config::default().openrgb
+        };
+        let mut io = OpenRgbIO::new(config);
+        let mut stream = configure(listener.accept().unwrap().0);
+
+        let (_, id, name) = read_packet(&mut stream);
+        assert_eq!((id, &name[..]), (SET_CLIENT_NAME, &b"ongeki-io\0"[..]));
+        assert_eq!(read_packet(&mut stream), (0, SET_CUSTOM_MODE, vec![]));
+        assert_eq!(read_packet(&mut stream), (3, SET_CUSTOM_MODE, vec![]));
+
+        io.set_led_new(1, &colors(10, 6));
+        let (device, id, data) = read_packet(&mut stream);
+        assert_eq!((device, id), (3, UPDATE_ZONE_LEDS));
+        assert_eq!(data[8..10], [6, 0]);
+        assert_eq!(data[10..14], [10, 2, 3, 0]);
+
+        let mut board0 = colors(0, 61);
+        board0[60] = RGB8::new(20, 2, 3);
+        io.set_led_new(0, &board0);
+        let (device, id, data) = read_packet(&mut stream);
+        assert_eq!((device, id), (0, UPDATE_LEDS));
+        assert_eq!(data[4..], [2, 0, 0, 2, 3, 0, 20, 2, 3, 0]);
+
+        // 断开后自动重连，并重新发送最近的颜色
+        drop(stream);
+        listener.set_nonblocking(true).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut stream = loop {
+            io.set_led_new(1, &colors(30, 6));
+            match listener.accept() {
+                Ok((stream, _)) => break configure(stream),
+                Err(_) => {
+                    assert!(Instant::now() < deadline, "没有重连");
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        };
+        assert_eq!(read_packet(&mut stream).1, SET_CLIENT_NAME);
+        let mut zone_colors = vec![];
+        let mut board0_resent = false;
+        while zone_colors.last() != Some(&30) || !board0_resent {
+            let (device, id, data) = read_packet(&mut stream);
+            match (device, id) {
+                (3, UPDATE_ZONE_LEDS) => zone_colors.push(data[10]),
+                (0, UPDATE_LEDS) => board0_resent = data[10] == 20,
+                _ => {}
+            }
+        }
+    }
+}
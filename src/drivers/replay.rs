@@ -0,0 +1,174 @@
+use std::time::Instant;
+
+use dyn_dyn::dyn_dyn_impl;
+
+use crate::{
+    config::{ReplayConfig, ReplayMode},
+    ipc::InputSnapshot,
+    recording::{InputRecord, Recording},
+};
+
+use super::{ButtonDriver, Driver, DriverError, LeverDriver, PollDriver};
+
+/// 代替真实硬件回放录制的输入
+pub struct ReplayIO {
+    records: Vec<InputRecord>,
+    mode: ReplayMode,
+    looping: bool,
+    /// 下一条要回放的记录
+    next: usize,
+    /// 实时模式下第一次 poll 的时间，循环时重新计时
+    start: Option<Instant>,
+    input: InputSnapshot,
+    finished: bool,
+}
+
+#[dyn_dyn_impl(Driver, PollDriver, ButtonDriver, LeverDriver)]
+impl Driver for ReplayIO {}
+
+impl ReplayIO {
+    pub fn new(config: &ReplayConfig, recording: Recording) -> Self {
+        Self {
+            records: recording.records,
+            mode: config.mode,
+            looping: config.looping,
+            next: 0,
+            start: None,
+            input: InputSnapshot::default(),
+            finished: false,
+        }
+    }
+
+    fn poll_at(&mut self, now: Instant) {
+        if self.next >= self.records.len() {
+            if self.looping && !self.records.is_empty() {
+                self.next = 0;
+                self.start = None;
+            } else {
+                // 回放结束后松开所有按键
+                if !self.finished {
+                    println!("Ongeki IO Replay: 回放结束");
+                    self.finished = true;
+                }
+                self.input = InputSnapshot::default();
+                return;
+            }
+        }
+
+        match self.mode {
+            ReplayMode::Step => {
+                self.input = self.records[self.next].input();
+                self.next += 1;
+            }
+            // 取已经到时间的最后一条
+            ReplayMode::Realtime => {
+                let start = *self.start.get_or_insert(now);
+                let elapsed_us = u64::try_from((now - start).as_micros()).unwrap_or(u64::MAX);
+                while let Some(record) = self.records.get(self.next) {
+                    if record.time_us > elapsed_us {
+                        break;
+                    }
+                    self.input = record.input();
+                    self.next += 1;
+                }
+            }
+        }
+    }
+}
+
+impl PollDriver for ReplayIO {
+    fn poll(&mut self) -> Result<(), DriverError> {
+        self.poll_at(Instant::now());
+        Ok(())
+    }
+}
+
+impl ButtonDriver for ReplayIO {
+    fn op_btns(&self) -> u8 {
+        self.input.op_btns
+    }
+
+    fn left_btns(&self) -> u8 {
+        self.input.left_btns
+    }
+
+    fn right_btns(&self) -> u8 {
+        self.input.right_btns
+    }
+}
+
+impl LeverDriver for ReplayIO {
+    fn lever(&self) -> i16 {
+        self.input.lever
+    }
+}
+
+#[cfg(test)]
+mod replay_test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::config::Config;
+
+    fn recording() -> Recording {
+        let record = |time_us, left_btns, lever| InputRecord {
+            time_us,
+            op_btns: 0,
+            left_btns,
+            right_btns: 0,
+            lever,
+        };
+        Recording {
+            records: vec![
+                record(0, 0x01, -100),
+                record(1000, 0x02, 0),
+                record(5000, 0x04, 100),
+            ],
+        }
+    }
+
+    fn replay(mode: ReplayMode, looping: bool) -> ReplayIO {
+        let config = ReplayConfig {
+            enabled: true,
+            mode,
+            looping,
+            ..Config::default().replay
+        };
+        ReplayIO::new(&config, recording())
+    }
+
+    #[test]
+    fn step_test() {
+        let mut io = replay(ReplayMode::Step, false);
+        let mut polled = vec![];
+        for _ in 0..4 {
+            io.poll().unwrap();
+            polled.push((io.left_btns(), io.lever()));
+        }
+        assert_eq!(polled, [(0x01, -100), (0x02, 0), (0x04, 100), (0, 0)]);
+
+        let mut io = replay(ReplayMode::Step, true);
+        for _ in 0..4 {
+            io.poll().unwrap();
+        }
+        assert_eq!(io.left_btns(), 0x01);
+    }
+
+    #[test]
+    fn realtime_test() {
+        let mut io = replay(ReplayMode::Realtime, true);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        io.poll_at(start);
+        assert_eq!(io.left_btns(), 0x01);
+        // 间隔内多条记录只保留最后一条
+        io.poll_at(at(6));
+        assert_eq!((io.left_btns(), io.lever()), (0x04, 100));
+        // 循环时从头重新计时
+        io.poll_at(at(7));
+        assert_eq!(io.left_btns(), 0x01);
+        io.poll_at(at(8));
+        assert_eq!(io.left_btns(), 0x02);
+    }
+}
@@ -0,0 +1,426 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use dyn_dyn::dyn_dyn_impl;
+use serialport::SerialPort;
+
+use crate::{
+    config::{SerialConfig, SerialProtocol},
+    enums::{HResult, ERROR_DEVICE_NOT_CONNECTED},
+    led_layout::LedLayout,
+};
+
+use super::{
+    ButtonDriver, Driver, DriverError, LEDriver, LEDriverNew, LeverDriver, PollDriver,
+    ReconnectDriver,
+};
+
+use self::protocol::{Frame, FrameDecoder, CMD_INPUT, CMD_LED_COLORS, CMD_LED_LEGACY};
+
+mod protocol;
+
+/// 每次 poll 最多读取的次数，避免设备持续发送时卡住
+const MAX_READS_PER_POLL: usize = 16;
+/// 未连接时两次打开串口的最小间隔
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum SerialError {
+    Open(serialport::Error),
+    Disconnected(io::Error),
+}
+
+impl SerialError {
+    pub fn hresult(&self) -> HResult {
+        match self {
+            SerialError::Open(e) => match e.kind() {
+                serialport::ErrorKind::NoDevice
+                | serialport::ErrorKind::Io(io::ErrorKind::NotFound) => {
+                    HResult::from_win32(ERROR_DEVICE_NOT_CONNECTED)
+                }
+                _ => HResult::E_HANDLE,
+            },
+            SerialError::Disconnected(_) => HResult::from_win32(ERROR_DEVICE_NOT_CONNECTED),
+        }
+    }
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SerialError::Open(e) => write!(f, "Ongeki IO Serial: 无法打开串口 {e}"),
+            SerialError::Disconnected(e) => write!(f, "Ongeki IO Serial: 设备断开 {e}"),
+        }
+    }
+}
+
+pub struct SerialIO {
+    op_btns: u8,
+    left_btns: u8,
+    right_btns: u8,
+    lever: i16,
+    config: SerialConfig,
+    port: Option<Box<dyn SerialPort>>,
+    /// 上一次尝试打开串口的时间，用于限制重连频率
+    last_connect: Option<Instant>,
+    /// 上一次打开失败的原因，重连间隔内再次返回
+    open_error: Option<serialport::Error>,
+    decoder: FrameDecoder,
+    /// 已经报告过的丢弃帧数
+    dropped: u32,
+    /// 连接后还没有通知重新发送 LED
+    reconnected: bool,
+    led_layout: LedLayout,
+}
+
+#[dyn_dyn_impl(
+    Driver,
+    PollDriver,
+    ButtonDriver,
+    LeverDriver,
+    LEDriver,
+    LEDriverNew,
+    ReconnectDriver
+)]
+impl Driver for SerialIO {}
+unsafe impl Sync for SerialIO {}
+
+impl SerialIO {
+    /// 同时返回首次打开串口的结果，未打开时之后 poll 会继续尝试
+    pub fn new(config: SerialConfig) -> (Self, Result<(), SerialError>) {
+        let mut s = SerialIO {
+            op_btns: 0,
+            left_btns: 0,
+            right_btns: 0,
+            lever: 0,
+            led_layout: LedLayout::new(config.led_routes.clone()),
+            config,
+            port: None,
+            last_connect: None,
+            open_error: None,
+            decoder: FrameDecoder::default(),
+            dropped: 0,
+            reconnected: false,
+        };
+        let status = s.reconnect(Instant::now());
+        (s, status)
+    }
+
+    /// 距离上一次尝试不足 `RECONNECT_INTERVAL` 时不打开串口
+    fn reconnect(&mut self, now: Instant) -> Result<(), SerialError> {
+        if self
+            .last_connect
+            .is_some_and(|t| now.saturating_duration_since(t) < RECONNECT_INTERVAL)
+        {
+            let e = self.open_error.clone().unwrap_or_else(|| {
+                serialport::Error::new(serialport::ErrorKind::NoDevice, "等待重连")
+            });
+            return Err(SerialError::Open(e));
+        }
+        self.last_connect = Some(now);
+        let result = self.try_connect_port();
+        self.open_error = match &result {
+            Err(SerialError::Open(e)) => Some(e.clone()),
+            _ => None,
+        };
+        result
+    }
+
+    fn try_connect_port(&mut self) -> Result<(), SerialError> {
+        // 超时为 0，读写都不阻塞游戏线程
+        let port = serialport::new(&self.config.port, self.config.baud_rate)
+            .timeout(Duration::ZERO)
+            .open()
+            .map_err(SerialError::Open)?;
+        println!("Ongeki IO Serial: {} 已连接", self.config.port);
+        self.port = Some(port);
+        self.reconnected = true;
+        self.decoder.reset();
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.port = None;
+        self.op_btns = 0;
+        self.left_btns = 0;
+        self.right_btns = 0;
+    }
+
+    fn handle_frame(&mut self, frame: Frame) {
+        if frame.cmd != CMD_INPUT || frame.payload.len() < 5 {
+            return;
+        }
+        let data = &frame.payload;
+        self.op_btns = data[0] & 0x07;
+        self.left_btns = data[1] & 0x1F;
+        self.right_btns = data[2] & 0x1F;
+        self.lever = i16::from_le_bytes([data[3], data[4]]);
+    }
+
+    fn write_frame(&mut self, cmd: u8, payload: &[u8]) {
+        let Some(ref mut port) = self.port else {
+            // 连接失败由 poll 报告
+            let _ = self.reconnect(Instant::now());
+            return;
+        };
+
+        let data = match self.config.protocol {
+            SerialProtocol::Framed => protocol::encode(cmd, payload),
+        };
+        match port.write_all(&data) {
+            // 发送缓冲区已满时丢弃这一帧，设备会在下一帧重新同步
+            Err(e) if is_idle(&e) => {}
+            Err(e) => {
+                println!("Ongeki IO Serial: 设备断开 {e}");
+                self.disconnect();
+            }
+            Ok(()) => {}
+        }
+    }
+}
+
+impl PollDriver for SerialIO {
+    fn poll(&mut self) -> Result<(), DriverError> {
+        let Some(ref mut port) = self.port else {
+            return Ok(self.reconnect(Instant::now())?);
+        };
+
+        let mut buf = [0u8; 256];
+        let mut frames = vec![];
+        for _ in 0..MAX_READS_PER_POLL {
+            match port.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => match self.config.protocol {
+                    SerialProtocol::Framed => frames.extend(self.decoder.decode(&buf[..n])),
+                },
+                // 没有更多数据
+                Err(e) if is_idle(&e) => break,
+                Err(e) => {
+                    self.disconnect();
+                    return Err(SerialError::Disconnected(e).into());
+                }
+            }
+        }
+        // 没有新数据时保持上一次的状态，多帧时以最后一帧为准
+        for frame in frames {
+            self.handle_frame(frame);
+        }
+
+        if self.decoder.dropped != self.dropped {
+            println!("Ongeki IO Serial: 已丢弃 {} 个错误帧", self.decoder.dropped);
+            self.dropped = self.decoder.dropped;
+        }
+        Ok(())
+    }
+}
+
+fn is_idle(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+impl ButtonDriver for SerialIO {
+    fn op_btns(&self) -> u8 {
+        self.op_btns
+    }
+
+    fn left_btns(&self) -> u8 {
+        self.left_btns
+    }
+
+    fn right_btns(&self) -> u8 {
+        self.right_btns
+    }
+}
+
+impl LeverDriver for SerialIO {
+    fn lever(&self) -> i16 {
+        self.lever
+    }
+}
+
+impl LEDriver for SerialIO {
+    fn set_led(&mut self, data: u32) {
+        self.write_frame(CMD_LED_LEGACY, &data.to_le_bytes());
+    }
+}
+
+impl LEDriverNew for SerialIO {
+    fn set_led_new(&mut self, board: u8, rgb: &[rgb::RGB8]) {
+        let mut payload = vec![board];
+        let rgb = self.led_layout.apply(board, rgb);
+        payload.extend(rgb.iter().flat_map(|c| [c.r, c.g, c.b]));
+        self.write_frame(CMD_LED_COLORS, &payload);
+    }
+}
+
+impl ReconnectDriver for SerialIO {
+    fn take_reconnected(&mut self) -> bool {
+        std::mem::take(&mut self.reconnected)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod serial_test {
+    use std::path::PathBuf;
+    use std::thread;
+
+    use serialport::TTYPort;
+
+    use super::protocol::{FrameDecoder, SYNC};
+    use super::*;
+    use crate::config::Config;
+    use crate::enums::{GameBtn, OpBtn};
+
+    /// 伪终端对，主端作为设备，驱动通过固定的符号链接打开从端
+    struct FakeDevice {
+        master: TTYPort,
+        link: PathBuf,
+    }
+
+    impl FakeDevice {
+        fn plug(link: PathBuf) -> Self {
+            let (master, slave) = TTYPort::pair().unwrap();
+            let _ = std::fs::remove_file(&link);
+            std::os::unix::fs::symlink(slave.name().unwrap(), &link).unwrap();
+            Self { master, link }
+        }
+
+        fn send_input(&mut self, op: u8, left: u8, right: u8, lever: i16) {
+            let [lo, hi] = lever.to_le_bytes();
+            let frame = protocol::encode(CMD_INPUT, &[op, left, right, lo, hi]);
+            self.master.write_all(&frame).unwrap();
+        }
+
+        fn read_frames(&mut self) -> Vec<Frame> {
+            let mut buf = [0u8; 1024];
+            let n = self.master.read(&mut buf).unwrap();
+            FrameDecoder::default().decode(&buf[..n])
+        }
+    }
+
+    fn serial_io(name: &str) -> (SerialIO, FakeDevice) {
+        let link =
+            std::env::temp_dir().join(format!("ongeki-io-serial-{name}-{}", std::process::id()));
+        let device = FakeDevice::plug(link.clone());
+        let config = SerialConfig {
+            enabled: true,
+            port: link.to_string_lossy().into_owned(),
+            ..Config::default().serial
+        };
+        let (io, status) = SerialIO::new(config);
+        assert!(status.is_ok());
+        assert!(io.port.is_some());
+        (io, device)
+    }
+
+    /// 伪终端转发需要一点时间
+    fn settle() {
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    #[test]
+    fn input_test() {
+        let (mut io, mut device) = serial_io("input");
+        io.poll().unwrap();
+        assert_eq!((io.op_btns(), io.left_btns(), io.right_btns()), (0, 0, 0));
+
+        // 杂讯与半帧之后的完整帧仍能解析
+        device.master.write_all(&[0x12, 0x34, SYNC, 5]).unwrap();
+        device.send_input(
+            OpBtn::Test as u8,
+            GameBtn::Btn1 as u8 | GameBtn::Side as u8,
+            0xFF,
+            -1234,
+        );
+        settle();
+        io.poll().unwrap();
+        assert_eq!(io.op_btns(), OpBtn::Test as u8);
+        assert_eq!(io.left_btns(), GameBtn::Btn1 as u8 | GameBtn::Side as u8);
+        assert_eq!(io.right_btns(), 0x1F);
+        assert_eq!(io.lever(), -1234);
+        assert_eq!(io.decoder.dropped, 1);
+
+        // 没有新数据时保持状态，多帧时以最后一帧为准
+        io.poll().unwrap();
+        assert_eq!(io.lever(), -1234);
+        device.send_input(0, 0, 0, 100);
+        device.send_input(0, GameBtn::Menu as u8, 0, i16::MAX);
+        settle();
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), GameBtn::Menu as u8);
+        assert_eq!(io.lever(), i16::MAX);
+
+        std::fs::remove_file(&device.link).unwrap();
+    }
+
+    #[test]
+    fn led_test() {
+        let (mut io, mut device) = serial_io("led");
+
+        io.set_led(0x0080_0040);
+        settle();
+        assert_eq!(
+            device.read_frames(),
+            vec![Frame {
+                cmd: CMD_LED_LEGACY,
+                payload: vec![0x40, 0, 0x80, 0],
+            }]
+        );
+
+        let mut rgb = [rgb::RGB8::default(); 6];
+        rgb[0] = rgb::RGB8::new(0xE0, 2, 3);
+        rgb[5] = rgb::RGB8::new(4, 5, 0xD0);
+        io.set_led_new(1, &rgb);
+        settle();
+        let frames = device.read_frames();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].cmd, CMD_LED_COLORS);
+        assert_eq!(frames[0].payload[..4], [1, 0xE0, 2, 3]);
+        assert_eq!(frames[0].payload[16..], [4, 5, 0xD0]);
+
+        std::fs::remove_file(&device.link).unwrap();
+    }
+
+    #[test]
+    fn reconnect_test() {
+        let (mut io, mut device) = serial_io("reconnect");
+        device.send_input(0, 0x1F, 0x1F, 0);
+        settle();
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), 0x1F);
+
+        // 拔出时松开按键并报告断开，之后打开失败
+        let link = device.link.clone();
+        drop(device);
+        let err = io.poll().unwrap_err();
+        assert!(matches!(
+            err,
+            DriverError::Serial(SerialError::Disconnected(_))
+        ));
+        assert_eq!(err.hresult().0 as u32, 0x8007_048F);
+        assert_eq!((io.left_btns(), io.right_btns()), (0, 0));
+        assert!(matches!(
+            io.poll(),
+            Err(DriverError::Serial(SerialError::Open(_)))
+        ));
+
+        // 重新插入后，过了重连间隔的下一次 poll 才重连
+        let mut device = FakeDevice::plug(link);
+        let now = Instant::now();
+        io.last_connect = Some(now);
+        assert!(io.reconnect(now + RECONNECT_INTERVAL / 2).is_err());
+        assert!(io.port.is_none());
+        io.last_connect = None;
+        io.poll().unwrap();
+        device.send_input(0, GameBtn::Btn3 as u8, 0, 0);
+        settle();
+        io.poll().unwrap();
+        assert_eq!(io.left_btns(), GameBtn::Btn3 as u8);
+
+        std::fs::remove_file(&device.link).unwrap();
+    }
+}
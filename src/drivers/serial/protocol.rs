@@ -0,0 +1,168 @@
+//! 串口帧协议
+//!
+//! 每帧为 `SYNC len cmd payload[len] sum`，`sum` 为 `len`、`cmd` 与 `payload` 之和的低 8 位。
+//! `SYNC` 之后出现的 `SYNC` 或 `ESCAPE` 以 `ESCAPE, b - 1` 发送，
+//! 因此任何位置读到 `SYNC` 都是新帧的开始，出错后在下一帧自动重新同步。
+
+pub const SYNC: u8 = 0xE0;
+pub const ESCAPE: u8 = 0xD0;
+
+/// 设备 -> 主机：`op left right lever_lo lever_hi`
+pub const CMD_INPUT: u8 = 0x01;
+/// 主机 -> 设备：`board rgb...`
+pub const CMD_LED_COLORS: u8 = 0x10;
+/// 主机 -> 设备：旧版 LED 位域，u32 小端
+pub const CMD_LED_LEGACY: u8 = 0x11;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub cmd: u8,
+    pub payload: Vec<u8>,
+}
+
+/// 编码一帧，超过 255 字节的负载被截断
+pub fn encode(cmd: u8, payload: &[u8]) -> Vec<u8> {
+    let payload = &payload[..payload.len().min(usize::from(u8::MAX))];
+    let len = payload.len() as u8;
+    let sum = payload
+        .iter()
+        .fold(len.wrapping_add(cmd), |s, b| s.wrapping_add(*b));
+
+    let mut buf = Vec::with_capacity(payload.len() + 4);
+    buf.push(SYNC);
+    for b in [len, cmd].iter().chain(payload).chain([&sum]) {
+        if *b == SYNC || *b == ESCAPE {
+            buf.extend([ESCAPE, b - 1]);
+        } else {
+            buf.push(*b);
+        }
+    }
+    buf
+}
+
+/// 逐字节解码，丢弃校验错误和被截断的帧
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    synced: bool,
+    escape: bool,
+    /// 累计丢弃的帧数
+    pub dropped: u32,
+}
+
+impl FrameDecoder {
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.synced = false;
+        self.escape = false;
+    }
+
+    pub fn push(&mut self, byte: u8) -> Option<Frame> {
+        if byte == SYNC {
+            if self.synced && !self.buf.is_empty() {
+                self.dropped += 1;
+            }
+            self.reset();
+            self.synced = true;
+            return None;
+        }
+        // 等待下一个 SYNC
+        if !self.synced {
+            return None;
+        }
+        if byte == ESCAPE {
+            self.escape = true;
+            return None;
+        }
+
+        let byte = if self.escape {
+            byte.wrapping_add(1)
+        } else {
+            byte
+        };
+        self.escape = false;
+        self.buf.push(byte);
+
+        let total = usize::from(self.buf[0]) + 3;
+        if self.buf.len() < total {
+            return None;
+        }
+        let (body, sum) = self.buf.split_at(total - 1);
+        let frame = (body.iter().fold(0u8, |s, b| s.wrapping_add(*b)) == sum[0]).then(|| Frame {
+            cmd: body[1],
+            payload: body[2..].to_vec(),
+        });
+        if frame.is_none() {
+            self.dropped += 1;
+        }
+        self.reset();
+        frame
+    }
+
+    pub fn decode(&mut self, data: &[u8]) -> Vec<Frame> {
+        data.iter().filter_map(|b| self.push(*b)).collect()
+    }
+}
+
+#[cfg(test)]
+mod protocol_test {
+    use super::*;
+
+    #[test]
+    fn round_trip_test() {
+        let payload = [SYNC, 1, ESCAPE, 0xFF, 0];
+        let data = encode(CMD_INPUT, &payload);
+        assert_eq!(data[0], SYNC);
+        assert!(!data[1..].contains(&SYNC));
+
+        let mut decoder = FrameDecoder::default();
+        assert_eq!(
+            decoder.decode(&data),
+            vec![Frame {
+                cmd: CMD_INPUT,
+                payload: payload.to_vec(),
+            }]
+        );
+        assert_eq!(decoder.dropped, 0);
+
+        // 负载之和恰好需要转义
+        let data = encode(0x20, &[0xBF]);
+        assert_eq!(data, [SYNC, 1, 0x20, 0xBF, ESCAPE, 0xDF]);
+        assert_eq!(decoder.decode(&data)[0].payload, [0xBF]);
+    }
+
+    #[test]
+    fn resync_test() {
+        let frame = encode(CMD_INPUT, &[1, 2, 3, 4, 5]);
+        let mut decoder = FrameDecoder::default();
+
+        // 开头的半帧和杂讯被忽略
+        let mut data = vec![3, 4, 5, 0x55];
+        data.extend(&frame);
+        // 被截断的帧在下一个 SYNC 处丢弃
+        data.extend(&frame[..4]);
+        data.extend(&frame);
+        // 校验错误
+        let mut bad = frame.clone();
+        bad[4] ^= 0x01;
+        data.extend(&bad);
+        data.extend(&frame);
+
+        let frames = decoder.decode(&data);
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|f| f.payload == [1, 2, 3, 4, 5]));
+        assert_eq!(decoder.dropped, 2);
+    }
+
+    #[test]
+    fn split_test() {
+        let frame = encode(CMD_LED_COLORS, &[0xE0; 184]);
+        let mut decoder = FrameDecoder::default();
+        let mut frames = vec![];
+        for chunk in frame.chunks(7) {
+            frames.extend(decoder.decode(chunk));
+        }
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].payload.len(), 184);
+    }
+}
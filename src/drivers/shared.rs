@@ -0,0 +1,127 @@
+use std::fmt;
+
+use crate::{
+    enums::{HResult, ERROR_TIMEOUT},
+    ipc::{self, Channel, InputSnapshot, LedCursor, LedFrame},
+};
+
+use super::{ButtonDriver, Driver, DriverError, LEDriver, LEDriverNew, LeverDriver, PollDriver};
+
+use dyn_dyn::dyn_dyn_impl;
+
+#[derive(Debug)]
+pub enum SharedError {
+    /// 主进程超过 `timeout_ms` 没有更新心跳
+    OwnerTimeout,
+}
+
+impl SharedError {
+    pub fn hresult(&self) -> HResult {
+        match self {
+            SharedError::OwnerTimeout => HResult::from_win32(ERROR_TIMEOUT),
+        }
+    }
+}
+
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SharedError::OwnerTimeout => write!(f, "Ongeki IO Shared: 主进程无响应"),
+        }
+    }
+}
+
+/// 子进程使用的驱动：从共享内存读取主进程的输入，并把 LED 数据转发给主进程
+pub struct SharedIO {
+    input: InputSnapshot,
+    owner_alive: bool,
+    timeout_ms: u64,
+    channel: Channel,
+}
+
+#[dyn_dyn_impl(Driver, PollDriver, ButtonDriver, LeverDriver, LEDriver, LEDriverNew)]
+impl Driver for SharedIO {}
+
+impl SharedIO {
+    pub fn new(channel: Channel, timeout_ms: u64) -> Self {
+        Self {
+            input: InputSnapshot::default(),
+            owner_alive: true,
+            timeout_ms,
+            channel,
+        }
+    }
+}
+
+impl PollDriver for SharedIO {
+    fn poll(&mut self) -> Result<(), DriverError> {
+        let alive = self.channel.owner_alive(ipc::now_ms(), self.timeout_ms);
+        if alive && !self.owner_alive {
+            println!("Ongeki IO Shared: 主进程已连接");
+        } else if !alive && self.owner_alive {
+            println!("Ongeki IO Shared: 主进程无响应，子进程不会接管硬件，请重新启动主进程");
+        }
+        self.owner_alive = alive;
+
+        // 主进程无响应时松开所有按键
+        if !alive {
+            self.input = InputSnapshot::default();
+            return Err(SharedError::OwnerTimeout.into());
+        }
+        self.input = self.channel.input();
+        Ok(())
+    }
+}
+
+impl ButtonDriver for SharedIO {
+    fn op_btns(&self) -> u8 {
+        self.input.op_btns
+    }
+
+    fn left_btns(&self) -> u8 {
+        self.input.left_btns
+    }
+
+    fn right_btns(&self) -> u8 {
+        self.input.right_btns
+    }
+}
+
+impl LeverDriver for SharedIO {
+    fn lever(&self) -> i16 {
+        self.input.lever
+    }
+}
+
+impl LEDriver for SharedIO {
+    fn set_led(&mut self, data: u32) {
+        self.channel.send_led(data);
+    }
+}
+
+impl LEDriverNew for SharedIO {
+    fn set_led_new(&mut self, board: u8, rgb: &[rgb::RGB8]) {
+        self.channel.send_led_colors(board, rgb);
+    }
+}
+
+/// 主进程一侧：每次 poll 后发布输入快照并取回子进程的 LED 数据
+pub struct SharedOwner {
+    channel: Channel,
+    cursor: LedCursor,
+}
+
+impl SharedOwner {
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            channel,
+            cursor: LedCursor::default(),
+        }
+    }
+
+    pub fn sync(&mut self, input: InputSnapshot) -> Vec<LedFrame> {
+        self.channel.publish_input(input);
+        self.channel.heartbeat(ipc::now_ms());
+        self.channel.recv_leds(&mut self.cursor)
+    }
+}
@@ -1,4 +1,6 @@
+use std::fmt;
 
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Copy, Clone)]
 #[repr(u8)]
@@ -19,9 +21,82 @@ pub enum GameBtn {
     Menu = 0x10,
 }
 
-#[derive(Debug, Copy, Clone)]
-#[repr(u64)]
-pub enum HResult {
-    Ok = 0x00,
-    Bad = 0x01,
-}
\ No newline at end of file
+pub const ERROR_DEVICE_NOT_CONNECTED: u32 = 1167;
+pub const ERROR_TIMEOUT: u32 = 1460;
+
+/// Windows HRESULT：32 位有符号数，小于 0 表示失败
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct HResult(pub i32);
+
+impl HResult {
+    pub const S_OK: Self = Self(0);
+    pub const S_FALSE: Self = Self(1);
+    pub const E_NOTIMPL: Self = Self(0x8000_4001_u32 as i32);
+    pub const E_POINTER: Self = Self(0x8000_4003_u32 as i32);
+    pub const E_ABORT: Self = Self(0x8000_4004_u32 as i32);
+    pub const E_FAIL: Self = Self(0x8000_4005_u32 as i32);
+    pub const E_UNEXPECTED: Self = Self(0x8000_FFFF_u32 as i32);
+    pub const E_ACCESSDENIED: Self = Self(0x8007_0005_u32 as i32);
+    pub const E_HANDLE: Self = Self(0x8007_0006_u32 as i32);
+    pub const E_OUTOFMEMORY: Self = Self(0x8007_000E_u32 as i32);
+    pub const E_INVALIDARG: Self = Self(0x8007_0057_u32 as i32);
+
+    /// 等同于 `HRESULT_FROM_WIN32`
+    pub const fn from_win32(code: u32) -> Self {
+        if code as i32 <= 0 {
+            Self(code as i32)
+        } else {
+            Self(((code & 0xFFFF) | 0x8007_0000) as i32)
+        }
+    }
+
+    pub const fn is_ok(self) -> bool {
+        self.0 >= 0
+    }
+}
+
+impl fmt::Display for HResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:08X}", self.0 as u32)
+    }
+}
+
+/// XInput 手柄按键，扳机按下超过阈值时视为按键
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[repr(u32)]
+pub enum PadButton {
+    DpadUp = 0x0001,
+    DpadDown = 0x0002,
+    DpadLeft = 0x0004,
+    DpadRight = 0x0008,
+    Start = 0x0010,
+    Back = 0x0020,
+    LeftThumb = 0x0040,
+    RightThumb = 0x0080,
+    LeftShoulder = 0x0100,
+    RightShoulder = 0x0200,
+    A = 0x1000,
+    B = 0x2000,
+    X = 0x4000,
+    Y = 0x8000,
+    LeftTrigger = 0x10000,
+    RightTrigger = 0x20000,
+}
+
+#[cfg(test)]
+mod enums_test {
+    use super::HResult;
+
+    #[test]
+    fn hresult_test() {
+        assert_eq!(HResult::E_FAIL.0 as u32, 0x8000_4005);
+        assert_eq!(HResult::from_win32(0), HResult::S_OK);
+        // ERROR_DEVICE_NOT_CONNECTED
+        assert_eq!(HResult::from_win32(1167).0 as u32, 0x8007_048F);
+        assert!(HResult::S_FALSE.is_ok());
+        assert!(!HResult::E_HANDLE.is_ok());
+        assert_eq!(HResult::E_INVALIDARG.to_string(), "0x80070057");
+    }
+}
@@ -0,0 +1,306 @@
+//! 摇杆信号处理
+//!
+//! 摇杆值先归一化到 [-1, 1]，依次经过平滑、死区/饱和、响应曲线、反向和速率限制，
+//! 再转换回 i16。每个环节都是独立的 [`LeverStage`]，可以单独测试。
+
+use std::f32::consts::PI;
+use std::time::Instant;
+
+use crate::config::{LeverFilterConfig, Smoothing};
+
+/// 两次 poll 间隔过小时使用的最小时间步长（秒）
+const MIN_DT: f32 = 1e-4;
+
+pub trait LeverStage: Send + Sync {
+    /// `x` 为归一化后的摇杆值，`dt` 为距离上次调用的秒数
+    fn process(&mut self, x: f32, dt: f32) -> f32;
+}
+
+/// 中心死区与边缘饱和：死区内输出 0，超过 `1 - saturation` 输出满值，中间线性拉伸
+pub struct Deadzone {
+    pub deadzone: f32,
+    pub saturation: f32,
+}
+
+impl LeverStage for Deadzone {
+    fn process(&mut self, x: f32, _dt: f32) -> f32 {
+        let range = 1.0 - self.saturation - self.deadzone;
+        if x.abs() <= self.deadzone || range <= 0.0 {
+            return if x.abs() > self.deadzone {
+                x.signum()
+            } else {
+                0.0
+            };
+        }
+        (((x.abs() - self.deadzone) / range).min(1.0)).copysign(x)
+    }
+}
+
+/// 指数移动平均
+pub struct Ema {
+    pub alpha: f32,
+    value: Option<f32>,
+}
+
+impl Ema {
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+}
+
+impl LeverStage for Ema {
+    fn process(&mut self, x: f32, _dt: f32) -> f32 {
+        let y = match self.value {
+            Some(y) => y + self.alpha * (x - y),
+            None => x,
+        };
+        self.value = Some(y);
+        y
+    }
+}
+
+/// One-Euro 滤波：慢速移动时强平滑去抖，快速移动时降低延迟
+pub struct OneEuro {
+    pub min_cutoff: f32,
+    pub beta: f32,
+    pub d_cutoff: f32,
+    value: Option<f32>,
+    derivative: f32,
+}
+
+impl OneEuro {
+    pub fn new(min_cutoff: f32, beta: f32, d_cutoff: f32) -> Self {
+        Self {
+            min_cutoff,
+            beta,
+            d_cutoff,
+            value: None,
+            derivative: 0.0,
+        }
+    }
+
+    fn alpha(cutoff: f32, dt: f32) -> f32 {
+        let tau = 1.0 / (2.0 * PI * cutoff.max(f32::EPSILON));
+        1.0 / (1.0 + tau / dt)
+    }
+}
+
+impl LeverStage for OneEuro {
+    fn process(&mut self, x: f32, dt: f32) -> f32 {
+        let Some(prev) = self.value else {
+            self.value = Some(x);
+            return x;
+        };
+        let dt = dt.max(MIN_DT);
+        let dx = (x - prev) / dt;
+        self.derivative += Self::alpha(self.d_cutoff, dt) * (dx - self.derivative);
+        let cutoff = self.min_cutoff + self.beta * self.derivative.abs();
+        let y = prev + Self::alpha(cutoff, dt) * (x - prev);
+        self.value = Some(y);
+        y
+    }
+}
+
+/// 响应曲线：`|x|^exponent`，大于 1 时中心更细腻，小于 1 时中心更灵敏
+pub struct Curve {
+    pub exponent: f32,
+}
+
+impl LeverStage for Curve {
+    fn process(&mut self, x: f32, _dt: f32) -> f32 {
+        x.abs().powf(self.exponent).copysign(x)
+    }
+}
+
+pub struct Invert;
+
+impl LeverStage for Invert {
+    fn process(&mut self, x: f32, _dt: f32) -> f32 {
+        -x
+    }
+}
+
+/// 速率限制：每秒最多变化 `rate`（满行程为 2）
+pub struct RateLimit {
+    pub rate: f32,
+    value: Option<f32>,
+}
+
+impl RateLimit {
+    pub fn new(rate: f32) -> Self {
+        Self { rate, value: None }
+    }
+}
+
+impl LeverStage for RateLimit {
+    fn process(&mut self, x: f32, dt: f32) -> f32 {
+        let y = match self.value {
+            Some(y) => {
+                let max_delta = self.rate * dt.max(0.0);
+                y + (x - y).clamp(-max_delta, max_delta)
+            }
+            None => x,
+        };
+        self.value = Some(y);
+        y
+    }
+}
+
+/// 按配置组装的滤波链，没有启用任何环节时原样输出
+#[derive(Default)]
+pub struct LeverFilter {
+    stages: Vec<Box<dyn LeverStage>>,
+    last: Option<Instant>,
+}
+
+impl LeverFilter {
+    pub fn new(config: &LeverFilterConfig) -> Self {
+        let mut stages: Vec<Box<dyn LeverStage>> = vec![];
+        match config.smoothing {
+            Smoothing::None => {}
+            Smoothing::Ema { alpha } => stages.push(Box::new(Ema::new(alpha))),
+            Smoothing::OneEuro {
+                min_cutoff,
+                beta,
+                d_cutoff,
+            } => stages.push(Box::new(OneEuro::new(min_cutoff, beta, d_cutoff))),
+        }
+        if config.deadzone > 0.0 || config.saturation > 0.0 {
+            stages.push(Box::new(Deadzone {
+                deadzone: config.deadzone.clamp(0.0, 1.0),
+                saturation: config.saturation.clamp(0.0, 1.0),
+            }));
+        }
+        if config.curve > 0.0 && config.curve != 1.0 {
+            stages.push(Box::new(Curve {
+                exponent: config.curve,
+            }));
+        }
+        if config.invert {
+            stages.push(Box::new(Invert));
+        }
+        if config.rate_limit > 0.0 {
+            stages.push(Box::new(RateLimit::new(config.rate_limit)));
+        }
+        Self { stages, last: None }
+    }
+
+    pub fn apply(&mut self, raw: i16, now: Instant) -> i16 {
+        if self.stages.is_empty() {
+            return raw;
+        }
+        let dt = self
+            .last
+            .map(|last| now.duration_since(last).as_secs_f32())
+            .unwrap_or_default();
+        self.last = Some(now);
+        self.process(raw, dt)
+    }
+
+    fn process(&mut self, raw: i16, dt: f32) -> i16 {
+        let x = f32::from(raw) / 32768.0;
+        let y = self.stages.iter_mut().fold(x, |x, s| s.process(x, dt));
+        (y * 32768.0)
+            .round()
+            .clamp(f32::from(i16::MIN), f32::from(i16::MAX)) as i16
+    }
+}
+
+#[cfg(test)]
+mod filter_test {
+    use super::*;
+
+    fn run(stage: &mut dyn LeverStage, input: &[f32], dt: f32) -> Vec<f32> {
+        input.iter().map(|x| stage.process(*x, dt)).collect()
+    }
+
+    #[test]
+    fn deadzone_test() {
+        let mut stage = Deadzone {
+            deadzone: 0.1,
+            saturation: 0.1,
+        };
+        let out = run(&mut stage, &[0.05, -0.1, 0.5, -0.5, 0.9, -0.95, 1.0], 0.001);
+        assert_eq!(out[0], 0.0);
+        assert_eq!(out[1], 0.0);
+        assert!((out[2] - 0.5).abs() < 1e-6);
+        assert!((out[3] + 0.5).abs() < 1e-6);
+        assert_eq!(&out[4..], &[1.0, -1.0, 1.0]);
+    }
+
+    #[test]
+    fn ema_test() {
+        let mut stage = Ema::new(0.5);
+        let out = run(&mut stage, &[0.0, 1.0, 1.0, 1.0], 0.001);
+        assert_eq!(out, vec![0.0, 0.5, 0.75, 0.875]);
+    }
+
+    #[test]
+    fn one_euro_test() {
+        // 噪声在静止时被压低
+        let mut stage = OneEuro::new(1.0, 0.0, 1.0);
+        let noise: Vec<f32> = (0..200)
+            .map(|i| if i % 2 == 0 { 0.02 } else { -0.02 })
+            .collect();
+        let out = run(&mut stage, &noise, 0.001);
+        let jitter = out
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f32::max);
+        assert!(jitter < 0.001, "{jitter}");
+
+        // beta 越大，快速移动时跟随越快
+        let ramp: Vec<f32> = (0..50).map(|i| i as f32 / 50.0).collect();
+        let slow = *run(&mut OneEuro::new(1.0, 0.0, 1.0), &ramp, 0.001)
+            .last()
+            .unwrap();
+        let fast = *run(&mut OneEuro::new(1.0, 10.0, 1.0), &ramp, 0.001)
+            .last()
+            .unwrap();
+        assert!(fast > slow);
+        assert!(fast <= 1.0);
+    }
+
+    #[test]
+    fn curve_test() {
+        let mut stage = Curve { exponent: 2.0 };
+        assert_eq!(
+            run(&mut stage, &[0.5, -0.5, 1.0, 0.0], 0.0),
+            vec![0.25, -0.25, 1.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn rate_limit_test() {
+        let mut stage = RateLimit::new(10.0);
+        let out = run(&mut stage, &[0.0, 1.0, 1.0, -1.0], 0.05);
+        assert_eq!(out, vec![0.0, 0.5, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn chain_test() {
+        let mut filter = LeverFilter::new(&LeverFilterConfig::default());
+        for raw in [i16::MIN, -1, 0, 1, 1234, i16::MAX] {
+            assert_eq!(filter.apply(raw, Instant::now()), raw);
+        }
+
+        let mut filter = LeverFilter::new(&LeverFilterConfig {
+            deadzone: 0.1,
+            invert: true,
+            ..Default::default()
+        });
+        assert_eq!(filter.process(1000, 0.001), 0);
+        assert_eq!(filter.process(i16::MAX, 0.001), -i16::MAX);
+        assert_eq!(filter.process(i16::MIN, 0.001), i16::MAX);
+
+        let mut filter = LeverFilter::new(&LeverFilterConfig {
+            smoothing: Smoothing::Ema { alpha: 0.5 },
+            ..Default::default()
+        });
+        assert_eq!(filter.process(0, 0.001), 0);
+        assert_eq!(filter.process(16384, 0.001), 8192);
+    }
+}
@@ -0,0 +1,460 @@
+//! mu3 与 amdaemon 两个进程之间的共享状态
+//!
+//! 两个进程各自加载一份 DLL，其中一个作为主进程持有硬件，每次 poll 后发布输入快照，
+//! 另一个作为子进程只读取快照，并把自己收到的 LED 数据写入共享内存交给主进程输出。
+
+use std::fs::OpenOptions;
+use std::io;
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use memmap2::MmapMut;
+use rgb::RGB8;
+
+use crate::config::{SharedConfig, SharedRole};
+
+/// 最后一个字节为协议版本
+const MAGIC: u32 = u32::from_le_bytes(*b"MU3\x01");
+
+/// 共享内存映射大小
+pub const SHARED_SIZE: usize = 4096;
+
+/// 最多 61 个 LED
+const LED_BYTES: usize = 61 * 3;
+
+/// 平台相关的共享内存
+pub trait SharedMemory: Send + Sync {
+    fn as_ptr(&self) -> *mut u8;
+    fn len(&self) -> usize;
+}
+
+/// 文件映射的共享内存，两个进程映射同一个文件即可共享
+pub struct MmapMemory {
+    ptr: *mut u8,
+    map: MmapMut,
+}
+
+unsafe impl Send for MmapMemory {}
+unsafe impl Sync for MmapMemory {}
+
+impl MmapMemory {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() < SHARED_SIZE as u64 {
+            file.set_len(SHARED_SIZE as u64)?;
+        }
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        Ok(Self {
+            ptr: map.as_mut_ptr(),
+            map,
+        })
+    }
+}
+
+impl SharedMemory for MmapMemory {
+    fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+/// Windows 命名共享内存，不落盘
+#[cfg(windows)]
+pub struct NamedMemory {
+    handle: windows::Win32::Foundation::HANDLE,
+    view: windows::Win32::System::Memory::MEMORY_MAPPED_VIEW_ADDRESS,
+}
+
+#[cfg(windows)]
+unsafe impl Send for NamedMemory {}
+#[cfg(windows)]
+unsafe impl Sync for NamedMemory {}
+
+#[cfg(windows)]
+impl NamedMemory {
+    pub fn open(name: &str) -> windows::core::Result<Self> {
+        use windows::core::PCWSTR;
+        use windows::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
+        use windows::Win32::System::Memory::{
+            CreateFileMappingW, MapViewOfFile, FILE_MAP_ALL_ACCESS, PAGE_READWRITE,
+        };
+
+        let name: Vec<u16> = format!("Local\\{name}")
+            .encode_utf16()
+            .chain(Some(0))
+            .collect();
+        unsafe {
+            let handle = CreateFileMappingW(
+                INVALID_HANDLE_VALUE,
+                None,
+                PAGE_READWRITE,
+                0,
+                SHARED_SIZE as u32,
+                PCWSTR(name.as_ptr()),
+            )?;
+            let view = MapViewOfFile(handle, FILE_MAP_ALL_ACCESS, 0, 0, SHARED_SIZE);
+            if view.Value.is_null() {
+                let e = windows::core::Error::from_win32();
+                let _ = CloseHandle(handle);
+                return Err(e);
+            }
+            Ok(Self { handle, view })
+        }
+    }
+}
+
+#[cfg(windows)]
+impl SharedMemory for NamedMemory {
+    fn as_ptr(&self) -> *mut u8 {
+        self.view.Value as *mut u8
+    }
+
+    fn len(&self) -> usize {
+        SHARED_SIZE
+    }
+}
+
+#[cfg(windows)]
+impl Drop for NamedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = windows::Win32::System::Memory::UnmapViewOfFile(self.view);
+            let _ = windows::Win32::Foundation::CloseHandle(self.handle);
+        }
+    }
+}
+
+/// 按平台打开共享内存
+pub fn open_memory(name: &str) -> io::Result<Box<dyn SharedMemory>> {
+    #[cfg(windows)]
+    {
+        NamedMemory::open(name)
+            .map(|m| Box::new(m) as Box<dyn SharedMemory>)
+            .map_err(io::Error::other)
+    }
+    #[cfg(not(windows))]
+    {
+        MmapMemory::open(Path::new(&format!("{name}.shm")))
+            .map(|m| Box::new(m) as Box<dyn SharedMemory>)
+    }
+}
+
+/// 单块 LED 板的数据，使用 seqlock：写入时 seq 为奇数
+#[repr(C)]
+struct LedSlot {
+    seq: AtomicU32,
+    len: AtomicU32,
+    data: [AtomicU8; LED_BYTES],
+}
+
+#[repr(C)]
+struct SharedState {
+    magic: AtomicU32,
+    owner: AtomicU32,
+    /// 主进程最后一次更新的时间戳（毫秒）
+    heartbeat: AtomicU64,
+    /// 打包后的输入快照，见 [`InputSnapshot`]
+    input: AtomicU64,
+    /// 高 32 位为序号，低 32 位为 `mu3_io_set_led` 数据
+    legacy_led: AtomicU64,
+    boards: [LedSlot; 2],
+}
+
+const _: () = assert!(size_of::<SharedState>() <= SHARED_SIZE);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InputSnapshot {
+    pub op_btns: u8,
+    pub left_btns: u8,
+    pub right_btns: u8,
+    pub lever: i16,
+}
+
+impl InputSnapshot {
+    pub fn pack(&self) -> u64 {
+        u64::from(self.op_btns)
+            | u64::from(self.left_btns) << 8
+            | u64::from(self.right_btns) << 16
+            | u64::from(self.lever as u16) << 32
+    }
+
+    pub fn unpack(v: u64) -> Self {
+        Self {
+            op_btns: v as u8,
+            left_btns: (v >> 8) as u8,
+            right_btns: (v >> 16) as u8,
+            lever: (v >> 32) as u16 as i16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedFrame {
+    Legacy(u32),
+    Colors(u8, Vec<RGB8>),
+}
+
+/// 主进程已经处理过的 LED 序号
+#[derive(Debug, Default)]
+pub struct LedCursor {
+    legacy: u32,
+    boards: [u32; 2],
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+pub struct Channel {
+    memory: Box<dyn SharedMemory>,
+    id: u32,
+}
+
+impl Channel {
+    /// `id` 用于区分进程，通常为进程 ID
+    pub fn new(memory: Box<dyn SharedMemory>, id: u32) -> io::Result<Self> {
+        if memory.len() < size_of::<SharedState>() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "共享内存过小"));
+        }
+        let channel = Self { memory, id };
+        let state = channel.state();
+        match state
+            .magic
+            .compare_exchange(0, MAGIC, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) | Err(MAGIC) => Ok(channel),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "共享内存格式或版本不一致",
+            )),
+        }
+    }
+
+    pub fn open(config: &SharedConfig) -> io::Result<Self> {
+        Self::new(open_memory(&config.name)?, std::process::id())
+    }
+
+    fn state(&self) -> &SharedState {
+        // 映射地址按页对齐，大小已在 new 中检查
+        unsafe { &*(self.memory.as_ptr() as *const SharedState) }
+    }
+
+    pub fn owner_alive(&self, now: u64, timeout_ms: u64) -> bool {
+        let state = self.state();
+        state.owner.load(Ordering::Acquire) != 0
+            && now.saturating_sub(state.heartbeat.load(Ordering::Acquire)) <= timeout_ms
+    }
+
+    /// 按角色尝试成为主进程，返回是否为主进程
+    ///
+    /// `Owner` 同样不会取代存活的主进程，避免两个进程同时打开硬件。
+    /// 只在启动时调用一次，之后主进程退出时子进程不会接管硬件
+    pub fn claim(&self, role: SharedRole, now: u64, timeout_ms: u64) -> bool {
+        let state = self.state();
+        let owner = state.owner.load(Ordering::Acquire);
+        let claimed = match role {
+            SharedRole::Client => false,
+            SharedRole::Owner | SharedRole::Auto => {
+                owner == self.id
+                    || (!self.owner_alive(now, timeout_ms)
+                        && state
+                            .owner
+                            .compare_exchange(owner, self.id, Ordering::AcqRel, Ordering::Acquire)
+                            .is_ok())
+            }
+        };
+        if claimed {
+            self.heartbeat(now);
+        }
+        claimed
+    }
+
+    pub fn heartbeat(&self, now: u64) {
+        self.state().heartbeat.store(now, Ordering::Release);
+    }
+
+    pub fn publish_input(&self, snapshot: InputSnapshot) {
+        self.state().input.store(snapshot.pack(), Ordering::Release);
+    }
+
+    pub fn input(&self) -> InputSnapshot {
+        InputSnapshot::unpack(self.state().input.load(Ordering::Acquire))
+    }
+
+    pub fn send_led(&self, data: u32) {
+        let legacy = &self.state().legacy_led;
+        let seq = (legacy.load(Ordering::Acquire) >> 32) as u32;
+        let seq = seq.wrapping_add(1).max(1);
+        legacy.store(u64::from(seq) << 32 | u64::from(data), Ordering::Release);
+    }
+
+    pub fn send_led_colors(&self, board: u8, rgb: &[RGB8]) {
+        let Some(slot) = self.state().boards.get(usize::from(board)) else {
+            return;
+        };
+        let bytes = rgb.iter().flat_map(|c| [c.r, c.g, c.b]).take(LED_BYTES);
+
+        let seq = slot.seq.load(Ordering::Acquire) | 1;
+        slot.seq.store(seq, Ordering::Relaxed);
+        fence(Ordering::Release);
+        let mut len = 0;
+        for (dst, b) in slot.data.iter().zip(bytes) {
+            dst.store(b, Ordering::Relaxed);
+            len += 1;
+        }
+        slot.len.store(len, Ordering::Relaxed);
+        slot.seq.store(seq.wrapping_add(1), Ordering::Release);
+    }
+
+    /// 取出子进程写入、主进程尚未处理的 LED 数据
+    pub fn recv_leds(&self, cursor: &mut LedCursor) -> Vec<LedFrame> {
+        let state = self.state();
+        let mut frames = vec![];
+
+        let legacy = state.legacy_led.load(Ordering::Acquire);
+        let seq = (legacy >> 32) as u32;
+        if seq != cursor.legacy {
+            cursor.legacy = seq;
+            frames.push(LedFrame::Legacy(legacy as u32));
+        }
+
+        for (board, slot) in state.boards.iter().enumerate() {
+            let seq = slot.seq.load(Ordering::Acquire);
+            if seq == cursor.boards[board] || seq % 2 == 1 {
+                continue;
+            }
+            let len = (slot.len.load(Ordering::Relaxed) as usize).min(LED_BYTES);
+            let data: Vec<u8> = slot.data[..len]
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect();
+            // 读取期间被改写，下次 poll 再读
+            fence(Ordering::Acquire);
+            if slot.seq.load(Ordering::Acquire) != seq {
+                continue;
+            }
+            cursor.boards[board] = seq;
+            let colors = data
+                .chunks_exact(3)
+                .map(|c| RGB8::new(c[0], c[1], c[2]))
+                .collect();
+            frames.push(LedFrame::Colors(board as u8, colors));
+        }
+
+        frames
+    }
+}
+
+#[cfg(test)]
+mod ipc_test {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn shm_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ongeki-io-{}-{name}.shm", std::process::id()))
+    }
+
+    fn channel(path: &Path, id: u32) -> Channel {
+        Channel::new(Box::new(MmapMemory::open(path).unwrap()), id).unwrap()
+    }
+
+    #[test]
+    fn claim_test() {
+        let path = shm_path("claim");
+        let _ = std::fs::remove_file(&path);
+        let a = channel(&path, 1);
+        let b = channel(&path, 2);
+
+        assert!(a.claim(SharedRole::Auto, 1000, 500));
+        assert!(!b.claim(SharedRole::Auto, 1200, 500));
+        assert!(a.claim(SharedRole::Auto, 1300, 500));
+        assert!(b.owner_alive(1500, 500));
+
+        // 主进程超时后由其他进程接管
+        assert!(!b.owner_alive(1900, 500));
+        assert!(b.claim(SharedRole::Auto, 1900, 500));
+        assert!(!a.claim(SharedRole::Auto, 2000, 500));
+
+        assert!(!a.claim(SharedRole::Client, 5000, 500));
+        // 指定为主进程时也不取代存活的主进程
+        assert!(!a.claim(SharedRole::Owner, 2100, 500));
+        assert!(b.claim(SharedRole::Owner, 2100, 500));
+        assert!(a.claim(SharedRole::Owner, 2700, 500));
+        assert!(!b.claim(SharedRole::Auto, 2800, 500));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn input_test() {
+        let path = shm_path("input");
+        let _ = std::fs::remove_file(&path);
+        let owner = channel(&path, 1);
+        let client = channel(&path, 2);
+
+        assert_eq!(client.input(), InputSnapshot::default());
+        let snapshot = InputSnapshot {
+            op_btns: 0x04,
+            left_btns: 0x1F,
+            right_btns: 0x02,
+            lever: -12345,
+        };
+        owner.publish_input(snapshot);
+        assert_eq!(client.input(), snapshot);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn led_test() {
+        let path = shm_path("led");
+        let _ = std::fs::remove_file(&path);
+        let owner = channel(&path, 1);
+        let client = channel(&path, 2);
+        let mut cursor = LedCursor::default();
+
+        assert!(owner.recv_leds(&mut cursor).is_empty());
+
+        let board0: Vec<RGB8> = (0..61).map(|i| RGB8::new(i, i + 1, i + 2)).collect();
+        client.send_led_colors(0, &board0);
+        client.send_led(0x00FF_FFC0);
+        assert_eq!(
+            owner.recv_leds(&mut cursor),
+            vec![LedFrame::Legacy(0x00FF_FFC0), LedFrame::Colors(0, board0)]
+        );
+        assert!(owner.recv_leds(&mut cursor).is_empty());
+
+        let board1 = vec![RGB8::new(255, 0, 0); 6];
+        client.send_led_colors(1, &[RGB8::default(); 6]);
+        client.send_led_colors(1, &board1);
+        client.send_led_colors(2, &board1);
+        assert_eq!(
+            owner.recv_leds(&mut cursor),
+            vec![LedFrame::Colors(1, board1)]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bad_magic_test() {
+        let path = shm_path("magic");
+        std::fs::write(&path, vec![0xAA; SHARED_SIZE]).unwrap();
+        assert!(Channel::new(Box::new(MmapMemory::open(&path).unwrap()), 1).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
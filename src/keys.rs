@@ -0,0 +1,265 @@
+use std::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// 按键名与 Windows 虚拟键码对照表，同一键码的第一个名称用于写入配置文件
+const KEY_NAMES: &[(&str, i32)] = &[
+    ("MOUSE1", 0x01),
+    ("MOUSE2", 0x02),
+    ("MOUSE3", 0x04),
+    ("MOUSE4", 0x05),
+    ("MOUSE5", 0x06),
+    ("BACKSPACE", 0x08),
+    ("TAB", 0x09),
+    ("ENTER", 0x0D),
+    ("RETURN", 0x0D),
+    ("SHIFT", 0x10),
+    ("CTRL", 0x11),
+    ("CONTROL", 0x11),
+    ("ALT", 0x12),
+    ("PAUSE", 0x13),
+    ("CAPSLOCK", 0x14),
+    ("ESC", 0x1B),
+    ("ESCAPE", 0x1B),
+    ("SPACE", 0x20),
+    ("PAGEUP", 0x21),
+    ("PAGEDOWN", 0x22),
+    ("END", 0x23),
+    ("HOME", 0x24),
+    ("LEFT", 0x25),
+    ("UP", 0x26),
+    ("RIGHT", 0x27),
+    ("DOWN", 0x28),
+    ("INSERT", 0x2D),
+    ("DELETE", 0x2E),
+    ("0", 0x30),
+    ("1", 0x31),
+    ("2", 0x32),
+    ("3", 0x33),
+    ("4", 0x34),
+    ("5", 0x35),
+    ("6", 0x36),
+    ("7", 0x37),
+    ("8", 0x38),
+    ("9", 0x39),
+    ("A", 0x41),
+    ("B", 0x42),
+    ("C", 0x43),
+    ("D", 0x44),
+    ("E", 0x45),
+    ("F", 0x46),
+    ("G", 0x47),
+    ("H", 0x48),
+    ("I", 0x49),
+    ("J", 0x4A),
+    ("K", 0x4B),
+    ("L", 0x4C),
+    ("M", 0x4D),
+    ("N", 0x4E),
+    ("O", 0x4F),
+    ("P", 0x50),
+    ("Q", 0x51),
+    ("R", 0x52),
+    ("S", 0x53),
+    ("T", 0x54),
+    ("U", 0x55),
+    ("V", 0x56),
+    ("W", 0x57),
+    ("X", 0x58),
+    ("Y", 0x59),
+    ("Z", 0x5A),
+    ("NUMPAD0", 0x60),
+    ("NUMPAD1", 0x61),
+    ("NUMPAD2", 0x62),
+    ("NUMPAD3", 0x63),
+    ("NUMPAD4", 0x64),
+    ("NUMPAD5", 0x65),
+    ("NUMPAD6", 0x66),
+    ("NUMPAD7", 0x67),
+    ("NUMPAD8", 0x68),
+    ("NUMPAD9", 0x69),
+    ("MULTIPLY", 0x6A),
+    ("ADD", 0x6B),
+    ("SUBTRACT", 0x6D),
+    ("DECIMAL", 0x6E),
+    ("DIVIDE", 0x6F),
+    ("F1", 0x70),
+    ("F2", 0x71),
+    ("F3", 0x72),
+    ("F4", 0x73),
+    ("F5", 0x74),
+    ("F6", 0x75),
+    ("F7", 0x76),
+    ("F8", 0x77),
+    ("F9", 0x78),
+    ("F10", 0x79),
+    ("F11", 0x7A),
+    ("F12", 0x7B),
+    ("LSHIFT", 0xA0),
+    ("RSHIFT", 0xA1),
+    ("LCTRL", 0xA2),
+    ("RCTRL", 0xA3),
+    ("LALT", 0xA4),
+    ("RALT", 0xA5),
+    ("SEMICOLON", 0xBA),
+    ("EQUALS", 0xBB),
+    ("COMMA", 0xBC),
+    ("MINUS", 0xBD),
+    ("PERIOD", 0xBE),
+    ("SLASH", 0xBF),
+    ("GRAVE", 0xC0),
+    ("LBRACKET", 0xDB),
+    ("BACKSLASH", 0xDC),
+    ("RBRACKET", 0xDD),
+    ("QUOTE", 0xDE),
+];
+
+/// Windows 虚拟键码，配置文件中可以写按键名（如 "A"、"LSHIFT"、"MOUSE1"）或键码数字
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualKey(pub i32);
+
+impl VirtualKey {
+    pub fn from_name(name: &str) -> Option<Self> {
+        KEY_NAMES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, vk)| Self(*vk))
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        KEY_NAMES
+            .iter()
+            .find(|(_, vk)| *vk == self.0)
+            .map(|(n, _)| *n)
+    }
+}
+
+impl Serialize for VirtualKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.name() {
+            Some(name) => serializer.serialize_str(name),
+            None => serializer.serialize_i32(self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for VirtualKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+
+        impl de::Visitor<'_> for KeyVisitor {
+            type Value = VirtualKey;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("按键名或 1-254 的虚拟键码")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<VirtualKey, E> {
+                if (1..=254).contains(&v) {
+                    Ok(VirtualKey(v as i32))
+                } else {
+                    Err(E::custom(format!("虚拟键码 {v} 超出范围 1-254")))
+                }
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<VirtualKey, E> {
+                self.visit_i64(v.min(i64::MAX as u64) as i64)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<VirtualKey, E> {
+                VirtualKey::from_name(v).ok_or_else(|| E::custom(format!("未知按键名 \"{v}\"")))
+            }
+        }
+
+        deserializer.deserialize_any(KeyVisitor)
+    }
+}
+
+/// 一个游戏按键可以绑定多个键，配置文件中可以写单个键或数组
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyBinding(pub Vec<VirtualKey>);
+
+impl KeyBinding {
+    pub fn iter(&self) -> impl Iterator<Item = &VirtualKey> {
+        self.0.iter()
+    }
+}
+
+impl From<i32> for KeyBinding {
+    fn from(vk: i32) -> Self {
+        Self(vec![VirtualKey(vk)])
+    }
+}
+
+impl Serialize for KeyBinding {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.as_slice() {
+            [key] => key.serialize(serializer),
+            keys => keys.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBinding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // 不用 untagged 枚举，否则未知按键名的错误信息会被吞掉
+        let value = toml::Value::deserialize(deserializer)?;
+        let keys = match value {
+            toml::Value::Array(_) => Vec::<VirtualKey>::deserialize(value),
+            _ => VirtualKey::deserialize(value).map(|k| vec![k]),
+        };
+        keys.map(Self).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod keys_test {
+    use serde::{Deserialize, Serialize};
+
+    use super::{KeyBinding, VirtualKey};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Binding {
+        key: KeyBinding,
+    }
+
+    fn parse(s: &str) -> Result<KeyBinding, toml::de::Error> {
+        toml::from_str::<Binding>(s).map(|b| b.key)
+    }
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(parse("key = \"A\"").unwrap(), KeyBinding::from(0x41));
+        assert_eq!(parse("key = \"lshift\"").unwrap(), KeyBinding::from(0xA0));
+        assert_eq!(parse("key = 0x31").unwrap(), KeyBinding::from(0x31));
+        assert_eq!(
+            parse("key = [\"MOUSE1\", \"SPACE\", 0x0D]").unwrap().0,
+            vec![VirtualKey(0x01), VirtualKey(0x20), VirtualKey(0x0D)]
+        );
+    }
+
+    #[test]
+    fn unknown_key_test() {
+        let err = parse("key = \"NOPE\"").unwrap_err().to_string();
+        assert!(err.contains("未知按键名 \"NOPE\""), "{err}");
+        let err = parse("key = [\"A\", \"NOPE\"]").unwrap_err().to_string();
+        assert!(err.contains("未知按键名 \"NOPE\""), "{err}");
+        assert!(parse("key = 300").is_err());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let binding = Binding {
+            key: KeyBinding(vec![VirtualKey(0x41), VirtualKey(0xFE)]),
+        };
+        let s = toml::to_string(&binding).unwrap();
+        assert_eq!(s.trim(), "key = [\"A\", 254]");
+        assert_eq!(parse(&s).unwrap(), binding.key);
+
+        let s = toml::to_string(&Binding {
+            key: KeyBinding::from(0x0D),
+        })
+        .unwrap();
+        assert_eq!(s.trim(), "key = \"ENTER\"");
+    }
+}
@@ -0,0 +1,230 @@
+//! 发送给 LED 驱动前的颜色处理
+//!
+//! 依次进行伽马校正、亮度和白平衡调整，最后按估算的电流限制整体亮度。
+//! 颜色顺序在每个输出上单独转换。
+
+use std::borrow::Cow;
+
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+
+use crate::config::LedColorConfig;
+use crate::led_layout::LedZone;
+
+/// 输出的颜色顺序，灯带或固件不是 RGB 顺序时使用
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelOrder {
+    #[default]
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+impl ChannelOrder {
+    pub fn apply(self, c: RGB8) -> RGB8 {
+        let RGB8 { r, g, b } = c;
+        match self {
+            ChannelOrder::Rgb => c,
+            ChannelOrder::Rbg => RGB8::new(r, b, g),
+            ChannelOrder::Grb => RGB8::new(g, r, b),
+            ChannelOrder::Gbr => RGB8::new(g, b, r),
+            ChannelOrder::Brg => RGB8::new(b, r, g),
+            ChannelOrder::Bgr => RGB8::new(b, g, r),
+        }
+    }
+
+    pub fn apply_all<'a>(self, rgb: &'a [RGB8]) -> Cow<'a, [RGB8]> {
+        match self {
+            ChannelOrder::Rgb => Cow::Borrowed(rgb),
+            _ => Cow::Owned(rgb.iter().map(|c| self.apply(*c)).collect()),
+        }
+    }
+}
+
+/// 单独调整 board 0 某个分区的亮度
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ZoneBrightness {
+    pub zone: LedZone,
+    pub brightness: f32,
+}
+
+pub struct ColorProcessor {
+    config: LedColorConfig,
+    /// 伽马校正后的值，保留小数以免与亮度相乘时损失精度
+    gamma: [f32; 256],
+}
+
+impl ColorProcessor {
+    pub fn new(config: &LedColorConfig) -> Self {
+        let gamma = std::array::from_fn(|v| (v as f32 / 255.0).powf(config.gamma) * 255.0);
+        Self {
+            config: config.clone(),
+            gamma,
+        }
+    }
+
+    /// 默认配置不改变颜色，可以跳过处理
+    pub fn is_identity(&self) -> bool {
+        let c = &self.config;
+        c.brightness == 1.0
+            && c.button_brightness == 1.0
+            && c.zones.iter().all(|z| z.brightness == 1.0)
+            && c.gamma == 1.0
+            && c.white_balance == [1.0; 3]
+            && c.current_limit_ma == 0
+    }
+
+    /// 某个 LED 的亮度系数
+    fn scale(&self, board: u8, index: usize) -> f32 {
+        let zone = match board {
+            0 => self
+                .config
+                .zones
+                .iter()
+                .filter(|z| z.zone.range().contains(&index))
+                .map(|z| z.brightness)
+                .product(),
+            _ => self.config.button_brightness,
+        };
+        self.config.brightness * zone
+    }
+
+    /// 按每个通道满亮度 `channel_ma` 估算的电流（毫安）
+    fn current_ma(&self, channels: impl IntoIterator<Item = f32>) -> f32 {
+        let sum: f32 = channels.into_iter().map(|v| v.clamp(0.0, 255.0)).sum();
+        sum / 255.0 * self.config.channel_ma
+    }
+
+    pub fn process<'a>(&self, board: u8, rgb: &'a [RGB8]) -> Cow<'a, [RGB8]> {
+        if self.is_identity() {
+            return Cow::Borrowed(rgb);
+        }
+        let [wr, wg, wb] = self.config.white_balance;
+        let mut linear: Vec<[f32; 3]> = rgb
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let s = self.scale(board, i);
+                [
+                    self.gamma[usize::from(c.r)] * s * wr,
+                    self.gamma[usize::from(c.g)] * s * wg,
+                    self.gamma[usize::from(c.b)] * s * wb,
+                ]
+            })
+            .collect();
+
+        // 超过电流限制时整体调暗
+        let limit = self.config.current_limit_ma as f32;
+        if limit > 0.0 {
+            let current = self.current_ma(linear.iter().flatten().copied());
+            if current > limit {
+                let k = limit / current;
+                linear.iter_mut().flatten().for_each(|v| *v *= k);
+            }
+        }
+
+        let channel = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+        Cow::Owned(
+            linear
+                .into_iter()
+                .map(|[r, g, b]| RGB8::new(channel(r), channel(g), channel(b)))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod led_color_test {
+    use super::*;
+    use crate::config::Config;
+
+    fn processor(f: impl FnOnce(&mut LedColorConfig)) -> ColorProcessor {
+        let mut config = Config::default().led_color;
+        f(&mut config);
+        ColorProcessor::new(&config)
+    }
+
+    #[test]
+    fn identity_test() {
+        let p = processor(|_| {});
+        assert!(p.is_identity());
+        let rgb = [RGB8::new(1, 2, 3)];
+        assert!(matches!(p.process(0, &rgb), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn process_test() {
+        let p = processor(|c| {
+            c.brightness = 0.5;
+            c.button_brightness = 0.5;
+            c.zones = vec![ZoneBrightness {
+                zone: LedZone::LeftPillarLower,
+                brightness: 0.5,
+            }];
+            c.white_balance = [1.0, 1.0, 0.5];
+        });
+        let rgb = [RGB8::new(200, 100, 200); 3];
+        // LED 2 属于 left_pillar_lower
+        assert_eq!(
+            p.process(0, &rgb)[..],
+            [
+                RGB8::new(100, 50, 50),
+                RGB8::new(100, 50, 50),
+                RGB8::new(50, 25, 25)
+            ]
+        );
+        // board 1 不受分区影响
+        assert_eq!(p.process(1, &rgb)[2], RGB8::new(50, 25, 25));
+
+        let p = processor(|c| c.gamma = 2.0);
+        let rgb = [RGB8::new(0, 128, 255)];
+        assert_eq!(p.process(1, &rgb)[0], RGB8::new(0, 64, 255));
+    }
+
+    #[test]
+    fn current_limit_test() {
+        let p = processor(|c| {
+            c.current_limit_ma = 600;
+            c.channel_ma = 20.0;
+        });
+        let current_ma =
+            |rgb: &[RGB8]| p.current_ma(rgb.iter().flat_map(|c| [c.r, c.g, c.b]).map(f32::from));
+        let white = [RGB8::new(255, 255, 255); 20];
+        assert_eq!(current_ma(&white), 1200.0);
+        let out = p.process(0, &white);
+        assert!(out.iter().all(|c| *c == RGB8::new(128, 128, 128)));
+        assert!(current_ma(&out) <= 605.0);
+
+        // 没有超过限制时不变
+        let dim = [RGB8::new(10, 0, 0); 20];
+        assert_eq!(p.process(0, &dim)[..], dim);
+    }
+
+    #[test]
+    fn channel_order_test() {
+        let c = RGB8::new(1, 2, 3);
+        assert_eq!(ChannelOrder::Grb.apply(c), RGB8::new(2, 1, 3));
+        assert_eq!(ChannelOrder::Brg.apply(c), RGB8::new(3, 1, 2));
+        assert_eq!(ChannelOrder::Bgr.apply(c), RGB8::new(3, 2, 1));
+        assert!(matches!(
+            ChannelOrder::Rgb.apply_all(&[c]),
+            Cow::Borrowed(_)
+        ));
+
+        let config: LedColorConfig = toml::from_str(
+            r#"
+            gamma = 2.2
+            [[zones]]
+            zone = "billboard"
+            brightness = 0.3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.zones[0].zone, LedZone::Billboard);
+        assert_eq!(config.brightness, 1.0);
+    }
+}
@@ -0,0 +1,270 @@
+//! 游戏没有更新 LED 时播放的待机灯效
+//!
+//! 灯效只由播放开始后的时间决定，同一时刻总是得到同样的画面。
+//! 游戏再次设置 LED 时立即停止播放。
+
+use std::f32::consts::TAU;
+use std::time::{Duration, Instant};
+
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+
+use crate::config::LedEffectsConfig;
+use crate::led_layout::LedZone;
+use crate::led_recording::{LedState, BOARD0_LEDS, BOARD1_LEDS};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "effect", rename_all = "snake_case")]
+pub enum LedEffect {
+    /// 彩虹沿 LED 流动，`period_ms` 为颜色循环一周的时间
+    Rainbow { duration_ms: u64, period_ms: u64 },
+    /// 所有 LED 以同一颜色呼吸
+    Breathe {
+        duration_ms: u64,
+        period_ms: u64,
+        color: [u8; 3],
+    },
+    /// 一段长 `length` 的光带从立柱底部跑到顶部，按键灯常亮
+    PillarChase {
+        duration_ms: u64,
+        period_ms: u64,
+        color: [u8; 3],
+        length: usize,
+    },
+}
+
+/// 色相 0.0-1.0 对应的全饱和颜色
+fn hue(h: f32) -> RGB8 {
+    let h = h.rem_euclid(1.0) * 6.0;
+    let x = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    RGB8::new(
+        x((h - 3.0).abs() - 1.0),
+        x(2.0 - (h - 2.0).abs()),
+        x(2.0 - (h - 4.0).abs()),
+    )
+}
+
+fn scale(c: [u8; 3], k: f32) -> RGB8 {
+    let x = |v: u8| (f32::from(v) * k).round() as u8;
+    RGB8::new(x(c[0]), x(c[1]), x(c[2]))
+}
+
+/// 播放到 `t_ms` 时在周期内的位置 0.0-1.0
+fn phase(t_ms: u64, period_ms: u64) -> f32 {
+    (t_ms % period_ms.max(1)) as f32 / period_ms.max(1) as f32
+}
+
+impl LedEffect {
+    pub fn duration_ms(&self) -> u64 {
+        match self {
+            LedEffect::Rainbow { duration_ms, .. }
+            | LedEffect::Breathe { duration_ms, .. }
+            | LedEffect::PillarChase { duration_ms, .. } => *duration_ms,
+        }
+    }
+
+    /// 播放到 `t_ms` 时的画面
+    pub fn render(&self, t_ms: u64) -> LedState {
+        let mut state = LedState::default();
+        match *self {
+            LedEffect::Rainbow { period_ms, .. } => {
+                let p = phase(t_ms, period_ms);
+                let n = state.board0.len() as f32;
+                for (i, led) in state.board0.iter_mut().enumerate() {
+                    *led = hue(i as f32 / n + p);
+                }
+                let n = state.board1.len() as f32;
+                for (i, led) in state.board1.iter_mut().enumerate() {
+                    *led = hue(i as f32 / n + p);
+                }
+            }
+            LedEffect::Breathe {
+                period_ms, color, ..
+            } => {
+                let k = (1.0 - (phase(t_ms, period_ms) * TAU).cos()) / 2.0;
+                state.board0 = [scale(color, k); BOARD0_LEDS];
+                state.board1 = [scale(color, k); BOARD1_LEDS];
+            }
+            LedEffect::PillarChase {
+                period_ms,
+                color,
+                length,
+                ..
+            } => {
+                // 左立柱的下标从下往上增加，右立柱从上往下增加
+                let left =
+                    LedZone::LeftPillarLower.range().start..LedZone::LeftPillarUpper.range().end;
+                let right =
+                    LedZone::RightPillarUpper.range().start..LedZone::RightPillarLower.range().end;
+                let height = left.len();
+                // 光带从完全在底部以下跑到完全在顶部以上
+                let travel = height + length;
+                let period_ms = period_ms.max(1);
+                let head = ((t_ms % period_ms) as usize * travel) / period_ms as usize;
+                let c = RGB8::new(color[0], color[1], color[2]);
+                for pos in head.saturating_sub(length)..head.min(height) {
+                    state.board0[left.start + pos] = c;
+                    state.board0[right.end - 1 - pos] = c;
+                }
+                state.board1 = [c; BOARD1_LEDS];
+            }
+        }
+        state
+    }
+}
+
+/// 判断是否空闲并按帧率生成灯效画面
+pub struct LedEffects {
+    config: LedEffectsConfig,
+    /// 游戏最近一次设置 LED 的时间，启动时视为刚设置过
+    last_update: Instant,
+    /// 正在播放时为开始播放的时间
+    started: Option<Instant>,
+    last_frame: Option<Instant>,
+}
+
+impl LedEffects {
+    pub fn new(config: &LedEffectsConfig, now: Instant) -> Self {
+        Self {
+            config: config.clone(),
+            last_update: now,
+            started: None,
+            last_frame: None,
+        }
+    }
+
+    /// 游戏设置了 LED，停止播放
+    pub fn game_update(&mut self, now: Instant) {
+        self.last_update = now;
+        if self.started.take().is_some() {
+            println!("Ongeki IO: 游戏恢复控制 LED，停止待机灯效");
+        }
+        self.last_frame = None;
+    }
+
+    /// 空闲且到了下一帧的时间时返回要显示的画面
+    pub fn tick(&mut self, now: Instant) -> Option<LedState> {
+        let total: u64 = self.config.effects.iter().map(LedEffect::duration_ms).sum();
+        let idle = Duration::from_millis(self.config.idle_timeout_ms);
+        if total == 0 || now.saturating_duration_since(self.last_update) < idle {
+            return None;
+        }
+        let period = Duration::from_secs(1) / self.config.fps.max(1);
+        if self.last_frame.is_some_and(|t| now - t < period) {
+            return None;
+        }
+        self.last_frame = Some(now);
+
+        let started = *self.started.get_or_insert_with(|| {
+            println!("Ongeki IO: 游戏没有更新 LED，开始播放待机灯效");
+            now
+        });
+        // 按顺序循环播放各个灯效
+        let mut t_ms = u64::try_from((now - started).as_millis()).unwrap_or(u64::MAX) % total;
+        for effect in &self.config.effects {
+            if t_ms < effect.duration_ms() {
+                return Some(effect.render(t_ms));
+            }
+            t_ms -= effect.duration_ms();
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod led_effects_test {
+    use super::*;
+    use crate::config::Config;
+
+    const RED: [u8; 3] = [255, 0, 0];
+
+    #[test]
+    fn render_test() {
+        let rainbow = LedEffect::Rainbow {
+            duration_ms: 1000,
+            period_ms: 1000,
+        };
+        let state = rainbow.render(0);
+        assert_eq!(state.board0[0], RGB8::new(255, 0, 0));
+        assert_eq!(state.board1[2], RGB8::new(0, 255, 0));
+        assert_eq!(state.board1[4], RGB8::new(0, 0, 255));
+        // 半个周期后颜色转过半圈
+        assert_eq!(rainbow.render(500).board1[0], RGB8::new(0, 255, 255));
+        assert_eq!(rainbow.render(1500), rainbow.render(500));
+
+        let breathe = LedEffect::Breathe {
+            duration_ms: 1000,
+            period_ms: 1000,
+            color: RED,
+        };
+        assert_eq!(breathe.render(0).board0[30], RGB8::default());
+        assert_eq!(breathe.render(250).board0[30], RGB8::new(128, 0, 0));
+        assert_eq!(breathe.render(500).board1[5], RGB8::new(255, 0, 0));
+    }
+
+    #[test]
+    fn chase_test() {
+        let chase = LedEffect::PillarChase {
+            duration_ms: 1000,
+            // 23 个 LED 加 3 个长度，每 10 毫秒移动一格
+            period_ms: 260,
+            color: RED,
+            length: 3,
+        };
+        let lit = |t_ms| {
+            let state = chase.render(t_ms);
+            (0..61)
+                .filter(|i| state.board0[*i] != RGB8::default())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lit(0), Vec::<usize>::new());
+        assert_eq!(lit(10), [2, 58]);
+        assert_eq!(lit(50), [4, 5, 6, 54, 55, 56]);
+        assert_eq!(lit(250), [24, 36]);
+        assert_eq!(chase.render(0).board1, [RGB8::new(255, 0, 0); 6]);
+    }
+
+    #[test]
+    fn engine_test() {
+        let config = LedEffectsConfig {
+            enabled: true,
+            idle_timeout_ms: 1000,
+            fps: 10,
+            effects: vec![
+                LedEffect::Breathe {
+                    duration_ms: 1000,
+                    period_ms: 1000,
+                    color: RED,
+                },
+                LedEffect::Rainbow {
+                    duration_ms: 500,
+                    period_ms: 1000,
+                },
+            ],
+        };
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut effects = LedEffects::new(&config, start);
+
+        assert!(effects.tick(at(999)).is_none());
+        // 从第一个灯效的开头播放
+        assert_eq!(effects.tick(at(1000)), Some(config.effects[0].render(0)));
+        assert!(effects.started.is_some());
+        // 帧率限制
+        assert!(effects.tick(at(1050)).is_none());
+        assert_eq!(effects.tick(at(2200)), Some(config.effects[1].render(200)));
+        // 播完后从头循环
+        assert_eq!(effects.tick(at(2600)), Some(config.effects[0].render(100)));
+
+        effects.game_update(at(2700));
+        assert!(effects.started.is_none());
+        assert!(effects.tick(at(3000)).is_none());
+        assert_eq!(effects.tick(at(3700)), Some(config.effects[0].render(0)));
+
+        let empty = LedEffectsConfig {
+            effects: vec![],
+            ..Config::default().led_effects
+        };
+        assert!(LedEffects::new(&empty, start).tick(at(60_000)).is_none());
+    }
+}
@@ -0,0 +1,195 @@
+//! 按下按键时在 board 1 对应的按键灯上叠加闪光
+//!
+//! board 1 的 6 个 LED 依次为左侧 Btn1-Btn3、右侧 Btn1-Btn3。
+//! 按住时完全显示闪光颜色，松开后在 `fade_ms` 内淡出回到游戏设置的颜色。
+
+use std::time::{Duration, Instant};
+
+use rgb::RGB8;
+
+use crate::config::ButtonFeedbackConfig;
+use crate::enums::GameBtn;
+use crate::led_recording::BOARD1_LEDS;
+
+const BUTTONS: [GameBtn; 3] = [GameBtn::Btn1, GameBtn::Btn2, GameBtn::Btn3];
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Button {
+    held: bool,
+    released: Option<Instant>,
+}
+
+pub struct ButtonFeedback {
+    config: ButtonFeedbackConfig,
+    buttons: [Button; BOARD1_LEDS],
+    /// 叠加闪光前的颜色，为游戏或待机灯效最近设置的颜色
+    base: [RGB8; BOARD1_LEDS],
+    last_game_update: Option<Instant>,
+    last_frame: Option<Instant>,
+    /// 上一帧是否有闪光，闪光结束时需要再发送一次原来的颜色
+    visible: bool,
+}
+
+impl ButtonFeedback {
+    pub fn new(config: &ButtonFeedbackConfig) -> Self {
+        Self {
+            config: config.clone(),
+            buttons: [Button::default(); BOARD1_LEDS],
+            base: [RGB8::default(); BOARD1_LEDS],
+            last_game_update: None,
+            last_frame: None,
+            visible: false,
+        }
+    }
+
+    pub fn update_buttons(&mut self, left_btns: u8, right_btns: u8, now: Instant) {
+        let bits = [left_btns, right_btns];
+        for (i, button) in self.buttons.iter_mut().enumerate() {
+            let pressed = bits[i / 3] & BUTTONS[i % 3] as u8 != 0;
+            if button.held && !pressed {
+                button.released = Some(now);
+            }
+            button.held = pressed;
+        }
+    }
+
+    pub fn set_base(&mut self, rgb: &[RGB8]) {
+        for (led, c) in self.base.iter_mut().zip(rgb) {
+            *led = *c;
+        }
+    }
+
+    /// 游戏设置了 board 1 的颜色
+    pub fn game_update(&mut self, rgb: &[RGB8], now: Instant) {
+        self.set_base(rgb);
+        self.last_game_update = Some(now);
+    }
+
+    /// 闪光的强度 0.0-1.0
+    fn intensity(&self, button: &Button, now: Instant) -> f32 {
+        if button.held {
+            return 1.0;
+        }
+        let Some(released) = button.released else {
+            return 0.0;
+        };
+        let fade = Duration::from_millis(self.config.fade_ms);
+        let elapsed = now.saturating_duration_since(released);
+        if elapsed >= fade {
+            0.0
+        } else {
+            1.0 - elapsed.as_secs_f32() / fade.as_secs_f32()
+        }
+    }
+
+    /// 有闪光时返回叠加后的颜色
+    pub fn blend(&self, now: Instant) -> Option<[RGB8; BOARD1_LEDS]> {
+        let idle = Duration::from_millis(self.config.idle_ms);
+        if self.config.only_when_idle
+            && self
+                .last_game_update
+                .is_some_and(|t| now.saturating_duration_since(t) < idle)
+        {
+            return None;
+        }
+        let k: [f32; BOARD1_LEDS] = std::array::from_fn(|i| self.intensity(&self.buttons[i], now));
+        if k.iter().all(|k| *k == 0.0) {
+            return None;
+        }
+        let [r, g, b] = self.config.color.map(f32::from);
+        let lerp = |from: u8, to: f32, k: f32| {
+            (f32::from(from) + (to - f32::from(from)) * k).round() as u8
+        };
+        Some(std::array::from_fn(|i| {
+            let c = self.base[i];
+            RGB8::new(lerp(c.r, r, k[i]), lerp(c.g, g, k[i]), lerp(c.b, b, k[i]))
+        }))
+    }
+
+    /// 闪光变化时按帧率返回要发送的颜色，闪光结束时返回原来的颜色
+    pub fn tick(&mut self, now: Instant) -> Option<[RGB8; BOARD1_LEDS]> {
+        let frame = self.blend(now);
+        let was_visible = std::mem::replace(&mut self.visible, frame.is_some());
+        let Some(frame) = frame else {
+            return was_visible.then_some(self.base);
+        };
+        let period = Duration::from_secs(1) / self.config.fps.max(1);
+        // 刚按下时立即发送
+        let due = !was_visible || self.last_frame.is_none_or(|t| now - t >= period);
+        if !due {
+            return None;
+        }
+        self.last_frame = Some(now);
+        Some(frame)
+    }
+
+    /// 其他途径已经发送了叠加后的颜色
+    pub fn mark_sent(&mut self, now: Instant) {
+        self.visible = self.blend(now).is_some();
+        self.last_frame = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod led_feedback_test {
+    use super::*;
+    use crate::config::Config;
+
+    fn feedback(f: impl FnOnce(&mut ButtonFeedbackConfig)) -> ButtonFeedback {
+        let mut config = ButtonFeedbackConfig {
+            enabled: true,
+            color: [255, 255, 255],
+            fade_ms: 100,
+            fps: 100,
+            ..Config::default().button_feedback
+        };
+        f(&mut config);
+        ButtonFeedback::new(&config)
+    }
+
+    #[test]
+    fn fade_test() {
+        let mut fb = feedback(|_| {});
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        fb.game_update(&[RGB8::new(0, 0, 255); 6], start);
+        assert_eq!(fb.tick(start), None);
+
+        // 左 Btn2 与右 Btn3
+        fb.update_buttons(GameBtn::Btn2 as u8, GameBtn::Btn3 as u8, at(0));
+        let frame = fb.tick(at(0)).unwrap();
+        assert_eq!(frame[1], RGB8::new(255, 255, 255));
+        assert_eq!(frame[5], RGB8::new(255, 255, 255));
+        assert_eq!(frame[0], RGB8::new(0, 0, 255));
+        // 帧率限制，按住时不变
+        assert_eq!(fb.tick(at(5)), None);
+        assert_eq!(fb.tick(at(10)), Some(frame));
+
+        fb.update_buttons(0, GameBtn::Btn3 as u8, at(20));
+        let frame = fb.tick(at(70)).unwrap();
+        assert_eq!(frame[1], RGB8::new(128, 128, 255));
+        assert_eq!(frame[5], RGB8::new(255, 255, 255));
+
+        // 淡出结束后恢复原来的颜色，之后不再发送
+        fb.update_buttons(0, 0, at(80));
+        assert!(fb.tick(at(150)).is_some());
+        assert_eq!(fb.tick(at(200)), Some([RGB8::new(0, 0, 255); 6]));
+        assert_eq!(fb.tick(at(300)), None);
+    }
+
+    #[test]
+    fn only_when_idle_test() {
+        let mut fb = feedback(|c| {
+            c.only_when_idle = true;
+            c.idle_ms = 1000;
+        });
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        fb.update_buttons(GameBtn::Btn1 as u8, 0, start);
+        assert!(fb.blend(start).is_some());
+
+        fb.game_update(&[RGB8::default(); 6], at(10));
+        assert_eq!(fb.blend(at(500)), None);
+        assert!(fb.blend(at(1010)).is_some());
+    }
+}
@@ -0,0 +1,175 @@
+//! board 0 的 LED 分区及其到实际灯带的映射
+//!
+//! 分区与 `mu3_io_led_set_colors` 的说明一致，每个输出可以把若干分区
+//! 按反向、偏移和数量缩放映射到自己的灯带上。
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedZone {
+    LeftSide,
+    LeftPillarLower,
+    LeftPillarCenter,
+    LeftPillarUpper,
+    Billboard,
+    RightPillarUpper,
+    RightPillarCenter,
+    RightPillarLower,
+    RightSide,
+}
+
+impl LedZone {
+    /// 在 board 0 中的下标范围
+    pub fn range(self) -> Range<usize> {
+        match self {
+            LedZone::LeftSide => 0..2,
+            LedZone::LeftPillarLower => 2..9,
+            LedZone::LeftPillarCenter => 9..18,
+            LedZone::LeftPillarUpper => 18..25,
+            LedZone::Billboard => 25..36,
+            LedZone::RightPillarUpper => 36..43,
+            LedZone::RightPillarCenter => 43..52,
+            LedZone::RightPillarLower => 52..59,
+            LedZone::RightSide => 59..61,
+        }
+    }
+}
+
+/// 把一个分区映射到输出灯带的 `offset..offset + count`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedRoute {
+    pub zone: LedZone,
+    #[serde(default)]
+    pub offset: usize,
+    /// 输出的 LED 数量，与分区不同时按比例取最近的 LED，不写时与分区相同
+    #[serde(default)]
+    pub count: Option<usize>,
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+/// 一个输出上的全部映射，为空时该输出直接使用 board 0 的原始数据
+#[derive(Debug, Clone, Default)]
+pub struct LedLayout {
+    routes: Vec<LedRoute>,
+}
+
+impl LedLayout {
+    pub fn new(routes: Vec<LedRoute>) -> Self {
+        Self { routes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// 输出灯带的长度
+    pub fn len(&self) -> usize {
+        self.routes
+            .iter()
+            .map(|r| r.offset + r.count.unwrap_or(r.zone.range().len()))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 有映射时把 board 0 换成输出灯带，其他情况原样返回
+    pub fn apply<'a>(&self, board: u8, rgb: &'a [RGB8]) -> Cow<'a, [RGB8]> {
+        if board == 0 && !self.is_empty() {
+            Cow::Owned(self.render(rgb))
+        } else {
+            Cow::Borrowed(rgb)
+        }
+    }
+
+    /// 按映射生成输出灯带，没有映射到的 LED 为黑色
+    pub fn render(&self, board0: &[RGB8]) -> Vec<RGB8> {
+        let mut strip = vec![RGB8::default(); self.len()];
+        for route in &self.routes {
+            let Some(zone) = board0.get(route.zone.range()) else {
+                continue;
+            };
+            let count = route.count.unwrap_or(zone.len());
+            for (i, led) in strip[route.offset..route.offset + count]
+                .iter_mut()
+                .enumerate()
+            {
+                let src = i * zone.len() / count;
+                let src = if route.reverse {
+                    zone.len() - 1 - src
+                } else {
+                    src
+                };
+                *led = zone[src];
+            }
+        }
+        strip
+    }
+}
+
+#[cfg(test)]
+mod led_layout_test {
+    use super::*;
+
+    fn board0() -> Vec<RGB8> {
+        (0..61).map(|i| RGB8::new(i, 0, 0)).collect()
+    }
+
+    fn reds(strip: &[RGB8]) -> Vec<u8> {
+        strip.iter().map(|c| c.r).collect()
+    }
+
+    #[test]
+    fn zones_test() {
+        use LedZone::*;
+        let zones = [
+            LeftSide,
+            LeftPillarLower,
+            LeftPillarCenter,
+            LeftPillarUpper,
+            Billboard,
+            RightPillarUpper,
+            RightPillarCenter,
+            RightPillarLower,
+            RightSide,
+        ];
+        let mut next = 0;
+        for zone in zones {
+            assert_eq!(zone.range().start, next, "{zone:?}");
+            next = zone.range().end;
+        }
+        assert_eq!(next, 61);
+    }
+
+    #[test]
+    fn render_test() {
+        let route = |zone, offset, count, reverse| LedRoute {
+            zone,
+            offset,
+            count,
+            reverse,
+        };
+        let layout = LedLayout::new(vec![
+            route(LedZone::LeftSide, 0, None, true),
+            // 7 个 LED 拉伸到 14 个
+            route(LedZone::LeftPillarLower, 3, Some(14), false),
+            // 9 个 LED 压缩到 3 个
+            route(LedZone::RightPillarCenter, 20, Some(3), true),
+        ]);
+        assert_eq!(layout.len(), 23);
+
+        let strip = reds(&layout.render(&board0()));
+        assert_eq!(strip[..3], [1, 0, 0]);
+        assert_eq!(strip[3..17], [2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8]);
+        assert_eq!(strip[17..20], [0, 0, 0]);
+        assert_eq!(strip[20..], [51, 48, 45]);
+
+        // board 0 数据不完整时跳过缺少的分区
+        assert_eq!(reds(&layout.render(&board0()[..5]))[..3], [1, 0, 0]);
+        assert!(LedLayout::default().render(&board0()).is_empty());
+    }
+}
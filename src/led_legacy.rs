@@ -0,0 +1,127 @@
+//! 旧版 API（0x0100）`mu3_io_set_led` 的位域
+//!
+//! 每个按键灯占 3 位，分别控制 R、G、B 是否点亮，位顺序与 HID 固件一致:
+//!
+//! | 按键灯 | R | G | B |
+//! | --- | --- | --- | --- |
+//! | 左 Btn1 | 23 | 19 | 22 |
+//! | 左 Btn2 | 20 | 21 | 18 |
+//! | 左 Btn3 | 17 | 16 | 15 |
+//! | 右 Btn1 | 14 | 13 | 12 |
+//! | 右 Btn2 | 11 | 10 | 9 |
+//! | 右 Btn3 | 8 | 7 | 6 |
+//!
+//! 按键灯的顺序与 board 1 的 6 个 LED 相同。
+
+use rgb::RGB8;
+
+use crate::config::LegacyLedConfig;
+use crate::led_recording::BOARD1_LEDS;
+
+/// board 1 上的按键灯，顺序与 LED 下标相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonLed {
+    LeftBtn1,
+    LeftBtn2,
+    LeftBtn3,
+    RightBtn1,
+    RightBtn2,
+    RightBtn3,
+}
+
+impl ButtonLed {
+    pub const LEFT: [ButtonLed; 3] = [
+        ButtonLed::LeftBtn1,
+        ButtonLed::LeftBtn2,
+        ButtonLed::LeftBtn3,
+    ];
+    pub const RIGHT: [ButtonLed; 3] = [
+        ButtonLed::RightBtn1,
+        ButtonLed::RightBtn2,
+        ButtonLed::RightBtn3,
+    ];
+
+    /// R、G、B 对应的位
+    fn bits(self) -> [u32; 3] {
+        match self {
+            ButtonLed::LeftBtn1 => [23, 19, 22],
+            ButtonLed::LeftBtn2 => [20, 21, 18],
+            ButtonLed::LeftBtn3 => [17, 16, 15],
+            ButtonLed::RightBtn1 => [14, 13, 12],
+            ButtonLed::RightBtn2 => [11, 10, 9],
+            ButtonLed::RightBtn3 => [8, 7, 6],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LegacyLedState(pub u32);
+
+impl LegacyLedState {
+    /// R、G、B 是否点亮
+    pub fn channels(self, led: ButtonLed) -> [bool; 3] {
+        led.bits().map(|bit| (self.0 >> bit) & 1 == 1)
+    }
+
+    /// 一组按键灯中点亮的通道数
+    pub fn lit(self, leds: &[ButtonLed]) -> usize {
+        leds.iter()
+            .flat_map(|led| self.channels(*led))
+            .filter(|lit| *lit)
+            .count()
+    }
+
+    pub fn color(self, led: ButtonLed, config: &LegacyLedConfig) -> RGB8 {
+        let [r, g, b] = self.channels(led);
+        let level = |lit: bool, i: usize| if lit { config.on[i] } else { config.off[i] };
+        RGB8::new(level(r, 0), level(g, 1), level(b, 2))
+    }
+
+    /// 转换为 board 1 的颜色
+    pub fn colors(self, config: &LegacyLedConfig) -> [RGB8; BOARD1_LEDS] {
+        let leds = [ButtonLed::LEFT, ButtonLed::RIGHT].concat();
+        std::array::from_fn(|i| self.color(leds[i], config))
+    }
+}
+
+#[cfg(test)]
+mod led_legacy_test {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn decode_test() {
+        let state = LegacyLedState(1 << 23 | 1 << 21 | 1 << 6);
+        assert_eq!(state.channels(ButtonLed::LeftBtn1), [true, false, false]);
+        assert_eq!(state.channels(ButtonLed::LeftBtn2), [false, true, false]);
+        assert_eq!(state.channels(ButtonLed::RightBtn3), [false, false, true]);
+        assert_eq!(state.lit(&ButtonLed::LEFT), 2);
+        assert_eq!(state.lit(&ButtonLed::RIGHT), 1);
+
+        let colors = state.colors(&Config::default().legacy_led);
+        assert_eq!(colors[0], RGB8::new(255, 0, 0));
+        assert_eq!(colors[1], RGB8::new(0, 255, 0));
+        assert_eq!(colors[5], RGB8::new(0, 0, 255));
+        assert!(colors[2..5].iter().all(|c| *c == RGB8::default()));
+
+        // 每一位只属于一个通道
+        let all: u32 = [ButtonLed::LEFT, ButtonLed::RIGHT]
+            .concat()
+            .iter()
+            .flat_map(|led| led.bits())
+            .map(|bit| 1 << bit)
+            .sum();
+        assert_eq!(all, 0x00FF_FFC0);
+    }
+
+    #[test]
+    fn config_test() {
+        let config = LegacyLedConfig {
+            on: [200, 100, 50],
+            off: [0, 0, 10],
+        };
+        let colors = LegacyLedState(1 << 14 | 1 << 13).colors(&config);
+        assert_eq!(colors[3], RGB8::new(200, 100, 10));
+        assert_eq!(colors[0], RGB8::new(0, 0, 10));
+    }
+}
@@ -0,0 +1,170 @@
+//! 每个 LED 驱动各自的输出队列
+//!
+//! 跳过与上一次发送相同的帧；超过 `max_fps` 的帧只保留每块板最新的一帧，
+//! 到了发送时间后在 `mu3_io_poll` 中发送。设备重新连接后重新发送每块板最近的一帧。
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::config::LedOutputConfig;
+use crate::led_recording::LedCall;
+
+#[derive(Debug, Default)]
+struct Board {
+    last: Option<LedCall>,
+    /// 因限速延后、还没有发送的帧
+    pending: Option<LedCall>,
+    sent: Option<Instant>,
+}
+
+impl Board {
+    fn send(&mut self, call: LedCall, now: Instant) -> LedCall {
+        self.last = Some(call.clone());
+        self.pending = None;
+        self.sent = Some(now);
+        call
+    }
+}
+
+pub struct LedQueue {
+    config: LedOutputConfig,
+    boards: BTreeMap<u8, Board>,
+}
+
+impl LedQueue {
+    pub fn new(config: &LedOutputConfig) -> Self {
+        Self {
+            config: config.clone(),
+            boards: BTreeMap::new(),
+        }
+    }
+
+    fn period(&self) -> Duration {
+        match self.config.max_fps {
+            0 => Duration::ZERO,
+            fps => Duration::from_secs(1) / fps,
+        }
+    }
+
+    /// 返回现在就要发送的帧
+    pub fn push(&mut self, call: LedCall, now: Instant) -> Option<LedCall> {
+        let period = self.period();
+        let board = self.boards.entry(call.board()).or_default();
+        if self.config.dedupe && board.last.as_ref() == Some(&call) {
+            // 延后的帧已经被新的帧取代
+            board.pending = None;
+            return None;
+        }
+        if board
+            .sent
+            .is_some_and(|t| now.saturating_duration_since(t) < period)
+        {
+            board.pending = Some(call);
+            return None;
+        }
+        Some(board.send(call, now))
+    }
+
+    /// 之前的帧可能没有送达，重新发送每块板最近的一帧，之后相同的帧也不再跳过
+    pub fn resend(&mut self) {
+        for board in self.boards.values_mut() {
+            if let Some(last) = board.last.take() {
+                board.pending.get_or_insert(last);
+            }
+        }
+    }
+
+    /// 返回到了发送时间的延后的帧
+    pub fn flush(&mut self, now: Instant) -> Vec<LedCall> {
+        let period = self.period();
+        self.boards
+            .values_mut()
+            .filter(|b| {
+                b.sent
+                    .is_none_or(|t| now.saturating_duration_since(t) >= period)
+            })
+            .filter_map(|b| b.pending.take().map(|call| b.send(call, now)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod led_output_test {
+    use rgb::RGB8;
+
+    use super::*;
+
+    fn colors(board: u8, v: u8) -> LedCall {
+        LedCall::Colors(board, vec![RGB8::new(v, v, v); 6])
+    }
+
+    #[test]
+    fn dedupe_test() {
+        let mut queue = LedQueue::new(&LedOutputConfig {
+            dedupe: true,
+            max_fps: 0,
+        });
+        let now = Instant::now();
+        assert_eq!(queue.push(colors(1, 1), now), Some(colors(1, 1)));
+        assert_eq!(queue.push(colors(1, 1), now), None);
+        // 每块板分别比较
+        assert_eq!(queue.push(colors(0, 1), now), Some(colors(0, 1)));
+        assert_eq!(
+            queue.push(LedCall::Legacy(1), now),
+            Some(LedCall::Legacy(1))
+        );
+        assert_eq!(queue.push(colors(1, 1), now), Some(colors(1, 1)));
+
+        let mut queue = LedQueue::new(&LedOutputConfig {
+            dedupe: false,
+            max_fps: 0,
+        });
+        assert!(queue.push(colors(1, 1), now).is_some());
+        assert!(queue.push(colors(1, 1), now).is_some());
+    }
+
+    #[test]
+    fn rate_limit_test() {
+        let mut queue = LedQueue::new(&LedOutputConfig {
+            dedupe: true,
+            max_fps: 100,
+        });
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(queue.push(colors(1, 1), at(0)).is_some());
+        // 10 毫秒内的帧只保留最新的一帧
+        assert_eq!(queue.push(colors(1, 2), at(2)), None);
+        assert_eq!(queue.push(colors(1, 3), at(4)), None);
+        assert!(queue.flush(at(8)).is_empty());
+        assert_eq!(queue.flush(at(10)), [colors(1, 3)]);
+
+        // 回到已发送的颜色时不再发送延后的帧
+        assert_eq!(queue.push(colors(1, 4), at(12)), None);
+        assert_eq!(queue.push(colors(1, 3), at(14)), None);
+        assert!(queue.flush(at(30)).is_empty());
+        assert_eq!(queue.push(colors(1, 5), at(31)), Some(colors(1, 5)));
+    }
+
+    #[test]
+    fn resend_test() {
+        let mut queue = LedQueue::new(&LedOutputConfig {
+            dedupe: true,
+            max_fps: 100,
+        });
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(queue.push(colors(0, 1), at(0)).is_some());
+        assert!(queue.push(colors(1, 1), at(0)).is_some());
+        assert_eq!(queue.push(colors(1, 2), at(2)), None);
+
+        // 延后的帧比最近发送的帧新
+        queue.resend();
+        assert_eq!(queue.flush(at(10)), [colors(0, 1), colors(1, 2)]);
+        assert_eq!(queue.push(colors(0, 1), at(20)), None);
+
+        // 新的帧取代要重新发送的帧，相同的帧也不再跳过
+        queue.resend();
+        assert_eq!(queue.push(colors(0, 1), at(20)), Some(colors(0, 1)));
+        assert_eq!(queue.flush(at(30)), [colors(1, 2)]);
+    }
+}
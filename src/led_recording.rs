@@ -0,0 +1,270 @@
+//! LED 录制文件，供 `led-preview` 离线预览
+//!
+//! 文件以 8 字节头 `OILR 版本 0 0 0` 开始，之后每次 LED 调用一条记录:
+//! 距上一条的微秒数 u32、board u8、数据长度 u16、数据，多字节字段均为小端。
+//! board 为 [`LEGACY_BOARD`] 时数据为 `mu3_io_set_led` 的 u32，否则为 RGB 数据。
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use rgb::RGB8;
+
+use crate::led_legacy::LegacyLedState;
+
+const MAGIC: [u8; 4] = *b"OILR";
+const VERSION: u8 = 1;
+/// 旧版 `mu3_io_set_led` 调用
+pub const LEGACY_BOARD: u8 = 0xFF;
+pub const BOARD0_LEDS: usize = 61;
+pub const BOARD1_LEDS: usize = 6;
+/// 缓冲的记录写入文件的间隔
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedCall {
+    Legacy(u32),
+    Colors(u8, Vec<RGB8>),
+}
+
+impl LedCall {
+    /// 旧版 API 只控制 board 1
+    pub fn board(&self) -> u8 {
+        match self {
+            LedCall::Legacy(_) => 1,
+            LedCall::Colors(board, _) => *board,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedEvent {
+    /// 距录制开始的微秒数
+    pub time_us: u64,
+    pub call: LedCall,
+}
+
+fn write_header(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(&MAGIC)?;
+    w.write_all(&[VERSION, 0, 0, 0])
+}
+
+fn write_event(w: &mut impl Write, delta_us: u32, call: &LedCall) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(delta_us)?;
+    match call {
+        LedCall::Legacy(data) => {
+            w.write_u8(LEGACY_BOARD)?;
+            w.write_u16::<LittleEndian>(4)?;
+            w.write_u32::<LittleEndian>(*data)
+        }
+        LedCall::Colors(board, rgb) => {
+            let rgb = &rgb[..rgb.len().min(usize::from(u16::MAX) / 3)];
+            w.write_u8(*board)?;
+            w.write_u16::<LittleEndian>((rgb.len() * 3) as u16)?;
+            let data: Vec<u8> = rgb.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+            w.write_all(&data)
+        }
+    }
+}
+
+/// 读取录制文件，末尾不完整的记录会被忽略
+pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<LedEvent>> {
+    read_from(BufReader::new(File::open(path)?))
+}
+
+pub fn read_from(mut r: impl Read) -> io::Result<Vec<LedEvent>> {
+    let mut header = [0u8; 8];
+    r.read_exact(&mut header)?;
+    if header[..4] != MAGIC || header[4] != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "不是 LED 录制文件或版本不支持",
+        ));
+    }
+
+    let mut events = vec![];
+    let mut time_us = 0u64;
+    loop {
+        let mut head = [0u8; 7];
+        match r.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let mut head = &head[..];
+        time_us += u64::from(head.read_u32::<LittleEndian>()?);
+        let board = head.read_u8()?;
+        let mut data = vec![0u8; usize::from(head.read_u16::<LittleEndian>()?)];
+        match r.read_exact(&mut data) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let call = if board == LEGACY_BOARD {
+            LedCall::Legacy((&data[..]).read_u32::<LittleEndian>()?)
+        } else {
+            let rgb = data
+                .chunks_exact(3)
+                .map(|c| RGB8::new(c[0], c[1], c[2]))
+                .collect();
+            LedCall::Colors(board, rgb)
+        };
+        events.push(LedEvent { time_us, call });
+    }
+    Ok(events)
+}
+
+/// 把 LED 调用追加写入录制文件
+pub struct LedRecordWriter {
+    writer: BufWriter<File>,
+    start: Instant,
+    last_us: u64,
+    flushed: Instant,
+}
+
+impl LedRecordWriter {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_header(&mut writer)?;
+        let now = Instant::now();
+        Ok(Self {
+            writer,
+            start: now,
+            last_us: 0,
+            flushed: now,
+        })
+    }
+
+    pub fn record(&mut self, call: &LedCall) -> io::Result<()> {
+        let time_us = u64::try_from(self.start.elapsed().as_micros()).unwrap_or(u64::MAX);
+        // 间隔超过 u32 时（约 71 分钟）按最大值记录
+        let delta = time_us.saturating_sub(self.last_us).min(u32::MAX.into()) as u32;
+        self.last_us += u64::from(delta);
+        write_event(&mut self.writer, delta, call)?;
+        if self.flushed.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.flushed = Instant::now();
+        self.writer.flush()
+    }
+}
+
+/// 某一时刻两块板的 LED 状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedState {
+    pub board0: [RGB8; BOARD0_LEDS],
+    pub board1: [RGB8; BOARD1_LEDS],
+}
+
+impl Default for LedState {
+    fn default() -> Self {
+        Self {
+            board0: [RGB8::default(); BOARD0_LEDS],
+            board1: [RGB8::default(); BOARD1_LEDS],
+        }
+    }
+}
+
+impl LedState {
+    pub fn apply(&mut self, call: &LedCall) {
+        match call {
+            LedCall::Legacy(data) => {
+                self.board1 = LegacyLedState(*data).colors(&Default::default());
+            }
+            LedCall::Colors(0, rgb) => {
+                for (led, c) in self.board0.iter_mut().zip(rgb) {
+                    *led = *c;
+                }
+            }
+            LedCall::Colors(1, rgb) => {
+                for (led, c) in self.board1.iter_mut().zip(rgb) {
+                    *led = *c;
+                }
+            }
+            LedCall::Colors(..) => {}
+        }
+    }
+}
+
+/// 以固定帧率采样录制，每帧为该时刻之前所有调用累积的状态
+pub fn sample(events: &[LedEvent], fps: u32) -> Vec<LedState> {
+    let Some(last) = events.last() else {
+        return vec![];
+    };
+    let period_us = 1_000_000 / u64::from(fps.max(1));
+    let mut state = LedState::default();
+    let mut next = 0;
+    // 最后一帧不早于最后一次调用
+    (0..=last.time_us.div_ceil(period_us))
+        .map(|i| {
+            while let Some(event) = events.get(next).filter(|e| e.time_us <= i * period_us) {
+                state.apply(&event.call);
+                next += 1;
+            }
+            state
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod led_recording_test {
+    use super::*;
+
+    #[test]
+    fn binary_test() {
+        let calls = [
+            LedCall::Colors(0, vec![RGB8::new(1, 2, 3); BOARD0_LEDS]),
+            LedCall::Legacy(0x0080_0040),
+            LedCall::Colors(1, vec![RGB8::new(4, 5, 6); BOARD1_LEDS]),
+        ];
+        let mut data = vec![];
+        write_header(&mut data).unwrap();
+        for (i, call) in calls.iter().enumerate() {
+            write_event(&mut data, i as u32 * 1000, call).unwrap();
+        }
+
+        let events = read_from(&data[..]).unwrap();
+        assert_eq!(
+            events.iter().map(|e| e.time_us).collect::<Vec<_>>(),
+            [0, 1000, 3000]
+        );
+        assert_eq!(
+            events.into_iter().map(|e| e.call).collect::<Vec<_>>(),
+            calls
+        );
+
+        // 末尾不完整的记录被忽略
+        assert_eq!(read_from(&data[..data.len() - 1]).unwrap().len(), 2);
+        data[0] = 0;
+        assert!(read_from(&data[..]).is_err());
+    }
+
+    #[test]
+    fn sample_test() {
+        let red = RGB8::new(255, 0, 0);
+        let events = [
+            LedEvent {
+                time_us: 0,
+                call: LedCall::Colors(1, vec![red; BOARD1_LEDS]),
+            },
+            LedEvent {
+                time_us: 150_000,
+                call: LedCall::Colors(0, vec![red; 2]),
+            },
+        ];
+        let frames = sample(&events, 10);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].board1, [red; BOARD1_LEDS]);
+        assert_eq!(frames[0].board0[0], RGB8::default());
+        // 只更新调用中给出的 LED
+        assert_eq!(frames[1].board0[0], RGB8::default());
+        assert_eq!(frames[2].board0[..3], [red, red, RGB8::default()]);
+        assert!(sample(&[], 30).is_empty());
+    }
+}
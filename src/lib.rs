@@ -3,17 +3,140 @@
 use drivers::Drivers;
 use lazy_static::lazy_static;
 use rgb::Rgb;
-use std::sync::RwLock;
-use windows::Win32::System::Console;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::ffi::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, Once, PoisonError, TryLockError};
 
 use enums::HResult;
+use ipc::InputSnapshot;
+use platform::ConsoleSink;
+use poller::{InputCell, Poller};
 
+mod calibration;
 mod config;
 mod drivers;
 mod enums;
+mod filter;
+mod ipc;
+mod keys;
+mod led_color;
+mod led_effects;
+mod led_feedback;
+mod led_layout;
+mod led_legacy;
+mod led_output;
+pub mod led_recording;
+mod platform;
+mod poller;
+pub mod recording;
 
 lazy_static! {
-    static ref DRIVERS: RwLock<Drivers> = RwLock::new(Drivers::new());
+    static ref DRIVERS: Mutex<Drivers> = Mutex::new(Drivers::new());
+    static ref POLLER: Mutex<Option<Poller>> = Mutex::new(None);
+}
+
+/// 每次 poll 后汇总的输入，按键和摇杆查询只读取这里，不访问驱动
+static INPUT: InputCell = InputCell::new();
+/// 为 true 时由后台线程 poll，`mu3_io_poll` 只返回最近一次的结果
+static THREADED: AtomicBool = AtomicBool::new(false);
+/// 已经由 `mu3_io_init` 或 `mu3_io_led_init` 初始化
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// 某个驱动 panic 后锁会中毒，继续使用其中的数据，不让之后的每次调用都失败
+fn lock_drivers() -> MutexGuard<'static, Drivers> {
+    DRIVERS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// 捕获 panic，不让它展开进游戏进程，出错时返回 `fallback`
+fn ffi_guard<T>(name: &str, fallback: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|e| {
+        println!("Ongeki IO: {name} 发生错误 {}", panic_message(&*e));
+        DRIVERS.clear_poison();
+        fallback
+    })
+}
+
+/// 轮询所有驱动并发布输入快照，驱动 panic 时松开所有按键
+fn poll_drivers() -> HResult {
+    let (input, result) = ffi_guard(
+        "mu3_io_poll",
+        (InputSnapshot::default(), HResult::E_FAIL),
+        || {
+            let mut drivers = lock_drivers();
+            let result = drivers.poll();
+            (drivers.input(), result)
+        },
+    );
+    INPUT.store(input, result);
+    result
+}
+
+fn start_poller(rate_hz: u32) {
+    let poller = Poller::spawn(rate_hz, || {
+        poll_drivers();
+    });
+    match poller {
+        Ok(poller) => {
+            println!("Ongeki IO: 后台轮询 {rate_hz} Hz");
+            THREADED.store(true, Ordering::Release);
+            *POLLER.lock().unwrap_or_else(PoisonError::into_inner) = Some(poller);
+        }
+        Err(e) => println!("Ongeki IO: 无法启动后台轮询线程，改为同步轮询 {e}"),
+    }
+}
+
+fn stop_poller() {
+    THREADED.store(false, Ordering::Release);
+    let poller = POLLER.lock().unwrap_or_else(PoisonError::into_inner).take();
+    if let Some(mut poller) = poller {
+        poller.stop();
+    }
+}
+
+extern "C" {
+    fn atexit(callback: extern "C" fn()) -> c_int;
+}
+
+/// 进程退出时保存摇杆校准和录制文件
+///
+/// DLL 中注册的回调在卸载时执行，此时持有加载器锁，不能等待其他线程：
+/// 不停止后台轮询，拿不到锁（正在 poll）时放弃保存
+extern "C" fn save_on_exit() {
+    let _ = panic::catch_unwind(|| match DRIVERS.try_lock() {
+        Ok(mut drivers) => drivers.shutdown(),
+        Err(TryLockError::Poisoned(e)) => e.into_inner().shutdown(),
+        Err(TryLockError::WouldBlock) => {}
+    });
+}
+
+fn register_exit_hook() {
+    static REGISTERED: Once = Once::new();
+    REGISTERED.call_once(|| {
+        if unsafe { atexit(save_on_exit) } != 0 {
+            println!("Ongeki IO: 无法注册退出时的保存");
+        }
+    });
+}
+
+fn panic_message(e: &(dyn Any + Send)) -> &str {
+    e.downcast_ref::<&str>()
+        .copied()
+        .or_else(|| e.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("未知错误")
+}
+
+/// 停止后台轮询并保存驱动状态（摇杆校准、录制文件）
+///
+/// 不属于 segatools 的 API，供加载本库的其他程序在退出前调用；
+/// segatools 下由初始化时注册的退出回调保存。
+#[no_mangle]
+pub extern "C" fn ongeki_io_shutdown() {
+    ffi_guard("ongeki_io_shutdown", (), || {
+        stop_poller();
+        lock_drivers().shutdown();
+    });
 }
 
 #[no_mangle]
@@ -21,65 +144,82 @@ pub extern "C" fn mu3_io_get_api_version() -> u16 {
     0x0101
 }
 
-#[no_mangle]
-pub extern "C" fn mu3_io_init() -> HResult {
-    unsafe {
-        let _ = Console::AttachConsole(Console::ATTACH_PARENT_PROCESS);
-    }
+/// 重新创建所有驱动，只输出 LED 的进程不启动后台轮询
+fn init_drivers(led_only: bool) -> HResult {
+    platform::Console.attach();
     color_backtrace::install();
 
     println!("Ongeki IO: 启动！");
 
-    let mut drivers = DRIVERS.write().unwrap();
-    drivers.init();
+    stop_poller();
+    let mut drivers = lock_drivers();
+    *drivers = Drivers::new();
+    let result = drivers.init(led_only);
+    register_exit_hook();
+    INPUT.store(InputSnapshot::default(), result);
+    let rate_hz = drivers.poll_rate().filter(|_| !led_only);
+    drop(drivers);
+    INITIALIZED.store(true, Ordering::Release);
 
-    HResult::Ok
+    if let Some(rate_hz) = rate_hz.filter(|_| result.is_ok()) {
+        start_poller(rate_hz);
+    }
+    result
 }
 
 #[no_mangle]
-pub extern "C" fn mu3_io_poll() -> HResult {
-    let mut drivers = DRIVERS.write().unwrap();
-    drivers.poll();
+pub extern "C" fn mu3_io_init() -> HResult {
+    ffi_guard("mu3_io_init", HResult::E_FAIL, || init_drivers(false))
+}
 
-    HResult::Ok
+#[no_mangle]
+pub extern "C" fn mu3_io_poll() -> HResult {
+    if THREADED.load(Ordering::Acquire) {
+        return INPUT.result();
+    }
+    poll_drivers()
 }
 
 #[no_mangle]
 pub extern "C" fn mu3_io_get_opbtns(option_btns: *mut u8) {
-    let drivers = DRIVERS.read().unwrap();
     if !option_btns.is_null() {
-        unsafe { *option_btns = drivers.op_btns() }
+        unsafe { *option_btns = INPUT.input().op_btns }
     }
 }
 
 #[no_mangle]
 pub extern "C" fn mu3_io_get_gamebtns(left: *mut u8, right: *mut u8) {
-    let drivers = DRIVERS.read().unwrap();
+    let input = INPUT.input();
     if !left.is_null() {
-        unsafe { *left = drivers.left_btns() }
+        unsafe { *left = input.left_btns }
     }
     if !right.is_null() {
-        unsafe { *right = drivers.right_btns() }
+        unsafe { *right = input.right_btns }
     }
 }
 
 #[no_mangle]
 pub extern "C" fn mu3_io_get_lever(pos: *mut i16) {
-    let drivers = DRIVERS.read().unwrap();
     if !pos.is_null() {
-        unsafe { *pos = drivers.lever().unwrap_or(0) }
+        unsafe { *pos = INPUT.input().lever }
     }
 }
 
+/// mu3 进程只调用 `mu3_io_led_init` 和 `mu3_io_led_set_colors`，此时在这里初始化，
+/// 启用共享时作为子进程把 board 0 转发给主进程。已经由 `mu3_io_init` 初始化时不再重复
 #[no_mangle]
 pub extern "C" fn mu3_io_led_init() -> HResult {
-    HResult::Ok
+    ffi_guard("mu3_io_led_init", HResult::E_FAIL, || {
+        if INITIALIZED.load(Ordering::Acquire) {
+            return HResult::S_OK;
+        }
+        init_drivers(true)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn mu3_io_set_led(data: u32) {
-    let mut drivers = DRIVERS.write().unwrap();
-    drivers.set_led(data);
+    ffi_guard("mu3_io_set_led", (), || lock_drivers().set_led(data));
 }
 
 /// Update the RGB LEDs. rgb is a pointer to an array of up to 61 * 3 = 183 bytes.
@@ -98,6 +238,9 @@ pub extern "C" fn mu3_io_set_led(data: u32) {
 ///    [52]-[58]: right pillar lower LEDs
 ///    [59]-[60]: right side button
 ///
+/// The zones are named by `led_layout::LedZone` and can be routed to physical
+/// strips with the `led_routes` option of each output driver.
+///
 /// Board 1 has 6 LEDs:
 ///    [0]-[5]: 3 left and 3 right controller buttons
 ///
@@ -110,7 +253,9 @@ pub extern "C" fn mu3_io_set_led(data: u32) {
 /// Minimum API version: 0x0101
 #[no_mangle]
 pub extern "C" fn mu3_io_led_set_colors(board: u8, rgb: *mut u8) {
-    let mut drivers = DRIVERS.write().unwrap();
+    if rgb.is_null() {
+        return;
+    }
     let len = if board == 0 { 183 } else { 18 };
     let data = unsafe { std::slice::from_raw_parts(rgb, len) };
     let led_colors: Vec<Rgb<u8>> = (0..len)
@@ -118,7 +263,9 @@ pub extern "C" fn mu3_io_led_set_colors(board: u8, rgb: *mut u8) {
         .map(|i| Rgb::new(data[i], data[i + 1], data[i + 2]))
         .collect();
 
-    drivers.set_led_new(board, &led_colors);
+    ffi_guard("mu3_io_led_set_colors", (), || {
+        lock_drivers().set_led_new(board, &led_colors)
+    });
 }
 
 #[cfg(test)]
@@ -132,3 +279,192 @@ mod tests {
         assert_eq!(temp, 857);
     }
 }
+
+#[cfg(test)]
+mod ffi_test {
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    use super::*;
+    use crate::config::{Config, SharedConfig};
+    use crate::drivers::fake::FakeIO;
+    use crate::ipc::{Channel, LedCursor, LedFrame};
+
+    /// FFI 测试共用全局的 DRIVERS，需要串行执行
+    static FFI_LOCK: Mutex<()> = Mutex::new(());
+
+    fn install(fakes: Vec<FakeIO>) -> MutexGuard<'static, ()> {
+        let guard = FFI_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        *lock_drivers() = Drivers::with_fakes(fakes);
+        guard
+    }
+
+    fn game_btns() -> (u8, u8) {
+        let (mut left, mut right) = (0xFF, 0xFF);
+        mu3_io_get_gamebtns(&mut left, &mut right);
+        (left, right)
+    }
+
+    #[test]
+    fn poll_error_test() {
+        let _guard = install(vec![
+            FakeIO {
+                fail: Some(HResult::E_HANDLE),
+                ..Default::default()
+            },
+            FakeIO {
+                left: 0x02,
+                ..Default::default()
+            },
+        ]);
+        assert_eq!(mu3_io_poll(), HResult::S_OK);
+        assert_eq!(game_btns(), (0x02, 0));
+
+        lock_drivers().replace_fake(
+            1,
+            FakeIO {
+                fail: Some(HResult::E_FAIL),
+                ..Default::default()
+            },
+        );
+        assert_eq!(mu3_io_poll(), HResult::E_HANDLE);
+    }
+
+    #[test]
+    fn threaded_poll_test() {
+        let guard = install(vec![FakeIO {
+            left: 0x04,
+            lever: -300,
+            ..Default::default()
+        }]);
+        start_poller(1000);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        // 后台线程被锁住时查询仍然返回最近的快照
+        let held = lock_drivers();
+        assert_eq!(mu3_io_poll(), HResult::S_OK);
+        assert_eq!(game_btns(), (0x04, 0));
+        let mut lever = 0;
+        mu3_io_get_lever(&mut lever);
+        assert_eq!(lever, -300);
+        drop(held);
+
+        stop_poller();
+        drop(guard);
+        let _guard = install(vec![FakeIO {
+            left: 0x01,
+            ..Default::default()
+        }]);
+        assert_eq!(mu3_io_poll(), HResult::S_OK);
+        // 同步轮询时查询同样不需要锁
+        let held = lock_drivers();
+        assert_eq!(game_btns(), (0x01, 0));
+        drop(held);
+    }
+
+    #[test]
+    fn poll_panic_test() {
+        let guard = install(vec![
+            FakeIO {
+                panic_poll: true,
+                ..Default::default()
+            },
+            FakeIO {
+                left: 0x01,
+                lever: 1234,
+                ..Default::default()
+            },
+        ]);
+
+        assert_eq!(mu3_io_poll(), HResult::E_FAIL);
+        assert_eq!(mu3_io_poll(), HResult::E_FAIL);
+        // 锁已恢复，驱动出错期间松开所有按键
+        assert!(!DRIVERS.is_poisoned());
+        assert_eq!(game_btns(), (0, 0));
+
+        drop(guard);
+        let _guard = install(vec![FakeIO {
+            lever: 1234,
+            ..Default::default()
+        }]);
+        assert_eq!(mu3_io_poll(), HResult::S_OK);
+        let mut lever = 0;
+        mu3_io_get_lever(&mut lever);
+        assert_eq!(lever, 1234);
+    }
+
+    #[test]
+    fn input_panic_test() {
+        let _guard = install(vec![FakeIO {
+            panic_input: true,
+            lever: -5,
+            ..Default::default()
+        }]);
+
+        assert_eq!(mu3_io_poll(), HResult::E_FAIL);
+        let mut op = 0xFF;
+        mu3_io_get_opbtns(&mut op);
+        assert_eq!(op, 0);
+        assert_eq!(game_btns(), (0, 0));
+        let mut lever = 1;
+        mu3_io_get_lever(&mut lever);
+        assert_eq!(lever, 0);
+
+        mu3_io_get_opbtns(std::ptr::null_mut());
+        mu3_io_get_gamebtns(std::ptr::null_mut(), std::ptr::null_mut());
+        mu3_io_get_lever(std::ptr::null_mut());
+    }
+
+    #[test]
+    fn led_panic_test() {
+        let _guard = install(vec![FakeIO {
+            panic_led: true,
+            ..Default::default()
+        }]);
+
+        let mut colors = [0u8; 183];
+        mu3_io_set_led(0xFFFF_FFFF);
+        mu3_io_led_set_colors(0, colors.as_mut_ptr());
+        mu3_io_led_set_colors(1, colors.as_mut_ptr());
+        mu3_io_led_set_colors(1, std::ptr::null_mut());
+        assert!(!DRIVERS.is_poisoned());
+        assert_eq!(mu3_io_poll(), HResult::S_OK);
+    }
+
+    #[test]
+    fn led_only_client_test() {
+        let _guard = install(vec![]);
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let shared = SharedConfig {
+            enabled: true,
+            name: dir
+                .join(format!("ongeki-io-ffi-{id}"))
+                .to_string_lossy()
+                .into_owned(),
+            ..Default::default()
+        };
+        let config = Config {
+            shared: shared.clone(),
+            ..Default::default()
+        };
+        let config_path = dir.join(format!("ongeki-io-ffi-{id}.toml"));
+        std::fs::write(&config_path, toml::to_string(&config).unwrap()).unwrap();
+        let owner = Channel::open(&shared).unwrap();
+
+        // mu3 进程不调用 mu3_io_init
+        std::env::set_var("ONGEKI_IO_CONFIG", &config_path);
+        INITIALIZED.store(false, Ordering::Release);
+        assert_eq!(mu3_io_led_init(), HResult::S_OK);
+        let mut colors = [0u8; 183];
+        colors[..3].copy_from_slice(&[1, 2, 3]);
+        mu3_io_led_set_colors(0, colors.as_mut_ptr());
+        let frames = owner.recv_leds(&mut LedCursor::default());
+
+        std::env::remove_var("ONGEKI_IO_CONFIG");
+        INITIALIZED.store(false, Ordering::Release);
+        let _ = std::fs::remove_file(&config_path);
+        let _ = std::fs::remove_file(format!("{}.shm", shared.name));
+        let mut board0 = vec![Rgb::new(0, 0, 0); 61];
+        board0[0] = Rgb::new(1, 2, 3);
+        assert_eq!(frames, [LedFrame::Colors(0, board0)]);
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+use super::{CursorSource, KeySource};
+use crate::enums::HResult;
+
+/// 测试用按键状态，克隆后共享同一份数据
+#[derive(Clone, Default)]
+pub struct FakeKeys {
+    pressed: Arc<Mutex<BTreeSet<i32>>>,
+}
+
+impl FakeKeys {
+    pub fn press(&self, vk: i32) {
+        self.pressed.lock().unwrap().insert(vk);
+    }
+
+    pub fn release(&self, vk: i32) {
+        self.pressed.lock().unwrap().remove(&vk);
+    }
+}
+
+impl KeySource for FakeKeys {
+    fn is_pressed(&self, vk: i32) -> bool {
+        self.pressed.lock().unwrap().contains(&vk)
+    }
+}
+
+/// 测试用光标，克隆后共享同一份数据
+#[derive(Clone)]
+pub struct FakeCursor {
+    x: Arc<Mutex<Result<i32, HResult>>>,
+    width: i32,
+}
+
+impl FakeCursor {
+    pub fn new(width: i32) -> Self {
+        Self {
+            x: Arc::new(Mutex::new(Ok(0))),
+            width,
+        }
+    }
+
+    pub fn set(&self, x: Result<i32, HResult>) {
+        *self.x.lock().unwrap() = x;
+    }
+}
+
+impl CursorSource for FakeCursor {
+    fn cursor_x(&self) -> Result<i32, HResult> {
+        *self.x.lock().unwrap()
+    }
+
+    fn screen_width(&self) -> i32 {
+        self.width
+    }
+}
@@ -0,0 +1,37 @@
+//! 平台相关的输入输出
+//!
+//! 驱动只依赖这里的 trait，Windows 上使用 Win32 API，其他平台使用空实现，
+//! 测试中使用 [`fake`] 里的内存实现。
+
+use crate::enums::HResult;
+
+#[cfg(test)]
+pub mod fake;
+#[cfg(not(windows))]
+mod null;
+#[cfg(windows)]
+mod win32;
+
+#[cfg(not(windows))]
+pub use self::null::{Console, Cursor, Keys};
+#[cfg(windows)]
+pub use self::win32::{Console, Cursor, Keys};
+
+/// 键盘及鼠标按键状态
+pub trait KeySource: Send + Sync {
+    /// `vk` 为 Windows 虚拟键码
+    fn is_pressed(&self, vk: i32) -> bool;
+}
+
+/// 光标位置
+pub trait CursorSource: Send + Sync {
+    /// 光标的 x 坐标，取不到时返回错误码
+    fn cursor_x(&self) -> Result<i32, HResult>;
+    fn screen_width(&self) -> i32;
+}
+
+/// 日志输出的控制台
+pub trait ConsoleSink: Send + Sync {
+    /// 附加到父进程的控制台，使 `println!` 可见
+    fn attach(&self);
+}
@@ -0,0 +1,30 @@
+//! 非 Windows 平台没有全局按键和光标，始终视为未按下、不可用
+
+use super::{ConsoleSink, CursorSource, KeySource};
+use crate::enums::HResult;
+
+pub struct Keys;
+
+impl KeySource for Keys {
+    fn is_pressed(&self, _vk: i32) -> bool {
+        false
+    }
+}
+
+pub struct Cursor;
+
+impl CursorSource for Cursor {
+    fn cursor_x(&self) -> Result<i32, HResult> {
+        Err(HResult::E_NOTIMPL)
+    }
+
+    fn screen_width(&self) -> i32 {
+        0
+    }
+}
+
+pub struct Console;
+
+impl ConsoleSink for Console {
+    fn attach(&self) {}
+}
@@ -0,0 +1,40 @@
+use windows::Win32::Foundation::POINT;
+use windows::Win32::System::Console as Win32Console;
+use windows::Win32::UI::Input::KeyboardAndMouse;
+use windows::Win32::UI::WindowsAndMessaging::{self, SM_CXSCREEN};
+
+use super::{ConsoleSink, CursorSource, KeySource};
+use crate::enums::HResult;
+
+pub struct Keys;
+
+impl KeySource for Keys {
+    fn is_pressed(&self, vk: i32) -> bool {
+        unsafe { KeyboardAndMouse::GetAsyncKeyState(vk) != 0 }
+    }
+}
+
+pub struct Cursor;
+
+impl CursorSource for Cursor {
+    fn cursor_x(&self) -> Result<i32, HResult> {
+        let mut p = POINT::default();
+        unsafe { WindowsAndMessaging::GetCursorPos(&mut p as *mut POINT) }
+            .map_err(|e| HResult(e.code().0))?;
+        Ok(p.x)
+    }
+
+    fn screen_width(&self) -> i32 {
+        unsafe { WindowsAndMessaging::GetSystemMetrics(SM_CXSCREEN) }
+    }
+}
+
+pub struct Console;
+
+impl ConsoleSink for Console {
+    fn attach(&self) {
+        unsafe {
+            let _ = Win32Console::AttachConsole(Win32Console::ATTACH_PARENT_PROCESS);
+        }
+    }
+}
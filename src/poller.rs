@@ -0,0 +1,128 @@
+//! 后台轮询线程
+//!
+//! 启用后驱动在独立线程中按固定频率轮询，结果写入 [`InputCell`]，
+//! 游戏线程只做原子读取，慢速驱动不会拖慢游戏。
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::enums::HResult;
+use crate::ipc::InputSnapshot;
+
+/// 最新一次轮询的输入与结果，读写都不加锁
+pub struct InputCell {
+    input: AtomicU64,
+    result: AtomicI32,
+}
+
+impl InputCell {
+    pub const fn new() -> Self {
+        Self {
+            input: AtomicU64::new(0),
+            result: AtomicI32::new(0),
+        }
+    }
+
+    pub fn store(&self, input: InputSnapshot, result: HResult) {
+        self.input.store(input.pack(), Ordering::Release);
+        self.result.store(result.0, Ordering::Release);
+    }
+
+    pub fn input(&self) -> InputSnapshot {
+        InputSnapshot::unpack(self.input.load(Ordering::Acquire))
+    }
+
+    pub fn result(&self) -> HResult {
+        HResult(self.result.load(Ordering::Acquire))
+    }
+}
+
+pub struct Poller {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Poller {
+    /// 以 `rate_hz` 的频率在新线程中调用 `poll`，处理不过来时跳过落后的周期
+    pub fn spawn(rate_hz: u32, mut poll: impl FnMut() + Send + 'static) -> io::Result<Self> {
+        let period = Duration::from_secs(1) / rate_hz.max(1);
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = thread::Builder::new()
+            .name("ongeki-io-poll".to_string())
+            .spawn({
+                let stop = stop.clone();
+                move || {
+                    let mut next = Instant::now();
+                    while !stop.load(Ordering::Acquire) {
+                        poll();
+                        next += period;
+                        let now = Instant::now();
+                        if next > now {
+                            thread::sleep(next - now);
+                        } else {
+                            next = now;
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// 通知线程退出并等待其结束
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod poller_test {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[test]
+    fn cell_test() {
+        let cell = InputCell::new();
+        assert_eq!(cell.input(), InputSnapshot::default());
+        assert_eq!(cell.result(), HResult::S_OK);
+
+        let input = InputSnapshot {
+            op_btns: 0x04,
+            left_btns: 0x11,
+            right_btns: 0x08,
+            lever: -1234,
+        };
+        cell.store(input, HResult::E_HANDLE);
+        assert_eq!(cell.input(), input);
+        assert_eq!(cell.result(), HResult::E_HANDLE);
+    }
+
+    #[test]
+    fn poller_test() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut poller = Poller::spawn(1000, {
+            let count = count.clone();
+            move || {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        poller.stop();
+
+        let polled = count.load(Ordering::Relaxed);
+        // 只检查线程确实在按频率运行，不对调度精度做要求
+        assert!((10..=150).contains(&polled), "{polled}");
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(count.load(Ordering::Relaxed), polled);
+    }
+}
@@ -0,0 +1,241 @@
+//! 输入录制文件
+//!
+//! 文件以 8 字节头 `OIRC 版本 0 0 0` 开始，之后每次 poll 一条 9 字节记录:
+//! 距上一条的微秒数 u32、功能键、左按键、右按键、摇杆 i16，多字节字段均为小端。
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+use crate::config::RecordConfig;
+use crate::ipc::InputSnapshot;
+
+const MAGIC: [u8; 4] = *b"OIRC";
+const VERSION: u8 = 1;
+/// 缓冲的记录写入文件的间隔
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputRecord {
+    /// 距录制开始的微秒数
+    pub time_us: u64,
+    pub op_btns: u8,
+    pub left_btns: u8,
+    pub right_btns: u8,
+    pub lever: i16,
+}
+
+impl InputRecord {
+    pub fn input(&self) -> InputSnapshot {
+        InputSnapshot {
+            op_btns: self.op_btns,
+            left_btns: self.left_btns,
+            right_btns: self.right_btns,
+            lever: self.lever,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recording {
+    pub records: Vec<InputRecord>,
+}
+
+fn write_header(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(&MAGIC)?;
+    w.write_all(&[VERSION, 0, 0, 0])
+}
+
+fn write_record(w: &mut impl Write, delta_us: u32, input: &InputSnapshot) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(delta_us)?;
+    w.write_all(&[input.op_btns, input.left_btns, input.right_btns])?;
+    w.write_i16::<LittleEndian>(input.lever)
+}
+
+impl Recording {
+    /// 扩展名为 `.json` 时按 JSON 读取，否则按二进制格式读取
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|e| e == "json") {
+            return Ok(serde_json::from_str(&fs::read_to_string(path)?)?);
+        }
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// 末尾不完整的记录（如进程被强制结束）会被忽略
+    pub fn read_from(mut r: impl Read) -> io::Result<Self> {
+        let mut header = [0u8; 8];
+        r.read_exact(&mut header)?;
+        if header[..4] != MAGIC || header[4] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "不是输入录制文件或版本不支持",
+            ));
+        }
+
+        let mut records = vec![];
+        let mut time_us = 0u64;
+        let mut buf = [0u8; 9];
+        loop {
+            match r.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let mut record = &buf[..];
+            time_us += u64::from(record.read_u32::<LittleEndian>()?);
+            records.push(InputRecord {
+                time_us,
+                op_btns: record.read_u8()?,
+                left_btns: record.read_u8()?,
+                right_btns: record.read_u8()?,
+                lever: record.read_i16::<LittleEndian>()?,
+            });
+        }
+        Ok(Self { records })
+    }
+
+    pub fn save_json(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let s = serde_json::to_string_pretty(self)?;
+        fs::write(path, s)
+    }
+}
+
+/// 把每次 poll 后的输入追加写入录制文件
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    start: Instant,
+    last_us: u64,
+    flushed: Instant,
+}
+
+impl InputRecorder {
+    pub fn create(config: &RecordConfig) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&config.path)?);
+        write_header(&mut writer)?;
+        let now = Instant::now();
+        Ok(Self {
+            writer,
+            start: now,
+            last_us: 0,
+            flushed: now,
+        })
+    }
+
+    pub fn record(&mut self, input: InputSnapshot) -> io::Result<()> {
+        self.record_at(input, self.start.elapsed())?;
+        if self.flushed.elapsed() >= FLUSH_INTERVAL {
+            self.writer.flush()?;
+            self.flushed = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn record_at(&mut self, input: InputSnapshot, elapsed: Duration) -> io::Result<()> {
+        let time_us = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        let delta = time_us.saturating_sub(self.last_us);
+        // 间隔超过 u32 时（约 71 分钟）按最大值记录
+        let delta = delta.min(u32::MAX.into()) as u32;
+        self.last_us += u64::from(delta);
+        write_record(&mut self.writer, delta, &input)
+    }
+
+    /// 写入剩余数据
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod recording_test {
+    use super::*;
+
+    fn record(time_us: u64, left_btns: u8, lever: i16) -> InputRecord {
+        InputRecord {
+            time_us,
+            op_btns: 0,
+            left_btns,
+            right_btns: 0x10,
+            lever,
+        }
+    }
+
+    fn encode(recording: &Recording) -> Vec<u8> {
+        let mut data = vec![];
+        write_header(&mut data).unwrap();
+        let mut last_us = 0;
+        for record in &recording.records {
+            let delta = (record.time_us - last_us) as u32;
+            write_record(&mut data, delta, &record.input()).unwrap();
+            last_us = record.time_us;
+        }
+        data
+    }
+
+    #[test]
+    fn binary_test() {
+        let recording = Recording {
+            records: vec![
+                record(0, 0, 0),
+                record(1000, 0x01, -1),
+                record(5_000_000, 0x1F, i16::MAX),
+            ],
+        };
+        let mut data = encode(&recording);
+        assert_eq!(data.len(), 8 + 3 * 9);
+        assert_eq!(Recording::read_from(&data[..]).unwrap(), recording);
+
+        // 末尾不完整的记录被忽略
+        assert_eq!(
+            Recording::read_from(&data[..data.len() - 4])
+                .unwrap()
+                .records,
+            recording.records[..2]
+        );
+        data[0] = b'X';
+        assert!(Recording::read_from(&data[..]).is_err());
+    }
+
+    #[test]
+    fn recorder_test() {
+        let dir = std::env::temp_dir();
+        let config = RecordConfig {
+            enabled: true,
+            path: dir
+                .join(format!("ongeki-io-record-{}.rec", std::process::id()))
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let mut recorder = InputRecorder::create(&config).unwrap();
+        let input = record(0, 0x02, -300).input();
+        recorder
+            .record_at(InputSnapshot::default(), Duration::from_micros(10))
+            .unwrap();
+        recorder.record_at(input, Duration::from_millis(2)).unwrap();
+        recorder.finish().unwrap();
+
+        let expected = Recording {
+            records: vec![
+                InputRecord {
+                    time_us: 10,
+                    ..Default::default()
+                },
+                record(2000, 0x02, -300),
+            ],
+        };
+        assert_eq!(Recording::load(&config.path).unwrap(), expected);
+        let json = Path::new(&config.path).with_extension("json");
+        expected.save_json(&json).unwrap();
+        assert_eq!(Recording::load(&json).unwrap(), expected);
+        let value: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!(value["records"][1]["lever"], -300);
+
+        fs::remove_file(&config.path).unwrap();
+        fs::remove_file(json).unwrap();
+    }
+}